@@ -10,8 +10,11 @@ fn main() {
     #[cfg(target_os = "linux")]
     println!("cargo:rustc-link-arg=-Wl,--export-dynamic");
 
+    // Only the stock Lua 5.4 VM is built as lua54.dll
     #[cfg(windows)]
-    build_lua_shared();
+    if std::env::var_os("CARGO_FEATURE_LUA54").is_some() {
+        build_lua_shared();
+    }
 }
 
 #[cfg(windows)]
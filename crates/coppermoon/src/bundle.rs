@@ -0,0 +1,188 @@
+//! `coppermoon bundle`
+//!
+//! Flattens an entry script and its require graph into a single plain Lua
+//! file. Each module becomes a `package.preload` entry compiled with `load`
+//! under its original file name, so error messages and tracebacks still
+//! point at `lib/foo.lua:12` rather than at the bundle.
+
+use crate::deps::{self, Module};
+use crate::lexer::{self, Token};
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use std::path::Path;
+
+/// Options for the `bundle` subcommand.
+pub struct BundleOptions {
+    pub file: String,
+    pub output: Option<String>,
+    pub minify: bool,
+}
+
+/// Bundle an entry script into a single Lua file.
+pub fn bundle(options: BundleOptions) -> Result<()> {
+    let entry = std::env::current_dir()?.join(&options.file);
+    if !entry.is_file() {
+        bail!("entry file '{}' not found", options.file);
+    }
+    let base = entry.parent().unwrap_or(Path::new("."));
+
+    let graph = deps::trace(&entry, base)?;
+    graph.print_warnings();
+
+    let bundled = render(&graph.entry, &graph.modules, options.minify);
+
+    match options.output {
+        Some(ref output) => {
+            std::fs::write(output, &bundled)
+                .with_context(|| format!("Failed to write '{}'", output))?;
+            eprintln!(
+                "{} {} ({} modules, {} KB)",
+                "Bundled".bright_green().bold(),
+                output,
+                graph.modules.len() + 1,
+                bundled.len() / 1024
+            );
+        }
+        None => print!("{}", bundled),
+    }
+
+    Ok(())
+}
+
+/// Render the bundle source.
+fn render(entry: &Module, modules: &[Module], minify: bool) -> String {
+    let mut out = format!(
+        "-- Bundled by CopperMoon {} from {}\n",
+        env!("CARGO_PKG_VERSION"),
+        entry.rel_path
+    );
+
+    for module in modules {
+        out.push_str(&format!(
+            "package.preload[{}] = assert(load({}, {}))\n",
+            quote(&module.name),
+            long_string(&prepare(&module.source, minify)),
+            quote(&format!("@{}", module.rel_path)),
+        ));
+    }
+
+    out.push_str(&format!(
+        "return assert(load({}, {}))(...)\n",
+        long_string(&prepare(&entry.source, minify)),
+        quote(&format!("@{}", entry.rel_path)),
+    ));
+    out
+}
+
+/// Strip a shebang line (keeping the line count) and optionally minify.
+fn prepare(source: &str, minify_source: bool) -> String {
+    let source = coppermoon_core::runtime::strip_shebang(source);
+
+    if minify_source {
+        minify(source)
+    } else {
+        source.to_string()
+    }
+}
+
+/// Remove comments and redundant whitespace. Line breaks are preserved so
+/// line numbers in error messages still match the original file.
+pub fn minify(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut prev: Option<Token> = None;
+    let mut pending_space = false;
+    let mut pending_newlines = 0usize;
+
+    for token in lexer::tokenize(source) {
+        if !token.is_significant() {
+            let newlines = token.text.matches('\n').count();
+            pending_newlines += newlines;
+            pending_space = true;
+            continue;
+        }
+
+        if pending_newlines > 0 {
+            out.push_str(&"\n".repeat(pending_newlines));
+        } else if pending_space && prev.is_some_and(|p| needs_space(&p, &token)) {
+            out.push(' ');
+        }
+        pending_newlines = 0;
+        pending_space = false;
+
+        out.push_str(token.text);
+        prev = Some(token);
+    }
+
+    if pending_newlines > 0 {
+        out.push('\n');
+    }
+    out
+}
+
+/// Whether removing the whitespace between two tokens would change how
+/// they lex (e.g. `local x`, `1 ..`, `- -1`, `t[ [[s]] ]`).
+fn needs_space(prev: &Token, next: &Token) -> bool {
+    let joined = format!("{}{}", prev.text, next.text);
+    lexer::tokenize(&joined)
+        .first()
+        .map_or(true, |first| first.text != prev.text)
+}
+
+/// Quote a string as a short Lua string literal.
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Wrap source in a long-bracket string whose level does not occur inside it.
+/// The newline after the opening bracket is skipped by Lua, so the source is
+/// reproduced byte for byte.
+fn long_string(source: &str) -> String {
+    let mut level = 0;
+    loop {
+        let close = format!("]{}]", "=".repeat(level));
+        if !source.contains(&close) && !source.ends_with(&close[..close.len() - 1]) {
+            break;
+        }
+        level += 1;
+    }
+    let eq = "=".repeat(level);
+    format!("[{eq}[\n{source}]{eq}]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify_keeps_lines_and_tokens() {
+        let src = "local x = 1 -- one\n\n  if x == 1 then\n    print(\"a  b\")\n  end";
+        assert_eq!(minify(src), "local x=1\n\nif x==1 then\nprint(\"a  b\")\nend");
+    }
+
+    #[test]
+    fn test_minify_keeps_separating_spaces() {
+        assert_eq!(minify("a = 1 .. b"), "a=1 ..b");
+        assert_eq!(minify("x = - -1"), "x=- -1");
+        assert_eq!(minify("f ( a , b )"), "f(a,b)");
+        assert_eq!(minify("t[ [[k]] ]"), "t[ [[k]]]");
+    }
+
+    #[test]
+    fn test_long_string_level() {
+        assert_eq!(long_string("a"), "[[\na]]");
+        assert_eq!(long_string("x = t[y[1]]"), "[=[\nx = t[y[1]]]=]");
+        assert_eq!(long_string("s = ']'"), "[[\ns = ']']]");
+    }
+}
@@ -1,19 +1,28 @@
 //! CLI argument parsing
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(name = "coppermoon")]
 #[command(author, version, about = "A high-performance Lua runtime written in Rust")]
 #[command(propagate_version = true)]
 pub struct Cli {
-    /// Lua file to execute (shorthand for `coppermoon run <file>`)
+    /// Lua file to execute (shorthand for `coppermoon run <file>`; `-` reads stdin)
     pub file: Option<String>,
 
     /// Arguments to pass to the Lua script
     #[arg(trailing_var_arg = true)]
     pub args: Vec<String>,
 
+    #[command(flatten)]
+    pub script: ScriptArgs,
+
+    #[command(flatten)]
+    pub watch: WatchArgs,
+
+    #[command(flatten)]
+    pub remote: RemoteReplArgs,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -22,17 +31,168 @@ pub struct Cli {
 pub enum Commands {
     /// Run a Lua file
     Run {
-        /// The Lua file to execute
+        /// The Lua file to execute (`-` reads the script from stdin)
         file: String,
 
         /// Arguments to pass to the script
         #[arg(trailing_var_arg = true)]
         args: Vec<String>,
+
+        #[command(flatten)]
+        script: ScriptArgs,
+
+        #[command(flatten)]
+        watch: WatchArgs,
+
+        #[command(flatten)]
+        remote: RemoteReplArgs,
+    },
+
+    /// Compile a script and everything it requires into a single executable
+    Compile {
+        /// The entry Lua file
+        file: String,
+
+        /// Output path (defaults to the entry file name without extension)
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Embed the runtime so the output runs with no files next to it
+        #[arg(long)]
+        standalone: bool,
+
+        /// Extra file or directory to embed, served read-only through `fs` (repeatable)
+        #[arg(long = "asset", value_name = "PATH")]
+        assets: Vec<String>,
+    },
+
+    /// Flatten a script and everything it requires into a single Lua file
+    Bundle {
+        /// The entry Lua file
+        file: String,
+
+        /// Output file (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Strip comments and redundant whitespace (line numbers are kept)
+        #[arg(long)]
+        minify: bool,
     },
 
     /// Start the interactive REPL
     Repl,
 
+    /// Attach to a script started with --repl-socket
+    Attach {
+        /// Unix socket path or localhost TCP port
+        target: String,
+
+        /// Session token (defaults to $COPPERMOON_REPL_TOKEN)
+        #[arg(long)]
+        token: Option<String>,
+    },
+
+    /// Native (Rust) module packages
+    Native {
+        #[command(subcommand)]
+        command: NativeCommands,
+    },
+
+    /// Print the effective coppermoon.toml configuration
+    Config {
+        /// Script or directory to look up the configuration from (defaults to the current directory)
+        path: Option<String>,
+    },
+
     /// Show version information
-    Version,
+    Version {
+        /// Also list the cargo features (optional modules) compiled in
+        #[arg(long)]
+        features: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum NativeCommands {
+    /// Build the crate declared by harbor.toml [native] into native/
+    Build {
+        /// Package directory containing harbor.toml (defaults to the current directory)
+        path: Option<String>,
+
+        /// Cargo profile (defaults to [native] profile, else "release")
+        #[arg(long, conflicts_with = "debug")]
+        profile: Option<String>,
+
+        /// Build with the dev profile
+        #[arg(long)]
+        debug: bool,
+
+        /// Target triple to cross-compile for
+        #[arg(long)]
+        target: Option<String>,
+    },
+}
+
+/// Inline code, preloaded modules and syntax checking for script runs.
+#[derive(Args)]
+pub struct ScriptArgs {
+    /// Execute a chunk of Lua before the script (repeatable)
+    #[arg(short = 'e', long = "eval", value_name = "CODE")]
+    pub eval: Vec<String>,
+
+    /// Evaluate an expression and print its result
+    #[arg(short = 'p', long = "print", value_name = "EXPR")]
+    pub print: Option<String>,
+
+    /// Require a module into a global before running: `-r name` or `-r global=name` (repeatable)
+    #[arg(short = 'r', long = "require", value_name = "MODULE")]
+    pub require: Vec<String>,
+
+    /// Only compile the script and inline code, reporting syntax errors
+    #[arg(long)]
+    pub check: bool,
+
+    /// Reload required modules in place when their files change (see `package.reload`)
+    #[arg(long)]
+    pub hot: bool,
+
+    /// Load environment variables from a .env file before running (repeatable)
+    #[arg(long = "env-file", value_name = "PATH")]
+    pub env_files: Vec<String>,
+}
+
+impl ScriptArgs {
+    /// Whether there is inline code to run without a script file.
+    pub fn has_inline(&self) -> bool {
+        !self.eval.is_empty() || self.print.is_some()
+    }
+}
+
+/// Restart-on-change options for script runs.
+#[derive(Args)]
+pub struct WatchArgs {
+    /// Restart the script when it or any module it requires changes
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Clear the screen before each restart
+    #[arg(long, requires = "watch")]
+    pub clear: bool,
+
+    /// Extra files to watch, as a glob such as "views/**/*.html" (repeatable)
+    #[arg(long = "watch-path", value_name = "GLOB", requires = "watch")]
+    pub watch_paths: Vec<String>,
+}
+
+/// Options for accepting remote REPL sessions (`coppermoon attach`).
+#[derive(Args)]
+pub struct RemoteReplArgs {
+    /// Accept remote REPL sessions on a Unix socket path or a localhost TCP port
+    #[arg(long, value_name = "PATH|PORT")]
+    pub repl_socket: Option<String>,
+
+    /// Token clients must present (defaults to $COPPERMOON_REPL_TOKEN, else a random one is printed)
+    #[arg(long, value_name = "TOKEN", requires = "repl_socket")]
+    pub repl_token: Option<String>,
 }
@@ -0,0 +1,125 @@
+//! `coppermoon compile --standalone`
+//!
+//! Produces a single executable: a copy of the running `coppermoon` binary
+//! with the entry script, every module it requires and any declared asset
+//! files appended as a payload (see `coppermoon_core::vfs`).
+
+use crate::deps;
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use coppermoon_core::vfs::{self, Vfs};
+use std::path::{Path, PathBuf};
+
+/// Options for the `compile` subcommand.
+pub struct CompileOptions {
+    pub file: String,
+    pub output: Option<String>,
+    pub standalone: bool,
+    pub assets: Vec<String>,
+}
+
+/// Compile an entry script into a standalone executable.
+pub fn compile(options: CompileOptions) -> Result<()> {
+    if !options.standalone {
+        bail!("only standalone output is supported; pass --standalone");
+    }
+
+    let cwd = std::env::current_dir()?;
+    let entry = cwd.join(&options.file);
+    if !entry.is_file() {
+        bail!("entry file '{}' not found", options.file);
+    }
+    let base = entry.parent().unwrap_or(Path::new(".")).to_path_buf();
+
+    // Collect the entry script and its require graph
+    let graph = deps::trace(&entry, &base)?;
+    graph.print_warnings();
+
+    let mut payload = Vfs::new(&graph.entry.rel_path);
+    payload.insert(&graph.entry.rel_path, graph.entry.source.clone().into_bytes());
+    for module in &graph.modules {
+        payload.insert(&module.rel_path, module.source.clone().into_bytes());
+    }
+
+    // Collect declared asset files and directories
+    for asset in &options.assets {
+        if Path::new(asset).components().any(|c| c == std::path::Component::ParentDir) {
+            bail!("asset '{}' must not contain '..'", asset);
+        }
+        let asset_path = cwd.join(asset);
+        if !asset_path.exists() {
+            bail!("asset '{}' not found", asset);
+        }
+        if !asset_path.starts_with(&base) {
+            bail!("asset '{}' must be inside the project directory '{}'", asset, base.display());
+        }
+        embed_path(&mut payload, &asset_path, &base)?;
+    }
+
+    // Copy the runtime (without any payload it may already carry) and append ours
+    let runtime_path = std::env::current_exe().context("Failed to locate the coppermoon binary")?;
+    let runtime = std::fs::read(&runtime_path)
+        .with_context(|| format!("Failed to read '{}'", runtime_path.display()))?;
+
+    let mut binary = runtime[..vfs::runtime_len(&runtime)].to_vec();
+    binary.extend_from_slice(&payload.to_payload());
+
+    let output = output_path(&options, &entry);
+    std::fs::write(&output, &binary)
+        .with_context(|| format!("Failed to write '{}'", output.display()))?;
+    make_executable(&output)?;
+
+    println!(
+        "{} {} ({} files, {} KB)",
+        "Compiled".bright_green().bold(),
+        output.display(),
+        payload.len(),
+        binary.len() / 1024
+    );
+
+    Ok(())
+}
+
+/// Recursively add a file or directory to the payload, keyed relative to `base`.
+fn embed_path(payload: &mut Vfs, path: &Path, base: &Path) -> Result<()> {
+    if path.is_dir() {
+        let entries = std::fs::read_dir(path)
+            .with_context(|| format!("Failed to read directory '{}'", path.display()))?;
+        for entry in entries {
+            embed_path(payload, &entry?.path(), base)?;
+        }
+    } else {
+        // The payload can't hold keys that leave the root or contain ':'
+        let key = deps::relative_key(path, base);
+        if vfs::normalize(&key).is_none() {
+            bail!("asset '{}' cannot be embedded under that name", path.display());
+        }
+        let data = std::fs::read(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        payload.insert(&key, data);
+    }
+    Ok(())
+}
+
+fn output_path(options: &CompileOptions, entry: &Path) -> PathBuf {
+    if let Some(ref output) = options.output {
+        return PathBuf::from(output);
+    }
+    let stem = entry
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "app".to_string());
+    PathBuf::from(format!("{}{}", stem, std::env::consts::EXE_SUFFIX))
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,451 @@
+//! `coppermoon.toml` project configuration
+//!
+//! The CLI looks for `coppermoon.toml` in the entry script's directory (or
+//! the current directory) and then in each parent directory, and uses the
+//! first one found. Every setting is optional; relative paths are resolved
+//! against the directory containing the file.
+//!
+//! ```toml
+//! [modules]
+//! paths = ["lib", "vendor"]
+//! aliases = { db = "app.database" }
+//!
+//! [database]
+//! modules = ["sqlite"]
+//!
+//! [http]
+//! max_body_size = 1048576
+//! timeout_secs = 10
+//!
+//! [permissions]
+//! process = false
+//!
+//! [env]
+//! files = [".env"]
+//!
+//! [log]
+//! level = "info"
+//! ```
+
+use anyhow::{bail, Context, Result};
+use coppermoon_core::module::ModuleSearch;
+use coppermoon_std::http_limits::Limits;
+use mlua::{Function, Lua, MultiValue, Table, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// File name searched for in the entry directory and its parents
+pub const CONFIG_FILE: &str = "coppermoon.toml";
+
+/// Native database modules that can be listed in `[database] modules`
+pub const DATABASE_MODULES: &[&str] = &["sqlite", "mysql", "postgresql"];
+
+/// The database modules compiled into this build (cargo features)
+pub const COMPILED_DATABASE_MODULES: &[&str] = &[
+    #[cfg(feature = "sqlite")]
+    "sqlite",
+    #[cfg(feature = "mysql")]
+    "mysql",
+    #[cfg(feature = "postgresql")]
+    "postgresql",
+];
+
+/// Effective configuration: the file's settings over the defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub modules: ModulesConfig,
+    pub database: DatabaseConfig,
+    pub http: HttpConfig,
+    pub permissions: PermissionsConfig,
+    pub env: EnvConfig,
+    pub log: LogConfig,
+    /// The file the settings were loaded from, if any
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModulesConfig {
+    /// Directories searched by `require` after the script's directory
+    pub paths: Vec<String>,
+    /// Module name -> module name it stands for
+    pub aliases: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// Native database modules registered as globals
+    pub modules: Vec<String>,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self { modules: COMPILED_DATABASE_MODULES.iter().map(|m| m.to_string()).collect() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub max_request_line: usize,
+    pub max_header_line: usize,
+    pub max_header_count: usize,
+    pub max_body_size: usize,
+    pub timeout_secs: u64,
+    pub keep_alive_secs: u64,
+    pub max_requests_per_connection: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        let limits = Limits::default();
+        Self {
+            max_request_line: limits.max_request_line,
+            max_header_line: limits.max_header_line,
+            max_header_count: limits.max_header_count,
+            max_body_size: limits.max_body_size,
+            timeout_secs: limits.timeout_secs,
+            keep_alive_secs: limits.keep_alive_secs,
+            max_requests_per_connection: limits.max_requests_per_connection,
+        }
+    }
+}
+
+/// Capabilities scripts may use. Everything is allowed by default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PermissionsConfig {
+    /// Writing, moving and removing files
+    pub fs_write: bool,
+    /// Running other programs
+    pub process: bool,
+    /// Outgoing connections: the `net` module and the HTTP client
+    pub net: bool,
+    /// Loading native (shared library) modules
+    pub native: bool,
+}
+
+impl Default for PermissionsConfig {
+    fn default() -> Self {
+        Self { fs_write: true, process: true, net: true, native: true }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnvConfig {
+    /// .env files loaded before any `--env-file`
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Log filter (`error`, `info`, `coppermoon_core=debug`, ...);
+    /// `RUST_LOG` takes precedence
+    pub level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self { level: "error".to_string() }
+    }
+}
+
+impl Config {
+    /// Load the nearest `coppermoon.toml` at or above `start`, or the
+    /// defaults if there is none.
+    pub fn discover(start: &Path) -> Result<Config> {
+        match start.ancestors().map(|dir| dir.join(CONFIG_FILE)).find(|path| path.is_file()) {
+            Some(path) => Config::load(&path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    /// Load and validate a config file.
+    pub fn load(path: &Path) -> Result<Config> {
+        let src = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        let mut config: Config = toml::from_str(&src)
+            .with_context(|| format!("Invalid configuration in '{}'", path.display()))?;
+        config.source = Some(path.to_path_buf());
+        config
+            .validate()
+            .with_context(|| format!("Invalid configuration in '{}'", path.display()))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for module in &self.database.modules {
+            if !DATABASE_MODULES.contains(&module.as_str()) {
+                bail!(
+                    "unknown database module '{}' (expected one of: {})",
+                    module,
+                    DATABASE_MODULES.join(", ")
+                );
+            }
+            if !COMPILED_DATABASE_MODULES.contains(&module.as_str()) {
+                bail!("database module '{}' is not compiled into this build (cargo feature '{}')", module, module);
+            }
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log.level) {
+            bail!("invalid log level '{}': {}", self.log.level, e);
+        }
+        Ok(())
+    }
+
+    /// Directory relative paths are resolved against.
+    fn dir(&self) -> PathBuf {
+        self.source
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default()
+    }
+
+    /// Whether the native database module `name` should be registered.
+    pub fn database_enabled(&self, name: &str) -> bool {
+        self.database.modules.iter().any(|m| m == name)
+    }
+
+    /// `[env] files`, resolved against the config directory.
+    pub fn env_files(&self) -> Vec<PathBuf> {
+        let dir = self.dir();
+        self.env.files.iter().map(|file| dir.join(file)).collect()
+    }
+
+    /// `[modules]` as extra search roots and aliases for the module loader.
+    pub fn module_search(&self) -> ModuleSearch {
+        let dir = self.dir();
+        ModuleSearch {
+            paths: self.modules.paths.iter().map(|path| dir.join(path)).collect(),
+            aliases: self.modules.aliases.clone().into_iter().collect(),
+        }
+    }
+
+    /// `[http]` as request limits for `http.server`.
+    pub fn http_limits(&self) -> Limits {
+        Limits {
+            max_request_line: self.http.max_request_line,
+            max_header_line: self.http.max_header_line,
+            max_header_count: self.http.max_header_count,
+            max_body_size: self.http.max_body_size,
+            timeout_secs: self.http.timeout_secs,
+            keep_alive_secs: self.http.keep_alive_secs,
+            max_requests_per_connection: self.http.max_requests_per_connection,
+        }
+    }
+
+    /// The effective configuration as TOML, with a comment naming its source.
+    pub fn to_toml(&self) -> Result<String> {
+        let header = match self.source {
+            Some(ref path) => format!("# Loaded from {}\n\n", path.display()),
+            None => format!("# No {} found; showing defaults\n\n", CONFIG_FILE),
+        };
+        Ok(header + &toml::to_string_pretty(self)?)
+    }
+}
+
+/// Replace the functions disabled by `[permissions]` with ones that raise
+/// an error. Call after the standard library is registered; modules left
+/// out of the build are skipped.
+pub fn apply_permissions(lua: &Lua, permissions: &PermissionsConfig) -> Result<()> {
+    let globals = lua.globals();
+    let module = |name: &str| globals.get::<Option<Table>>(name);
+
+    if !permissions.fs_write {
+        const FS_WRITE: &[&str] = &[
+            "write", "write_bytes", "append", "remove", "copy", "rename", "move", "touch",
+            "mkdir", "mkdir_all", "rmdir", "rmdir_all", "copy_dir",
+        ];
+        if let Some(fs) = module("fs")? {
+            deny(lua, &fs, "fs", FS_WRITE, "fs_write")?;
+        }
+        deny(lua, &globals.get("os")?, "os", &["remove", "rename", "tmpname"], "fs_write")?;
+
+        // io.open stays usable for reading
+        let io: Table = globals.get("io")?;
+        let open: Function = io.get("open")?;
+        io.set("open", lua.create_function(move |_, (path, mode): (Value, Option<String>)| {
+            if mode.as_deref().is_some_and(|m| m.contains(['w', 'a', '+'])) {
+                return Err(denied("io.open in write mode", "fs_write"));
+            }
+            open.call::<MultiValue>((path, mode))
+        })?)?;
+        // io.output(file) and io.output() stay usable; a path opens it for writing
+        let output: Function = io.get("output")?;
+        io.set("output", lua.create_function(move |_, file: Value| {
+            if matches!(file, Value::String(_)) {
+                return Err(denied("io.output with a path", "fs_write"));
+            }
+            output.call::<MultiValue>(file)
+        })?)?;
+        deny(lua, &io, "io", &["tmpfile"], "fs_write")?;
+
+        // Writers inside methods and options (archive extraction, heap
+        // snapshots, upload spooling, sqlite files) check this themselves
+        let reason = format!("disabled by [permissions] fs_write in {}", CONFIG_FILE);
+        coppermoon_core::permissions::deny_fs_write(lua, &reason);
+    }
+
+    if !permissions.process {
+        if let Some(process) = module("process")? {
+            deny(lua, &process, "process", &["exec", "spawn"], "process")?;
+        }
+        deny(lua, &globals.get("os")?, "os", &["execute"], "process")?;
+        deny(lua, &globals.get("io")?, "io", &["popen"], "process")?;
+    }
+
+    if !permissions.net {
+        const HTTP_CLIENT: &[&str] = &["get", "post", "put", "delete", "patch", "request", "create_session"];
+        if let Some(http) = module("http")? {
+            // Only the client functions that were compiled in
+            let present: Vec<&str> = HTTP_CLIENT
+                .iter()
+                .copied()
+                .filter(|name| http.contains_key(*name).unwrap_or(false))
+                .collect();
+            deny(lua, &http, "http", &present, "net")?;
+        }
+        if let Some(net) = module("net")? {
+            deny_all(lua, &net, "net", "net")?;
+        }
+    }
+
+    if !permissions.native {
+        let reason = format!("disabled by [permissions] native in {}", CONFIG_FILE);
+        coppermoon_core::module::disable_native_modules(lua, &reason)?;
+    }
+
+    Ok(())
+}
+
+fn denied(what: &str, setting: &str) -> mlua::Error {
+    mlua::Error::runtime(format!(
+        "permission denied: {} is disabled by [permissions] {} in {}",
+        what, setting, CONFIG_FILE
+    ))
+}
+
+fn deny(lua: &Lua, table: &Table, module: &str, names: &[&str], setting: &'static str) -> mlua::Result<()> {
+    for name in names {
+        let what = format!("{}.{}", module, name);
+        table.set(*name, lua.create_function(move |_, _: MultiValue| -> mlua::Result<()> {
+            Err(denied(&what, setting))
+        })?)?;
+    }
+    Ok(())
+}
+
+/// Deny every function in `table` and its sub-tables (e.g. `net.ws`).
+fn deny_all(lua: &Lua, table: &Table, module: &str, setting: &'static str) -> mlua::Result<()> {
+    for pair in table.clone().pairs::<String, Value>() {
+        let (name, value) = pair?;
+        match value {
+            Value::Function(_) => deny(lua, table, module, &[name.as_str()], setting)?,
+            Value::Table(sub) => deny_all(lua, &sub, &format!("{}.{}", module, name), setting)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_merges_with_defaults() {
+        let config: Config = toml::from_str("[http]\ntimeout_secs = 5\n[permissions]\nprocess = false\n").unwrap();
+        assert_eq!(config.http.timeout_secs, 5);
+        assert_eq!(config.http.max_body_size, Limits::default().max_body_size);
+        assert!(!config.permissions.process);
+        assert!(config.permissions.fs_write);
+        assert!(config.database_enabled("sqlite"));
+
+        assert!(toml::from_str::<Config>("[http]\ntimeout = 5\n").is_err());
+    }
+
+    #[test]
+    fn test_discover_walks_up_and_resolves_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("src/app");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(dir.path().join(CONFIG_FILE), "[modules]\npaths = [\"lib\"]\n[database]\nmodules = []\n").unwrap();
+
+        let config = Config::discover(&nested).unwrap();
+        assert_eq!(config.module_search().paths, vec![dir.path().join("lib")]);
+        assert!(!config.database_enabled("mysql"));
+
+        std::fs::write(dir.path().join(CONFIG_FILE), "[database]\nmodules = [\"oracle\"]\n").unwrap();
+        assert!(Config::discover(&nested).is_err());
+    }
+
+    #[test]
+    fn test_fs_write_denies_every_writer() {
+        let lua = Lua::new();
+        coppermoon_std::register_all(&lua).unwrap();
+        #[cfg(feature = "sqlite")]
+        coppermoon_sqlite::register_global(&lua).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        lua.globals().set("dir", path("")).unwrap();
+        // Archives to extract once writes are off
+        #[cfg(feature = "archive")]
+        lua.load(r#"
+            local z = archive.zip.create(dir .. "in.zip")
+            z:add_string("a.txt", "a")
+            z:close()
+            local t = archive.tar.create(dir .. "in.tar")
+            t:add_string("a.txt", "a")
+            t:close()
+        "#).exec().unwrap();
+        let before = std::fs::read_dir(dir.path()).unwrap().count();
+
+        let permissions = PermissionsConfig { fs_write: false, ..Default::default() };
+        apply_permissions(&lua, &permissions).unwrap();
+
+        let mut writers = vec![
+            "os.remove(dir .. 'in.zip')",
+            "os.rename(dir .. 'in.zip', dir .. 'out.zip')",
+            "os.tmpname()",
+            "io.open(dir .. 'a.txt', 'w')",
+            "io.open(dir .. 'a.txt', 'r+')",
+            "io.output(dir .. 'a.txt')",
+            "io.tmpfile()",
+            "runtime.heap_snapshot(dir .. 'heap.json')",
+        ];
+        #[cfg(feature = "fs")]
+        writers.extend(["fs.write(dir .. 'a.txt', 'a')", "fs.mkdir(dir .. 'sub')", "fs.touch(dir .. 'a.txt')"]);
+        #[cfg(feature = "archive")]
+        writers.extend([
+            "archive.zip.open(dir .. 'in.zip'):extract(dir .. 'out')",
+            "archive.tar.open(dir .. 'in.tar'):extract(dir .. 'out')",
+            "archive.zip.create(dir .. 'out.zip')",
+            "archive.tar.create(dir .. 'out.tar')",
+        ]);
+        #[cfg(feature = "http-server")]
+        writers.push("http.server.new():listen(0, { uploads = {} })");
+        #[cfg(all(unix, feature = "http-server"))]
+        writers.push("http.server.new():listen('unix:' .. dir .. 'app.sock')");
+        #[cfg(feature = "sqlite")]
+        writers.push("sqlite.open(dir .. 'app.db')");
+        for code in writers {
+            let err = lua.load(code).exec().expect_err(code).to_string();
+            assert!(err.contains("permission denied"), "{}: {}", code, err);
+        }
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), before);
+
+        // Reading is unaffected
+        lua.load("assert(io.open(dir .. 'in.zip', 'rb')):close()").exec().unwrap();
+        lua.load("io.output(io.stdout)").exec().unwrap();
+        #[cfg(feature = "sqlite")]
+        lua.load("sqlite.open(':memory:')").exec().unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+//! Static `require` graph tracing
+//!
+//! Follows `require("name")` calls from an entry script using the same
+//! resolution rules as the runtime loader (`coppermoon_core::module`).
+//! Requires whose argument is not a string literal cannot be followed and
+//! are reported as dynamic.
+
+use crate::lexer::{self, TokenKind};
+use anyhow::{Context, Result};
+use std::collections::{HashSet, VecDeque};
+use std::path::Path;
+
+/// A `require` call found in a source file.
+#[derive(Debug, Clone)]
+pub struct RequireCall {
+    /// Module name, or `None` if the argument is not a string literal
+    pub name: Option<String>,
+    pub line: u32,
+}
+
+/// A resolved Lua module.
+#[derive(Debug, Clone)]
+pub struct Module {
+    /// Module name as passed to `require` (the entry script uses its file stem)
+    pub name: String,
+    /// Path relative to the project base directory, `/`-separated
+    pub rel_path: String,
+    pub source: String,
+}
+
+/// Where an unresolved require was found.
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub file: String,
+    pub line: u32,
+}
+
+/// The result of tracing an entry script.
+#[derive(Debug)]
+pub struct Graph {
+    pub entry: Module,
+    /// Required modules in discovery order (the entry is not included)
+    pub modules: Vec<Module>,
+    /// `require` calls with a non-literal argument
+    pub dynamic: Vec<Location>,
+    /// Literal module names with no Lua file (native or preloaded modules)
+    pub missing: Vec<(String, Location)>,
+}
+
+impl Graph {
+    /// Print warnings for requires that could not be followed.
+    pub fn print_warnings(&self) {
+        use colored::Colorize;
+
+        for loc in &self.dynamic {
+            eprintln!(
+                "{}: dynamic require at {}:{} cannot be resolved statically",
+                "warning".yellow().bold(),
+                loc.file,
+                loc.line
+            );
+        }
+        for (name, loc) in &self.missing {
+            eprintln!(
+                "{}: module '{}' required at {}:{} has no Lua source (native or preloaded?)",
+                "warning".yellow().bold(),
+                name,
+                loc.file,
+                loc.line
+            );
+        }
+    }
+}
+
+/// Find all `require` calls in Lua source.
+pub fn find_requires(src: &str) -> Vec<RequireCall> {
+    let tokens: Vec<_> = lexer::tokenize(src)
+        .into_iter()
+        .filter(|t| t.is_significant())
+        .collect();
+
+    let mut calls = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Name || token.text != "require" {
+            continue;
+        }
+
+        // Skip field accesses (`foo.require`), method calls and definitions
+        // (`local require = ...`, `function require(...)`)
+        if let Some(prev) = i.checked_sub(1).map(|p| &tokens[p]) {
+            if matches!(prev.text, "." | ":" | "local" | "function") {
+                continue;
+            }
+        }
+
+        let next = tokens.get(i + 1);
+        let name = match next {
+            // require "name" / require [[name]]
+            Some(t) if t.kind == TokenKind::String => t.string_value(),
+            // require("name")
+            Some(t) if t.text == "(" => match (tokens.get(i + 2), tokens.get(i + 3)) {
+                (Some(arg), Some(close)) if close.text == ")" => arg.string_value(),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        calls.push(RequireCall {
+            name: name.map(str::to_string),
+            line: token.line,
+        });
+    }
+    calls
+}
+
+/// Trace the require graph of `entry`, resolving modules against `base`.
+pub fn trace(entry: &Path, base: &Path) -> Result<Graph> {
+    let entry_source = std::fs::read_to_string(entry)
+        .with_context(|| format!("Failed to read '{}'", entry.display()))?;
+    let entry_module = Module {
+        name: entry
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        rel_path: relative_key(entry, base),
+        source: entry_source,
+    };
+
+    let mut graph = Graph {
+        entry: entry_module.clone(),
+        modules: Vec::new(),
+        dynamic: Vec::new(),
+        missing: Vec::new(),
+    };
+
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut queue: VecDeque<Module> = VecDeque::from([entry_module]);
+
+    while let Some(module) = queue.pop_front() {
+        for call in find_requires(&module.source) {
+            let location = Location {
+                file: module.rel_path.clone(),
+                line: call.line,
+            };
+
+            let Some(name) = call.name else {
+                graph.dynamic.push(location);
+                continue;
+            };
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+
+            let path = coppermoon_core::module::resolve_module_path(base, &name)
+                .filter(|p| p.exists());
+            let Some(path) = path else {
+                graph.missing.push((name, location));
+                continue;
+            };
+
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read '{}'", path.display()))?;
+            let resolved = Module {
+                name,
+                rel_path: relative_key(&path, base),
+                source,
+            };
+            graph.modules.push(resolved.clone());
+            queue.push_back(resolved);
+        }
+    }
+
+    Ok(graph)
+}
+
+/// Path of `path` relative to `base`, `/`-separated.
+pub fn relative_key(path: &Path, base: &Path) -> String {
+    let rel = path.strip_prefix(base).unwrap_or(path);
+    rel.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(src: &str) -> Vec<Option<String>> {
+        find_requires(src).into_iter().map(|c| c.name).collect()
+    }
+
+    #[test]
+    fn test_find_literal_requires() {
+        let src = "local a = require(\"a\")\nlocal b = require 'b.c'\nlocal c = require [[c]]";
+        assert_eq!(names(src), vec![Some("a".into()), Some("b.c".into()), Some("c".into())]);
+    }
+
+    #[test]
+    fn test_ignores_strings_and_comments() {
+        let src = "-- require('x')\nprint(\"require('y')\")\n--[[ require('z') ]]";
+        assert!(find_requires(src).is_empty());
+    }
+
+    #[test]
+    fn test_dynamic_require() {
+        let calls = find_requires("local m = require(name)\nlocal n = require('p' .. x)");
+        assert_eq!(calls.len(), 2);
+        assert!(calls.iter().all(|c| c.name.is_none()));
+        assert_eq!(calls[1].line, 2);
+    }
+
+    #[test]
+    fn test_skips_field_access() {
+        assert!(find_requires("loader.require('x')").is_empty());
+    }
+}
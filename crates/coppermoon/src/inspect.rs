@@ -0,0 +1,224 @@
+//! Value inspector for the REPL
+//!
+//! Renders Lua values as Lua-like literals: tables are expanded up to a
+//! depth limit, long tables are truncated and self-referencing tables print
+//! `<cycle>` instead of recursing forever.
+
+use mlua::{Lua, Table, Value};
+use std::cmp::Ordering;
+use std::ffi::c_void;
+
+/// Nesting depth at which tables are shown as `{...}`
+const MAX_DEPTH: usize = 4;
+
+/// Entries shown per table before the rest is summarized
+const MAX_ITEMS: usize = 50;
+
+/// Tables whose inline rendering is longer than this are split over lines
+const MAX_WIDTH: usize = 72;
+
+/// Render a value for display.
+pub fn inspect(lua: &Lua, value: &Value) -> String {
+    let mut inspector = Inspector { lua, visiting: Vec::new() };
+    inspector.value(value, 0)
+}
+
+struct Inspector<'a> {
+    lua: &'a Lua,
+    /// Tables on the current path, for cycle detection
+    visiting: Vec<*const c_void>,
+}
+
+impl Inspector<'_> {
+    fn value(&mut self, value: &Value, indent: usize) -> String {
+        match value {
+            Value::Nil => "nil".to_string(),
+            Value::Boolean(b) => b.to_string(),
+            Value::Integer(i) => i.to_string(),
+            Value::Number(n) => format_number(*n),
+            Value::String(s) => quote(&s.to_string_lossy()),
+            Value::Table(t) => self.table(t, indent),
+            Value::Error(e) => format!("error: {}", e),
+            other => self.tostring(other),
+        }
+    }
+
+    fn table(&mut self, table: &Table, indent: usize) -> String {
+        let ptr = table.to_pointer();
+        if self.visiting.contains(&ptr) {
+            return "<cycle>".to_string();
+        }
+
+        let len = table.raw_len();
+        let mut keyed: Vec<(Value, Value)> = table
+            .pairs::<Value, Value>()
+            .filter_map(|pair| pair.ok())
+            .filter(|(k, _)| !matches!(k, Value::Integer(i) if *i >= 1 && (*i as usize) <= len))
+            .collect();
+
+        if len == 0 && keyed.is_empty() {
+            return "{}".to_string();
+        }
+        if self.visiting.len() >= MAX_DEPTH {
+            return "{...}".to_string();
+        }
+
+        keyed.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+        let total = len + keyed.len();
+
+        self.visiting.push(ptr);
+        let mut items = Vec::new();
+        for i in 1..=len.min(MAX_ITEMS) {
+            let value = table.raw_get::<Value>(i).unwrap_or(Value::Nil);
+            items.push(self.value(&value, indent + 1));
+        }
+        for (key, value) in keyed.iter().take(MAX_ITEMS.saturating_sub(items.len())) {
+            let key = self.key(key);
+            items.push(format!("{} = {}", key, self.value(value, indent + 1)));
+        }
+        self.visiting.pop();
+
+        if total > items.len() {
+            items.push(format!("... ({} more)", total - items.len()));
+        }
+
+        let inline = format!("{{ {} }}", items.join(", "));
+        if inline.len() + indent * 2 <= MAX_WIDTH && !inline.contains('\n') {
+            return inline;
+        }
+
+        let pad = "  ".repeat(indent + 1);
+        let mut out = String::from("{\n");
+        for item in items {
+            out.push_str(&pad);
+            out.push_str(&item);
+            out.push_str(",\n");
+        }
+        out.push_str(&"  ".repeat(indent));
+        out.push('}');
+        out
+    }
+
+    /// `name` for identifier keys, `[key]` otherwise.
+    fn key(&mut self, key: &Value) -> String {
+        if let Value::String(s) = key {
+            let s = s.to_string_lossy();
+            if is_identifier(&s) {
+                return s.to_string();
+            }
+        }
+        let rendered = match key {
+            Value::Table(_) => self.tostring(key),
+            other => self.value(other, 0),
+        };
+        format!("[{}]", rendered)
+    }
+
+    /// Lua's own `tostring`, which honors `__tostring` and `__name`.
+    fn tostring(&self, value: &Value) -> String {
+        self.lua
+            .globals()
+            .get::<mlua::Function>("tostring")
+            .and_then(|f| f.call::<String>(value.clone()))
+            .unwrap_or_else(|_| value.type_name().to_string())
+    }
+}
+
+/// Order of non-array keys: numbers, then strings, then everything else.
+fn compare_keys(a: &Value, b: &Value) -> Ordering {
+    fn rank(key: &Value) -> u8 {
+        match key {
+            Value::Integer(_) | Value::Number(_) => 0,
+            Value::String(_) => 1,
+            Value::Boolean(_) => 2,
+            _ => 3,
+        }
+    }
+
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => (*a.as_bytes()).cmp(&*b.as_bytes()),
+        _ => match (a.as_f64(), b.as_f64()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            _ => rank(a).cmp(&rank(b)),
+        },
+    }
+}
+
+fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        "nan".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        // Debug keeps the `.0` of integral floats, like Lua 5.4's `1.0`
+        format!("{:?}", n)
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !crate::repl::LUA_KEYWORDS.contains(&s)
+}
+
+/// Quote a string as a Lua literal.
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // Three digits, so a digit that follows is not read as part of
+            // the escape; one per UTF-8 byte, as Lua escapes are bytes
+            c if c.is_control() => {
+                for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                    out.push_str(&format!("\\{:03}", byte));
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(lua: &Lua, code: &str) -> String {
+        let value: Value = lua.load(code).eval().unwrap();
+        inspect(lua, &value)
+    }
+
+    #[test]
+    fn test_inspect_tables() {
+        let lua = Lua::new();
+        assert_eq!(eval(&lua, "{}"), "{}");
+        assert_eq!(eval(&lua, "{1, 2, 'x'}"), "{ 1, 2, \"x\" }");
+        assert_eq!(eval(&lua, "{b = 2, a = 1, [10] = true, ['a b'] = 1.5}"),
+            "{ [10] = true, a = 1, [\"a b\"] = 1.5, b = 2 }");
+    }
+
+    #[test]
+    fn test_inspect_cycle_and_depth() {
+        let lua = Lua::new();
+        assert_eq!(eval(&lua, "local t = {} t.self = t return t"), "{ self = <cycle> }");
+        assert_eq!(eval(&lua, "{{{{{1}}}}}"), "{ { { { {...} } } } }");
+    }
+
+    #[test]
+    fn test_inspect_escapes_control_characters() {
+        let lua = Lua::new();
+        let quoted = eval(&lua, r#""\0011\t\127\u{85}""#);
+        assert_eq!(quoted, r#""\0011\t\127\194\133""#);
+        // The output reads back as the same string
+        let back: String = lua.load(format!("return {}", quoted)).eval().unwrap();
+        assert_eq!(back, "\u{1}1\t\u{7f}\u{85}");
+    }
+}
@@ -0,0 +1,179 @@
+//! Lossless Lua tokenizer
+//!
+//! Splits Lua source into tokens whose texts concatenate back to the exact
+//! input. Used by the dependency tracer to find `require` calls without
+//! being fooled by strings and comments.
+
+/// Kind of a lexical token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Identifier or keyword
+    Name,
+    /// Quoted or long-bracket string literal
+    String,
+    /// Numeric literal
+    Number,
+    /// Operator or punctuation
+    Symbol,
+    /// Line comment, block comment or shebang line
+    Comment,
+    /// Spaces, tabs and newlines
+    Whitespace,
+}
+
+/// A token borrowed from the source text.
+#[derive(Debug, Clone, Copy)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// 1-based line on which the token starts
+    pub line: u32,
+}
+
+impl Token<'_> {
+    /// Returns `true` for tokens that carry meaning (not whitespace/comments).
+    pub fn is_significant(&self) -> bool {
+        !matches!(self.kind, TokenKind::Whitespace | TokenKind::Comment)
+    }
+
+    /// The value of a string literal, if it can be read without evaluating
+    /// escape sequences. Returns `None` for non-strings and escaped strings.
+    pub fn string_value(&self) -> Option<&str> {
+        if self.kind != TokenKind::String {
+            return None;
+        }
+        let text = self.text;
+        if text.starts_with('"') || text.starts_with('\'') {
+            let quote = &text[..1];
+            if text.len() < 2 || !text.ends_with(quote) {
+                return None;
+            }
+            let inner = &text[1..text.len() - 1];
+            return (!inner.contains('\\')).then_some(inner);
+        }
+        // Long string: [==[ ... ]==]
+        let level = text[1..].bytes().take_while(|&b| b == b'=').count();
+        let open = level + 2;
+        let inner = text.get(open..text.len().checked_sub(open)?)?;
+        // A newline directly after the opening bracket is skipped by Lua
+        Some(inner.strip_prefix("\r\n").or_else(|| inner.strip_prefix('\n')).unwrap_or(inner))
+    }
+}
+
+/// Tokenize Lua source. Unterminated strings and comments run to the end of
+/// the input rather than failing; the parser reports those errors later.
+pub fn tokenize(src: &str) -> Vec<Token<'_>> {
+    let bytes = src.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut line = 1u32;
+
+    // Shebang line
+    if bytes.starts_with(b"#") {
+        let end = find_byte(bytes, 0, b'\n').unwrap_or(bytes.len());
+        tokens.push(Token { kind: TokenKind::Comment, text: &src[..end], line });
+        pos = end;
+    }
+
+    while pos < bytes.len() {
+        let start = pos;
+        let b = bytes[pos];
+
+        let kind = if b.is_ascii_whitespace() {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            TokenKind::Whitespace
+        } else if bytes[pos..].starts_with(b"--") {
+            pos += 2;
+            match long_bracket_level(bytes, pos) {
+                Some(level) => pos = skip_long_bracket(bytes, pos, level),
+                None => pos = find_byte(bytes, pos, b'\n').unwrap_or(bytes.len()),
+            }
+            TokenKind::Comment
+        } else if b == b'"' || b == b'\'' {
+            pos += 1;
+            while pos < bytes.len() {
+                match bytes[pos] {
+                    b'\\' => pos += 2,
+                    b'\n' => break,
+                    c if c == b => {
+                        pos += 1;
+                        break;
+                    }
+                    _ => pos += 1,
+                }
+            }
+            pos = pos.min(bytes.len());
+            TokenKind::String
+        } else if let Some(level) = long_bracket_level(bytes, pos) {
+            pos = skip_long_bracket(bytes, pos, level);
+            TokenKind::String
+        } else if b.is_ascii_digit() || (b == b'.' && bytes.get(pos + 1).is_some_and(|c| c.is_ascii_digit())) {
+            while pos < bytes.len() {
+                let c = bytes[pos];
+                if matches!(c, b'e' | b'E' | b'p' | b'P')
+                    && matches!(bytes.get(pos + 1), Some(b'+') | Some(b'-'))
+                {
+                    pos += 2;
+                } else if c.is_ascii_alphanumeric() || c == b'.' || c == b'_' {
+                    pos += 1;
+                } else {
+                    break;
+                }
+            }
+            TokenKind::Number
+        } else if is_name_byte(b) {
+            while pos < bytes.len() && (is_name_byte(bytes[pos]) || bytes[pos].is_ascii_digit()) {
+                pos += 1;
+            }
+            TokenKind::Name
+        } else {
+            const MULTI: [&[u8]; 10] = [b"...", b"..", b"==", b"~=", b"<=", b">=", b"<<", b">>", b"//", b"::"];
+            pos += MULTI
+                .iter()
+                .find(|op| bytes[pos..].starts_with(op))
+                .map(|op| op.len())
+                .unwrap_or(1);
+            TokenKind::Symbol
+        };
+
+        let text = &src[start..pos];
+        tokens.push(Token { kind, text, line });
+        line += text.bytes().filter(|&c| c == b'\n').count() as u32;
+    }
+
+    tokens
+}
+
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_' || b >= 0x80
+}
+
+fn find_byte(bytes: &[u8], from: usize, needle: u8) -> Option<usize> {
+    bytes[from..].iter().position(|&b| b == needle).map(|i| from + i)
+}
+
+/// If a long bracket (`[[`, `[==[`) opens at `pos`, return its level.
+fn long_bracket_level(bytes: &[u8], pos: usize) -> Option<usize> {
+    if bytes.get(pos) != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[pos + 1..].iter().take_while(|&&b| b == b'=').count();
+    (bytes.get(pos + 1 + level) == Some(&b'[')).then_some(level)
+}
+
+/// Skip a long bracket opened at `pos`; returns the position after its close.
+fn skip_long_bracket(bytes: &[u8], pos: usize, level: usize) -> usize {
+    let mut close = Vec::with_capacity(level + 2);
+    close.push(b']');
+    close.extend(std::iter::repeat(b'=').take(level));
+    close.push(b']');
+
+    let body = pos + level + 2;
+    bytes[body..]
+        .windows(close.len())
+        .position(|w| w == close.as_slice())
+        .map(|i| body + i + close.len())
+        .unwrap_or(bytes.len())
+}
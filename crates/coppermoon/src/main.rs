@@ -2,41 +2,99 @@
 //!
 //! The main entry point for the CopperMoon runtime.
 
+mod bundle;
 mod cli;
+mod compile;
+mod config;
+mod deps;
+mod inspect;
+mod lexer;
+mod native;
+mod remote;
 mod repl;
+mod watch;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
-use cli::{Cli, Commands};
+use cli::{Cli, Commands, NativeCommands, ScriptArgs};
 use colored::Colorize;
+use config::Config;
+use coppermoon_core::{Runtime, Vfs};
+use std::path::{Path, PathBuf};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 fn main() -> Result<()> {
-    // Setup tracing
-    tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
-        .init();
+    // A standalone executable carries its program as an appended payload;
+    // every command-line argument then belongs to the script.
+    if let Some(vfs) = Vfs::from_current_exe() {
+        let config = Config::default();
+        init_tracing(&config);
+        return run_standalone(vfs, &config);
+    }
 
     let cli = Cli::parse();
 
+    // coppermoon.toml is looked up from the entry script's directory, and
+    // only by the commands that use it; a broken one shouldn't stop the rest
+    let config = match cli.command {
+        Some(Commands::Run { ref file, .. }) => Config::discover(&config_dir(Some(file))?)?,
+        Some(Commands::Config { ref path }) => Config::discover(&config_dir(path.as_deref())?)?,
+        Some(Commands::Repl) => Config::discover(&config_dir(None)?)?,
+        None => Config::discover(&config_dir(cli.file.as_deref())?)?,
+        Some(_) => Config::default(),
+    };
+    init_tracing(&config);
+
     match cli.command {
-        Some(Commands::Run { file, args }) => {
-            run_file(&file, args)?;
+        Some(Commands::Run { file, args, script, watch, remote }) => {
+            if watch.watch && !watch::is_child() {
+                return watch::run(Some(&file), &watch);
+            }
+            load_script_env(&script, &config)?;
+            remote::serve(&remote)?;
+            let result = run_script(&script, Some(&file), args, &config);
+            remote::shutdown();
+            result?;
+        }
+        Some(Commands::Compile { file, output, standalone, assets }) => {
+            compile::compile(compile::CompileOptions { file, output, standalone, assets })?;
+        }
+        Some(Commands::Bundle { file, output, minify }) => {
+            bundle::bundle(bundle::BundleOptions { file, output, minify })?;
         }
         Some(Commands::Repl) => {
-            repl::start()?;
+            repl::start(&config)?;
+        }
+        Some(Commands::Attach { target, token }) => {
+            remote::attach(&target, token)?;
         }
-        Some(Commands::Version) => {
+        Some(Commands::Native { command: NativeCommands::Build { path, profile, debug, target } }) => {
+            let profile = if debug { Some("dev".to_string()) } else { profile };
+            native::build(native::BuildOptions { path, profile, target })?;
+        }
+        Some(Commands::Config { .. }) => {
+            print!("{}", config.to_toml()?);
+        }
+        Some(Commands::Version { features }) => {
             print_version();
+            if features {
+                print_features();
+            }
         }
         None => {
-            // If a file is provided as first argument, run it
-            if let Some(file) = cli.file {
-                run_file(&file, cli.args)?;
+            // If a file or inline code is provided, run it
+            if cli.file.is_some() || cli.script.has_inline() || cli.script.check {
+                if cli.watch.watch && !watch::is_child() {
+                    return watch::run(cli.file.as_deref(), &cli.watch);
+                }
+                load_script_env(&cli.script, &config)?;
+                remote::serve(&cli.remote)?;
+                let result = run_script(&cli.script, cli.file.as_deref(), cli.args, &config);
+                remote::shutdown();
+                result?;
             } else {
                 // Otherwise, start REPL
-                repl::start()?;
+                repl::start(&config)?;
             }
         }
     }
@@ -44,54 +102,286 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn run_file(file: &str, args: Vec<String>) -> Result<()> {
-    let path = std::path::Path::new(file);
+/// Directory to look for coppermoon.toml from: the entry script's
+/// directory (or the entry itself if it is a directory), else the current
+/// directory.
+fn config_dir(entry: Option<&str>) -> Result<PathBuf> {
+    let cwd = std::env::current_dir()?;
+    Ok(match entry.filter(|e| *e != "-") {
+        Some(entry) => {
+            let path = cwd.join(entry);
+            if path.is_dir() {
+                path
+            } else {
+                path.parent().map(Path::to_path_buf).unwrap_or(cwd)
+            }
+        }
+        None => cwd,
+    })
+}
 
-    // Canonicalize the path to get absolute path
-    let absolute_path = if path.is_absolute() {
-        path.to_path_buf()
+/// Setup tracing; `RUST_LOG` overrides the configured level.
+fn init_tracing(config: &Config) {
+    let filter = if std::env::var_os("RUST_LOG").is_some() {
+        EnvFilter::from_default_env()
     } else {
-        std::env::current_dir()?.join(path)
+        EnvFilter::new(&config.log.level)
     };
+    tracing_subscriber::registry()
+        .with(fmt::layer())
+        .with(filter)
+        .init();
+}
 
-    let base_path = absolute_path.parent().unwrap_or(std::path::Path::new("."));
-    let file_name = absolute_path.file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or(file);
-
-    let runtime = coppermoon_core::Runtime::with_base_path(base_path)?;
+/// Create a runtime with the module loader, the compiled-in standard
+/// library and the configured database modules registered, and the project configuration
+/// applied.
+pub(crate) fn create_runtime(base_path: &Path, config: &Config) -> Result<Runtime> {
+    let runtime = Runtime::with_base_path(base_path)?;
+    let lua = runtime.lua();
 
     // Setup module loader
+    runtime.set_module_search(config.module_search());
     runtime.setup_module_loader()?;
 
     // Register standard library
-    coppermoon_std::register_all(runtime.lua())?;
+    coppermoon_std::register_all(lua)?;
+    coppermoon_std::http_limits::set_limits(lua, config.http_limits());
 
     // Register SQLite module
-    coppermoon_sqlite::register_global(runtime.lua())?;
+    #[cfg(feature = "sqlite")]
+    if config.database_enabled("sqlite") {
+        coppermoon_sqlite::register_global(lua)?;
+    }
 
     // Register MySQL module
-    coppermoon_mysql::register_global(runtime.lua())?;
+    #[cfg(feature = "mysql")]
+    if config.database_enabled("mysql") {
+        coppermoon_mysql::register_global(lua)?;
+    }
 
     // Register PostgreSQL module
-    coppermoon_postgresql::register_global(runtime.lua())?;
+    #[cfg(feature = "postgresql")]
+    if config.database_enabled("postgresql") {
+        coppermoon_postgresql::register_global(lua)?;
+    }
 
-    // Set script arguments
+    config::apply_permissions(lua, &config.permissions)?;
+
+    Ok(runtime)
+}
+
+/// Set the `arg` global: `arg[0]` is the script name, `arg[1..]` its arguments.
+fn set_script_args(runtime: &Runtime, script: &str, args: &[String]) -> Result<()> {
     let lua = runtime.lua();
     let arg_table = lua.create_table()?;
 
-    // arg[0] is the script name (original path given by user)
-    arg_table.set(0, file)?;
+    arg_table.set(0, script)?;
 
-    // arg[1..] are the arguments
     for (i, arg) in args.iter().enumerate() {
         arg_table.set(i as i64 + 1, arg.as_str())?;
     }
 
     lua.globals().set("arg", arg_table)?;
+    Ok(())
+}
+
+/// Chunk name for `-e` and `-p` code, as in the `lua` interpreter
+const COMMAND_LINE_CHUNK: &str = "=(command line)";
 
-    // Execute the file (just the filename, base_path is already set)
-    if let Err(e) = runtime.exec_file(file_name) {
+/// Run a script file (`-` for stdin) together with `-r`, `-e` and `-p`,
+/// in that order, or only compile them with `--check`.
+fn run_script(options: &ScriptArgs, file: Option<&str>, args: Vec<String>, config: &Config) -> Result<()> {
+    if options.check {
+        return check(options, file);
+    }
+
+    let cwd = std::env::current_dir()?;
+    let script_path = file.filter(|f| *f != "-").map(|f| cwd.join(f));
+    let base_path = script_path
+        .as_deref()
+        .and_then(Path::parent)
+        .unwrap_or(cwd.as_path());
+
+    let runtime = create_runtime(base_path, config)?;
+    watch::report_modules(&runtime);
+    if options.hot {
+        coppermoon_core::hot::set_auto(runtime.lua(), true);
+    }
+
+    // arg[0] is the script name (original path given by user)
+    set_script_args(&runtime, file.unwrap_or("-e"), &args)?;
+
+    let result = execute(&runtime, options, file, script_path.as_deref());
+
+    if let Err(e) = result {
+        eprintln!("{}: {}", "error".red().bold(), e);
+        remote::shutdown();
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// `[env] files` and `--env-file`: load .env files in order. Variables set
+/// by the real environment win; among the files, later ones override
+/// earlier ones.
+///
+/// Must run before `remote::serve` and the runtime start any thread, since
+/// setting variables races with other threads reading the environment.
+fn load_script_env(options: &ScriptArgs, config: &Config) -> Result<()> {
+    if options.check {
+        return Ok(());
+    }
+
+    // Files from coppermoon.toml come first so --env-file can override them
+    let mut files = config.env_files();
+    files.extend(options.env_files.iter().map(PathBuf::from));
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let inherited: std::collections::HashSet<std::ffi::OsString> =
+        std::env::vars_os().map(|(key, _)| key).collect();
+
+    for file in &files {
+        let entries = coppermoon_std::dotenv::parse_file(file).map_err(anyhow::Error::msg)?;
+        for (key, value) in entries {
+            if !inherited.contains(std::ffi::OsStr::new(&key)) {
+                // No other thread exists yet (see above)
+                unsafe {
+                    std::env::set_var(&key, &value);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the parts of a script invocation in order.
+fn execute(
+    runtime: &Runtime,
+    options: &ScriptArgs,
+    file: Option<&str>,
+    script_path: Option<&Path>,
+) -> coppermoon_core::Result<()> {
+    for spec in &options.require {
+        require_into_global(runtime, spec)?;
+    }
+
+    for code in &options.eval {
+        runtime.exec_chunk(code, COMMAND_LINE_CHUNK)?;
+    }
+
+    if let Some(ref expr) = options.print {
+        print_expression(runtime, expr)?;
+    }
+
+    match (file, script_path) {
+        (Some("-"), _) => {
+            let code = std::io::read_to_string(std::io::stdin())
+                .map_err(|e| coppermoon_core::Error::Runtime(format!("Failed to read stdin: {}", e)))?;
+            runtime.exec_chunk(&code, "=stdin")
+        }
+        (_, Some(path)) => runtime.exec_file(path),
+        _ => Ok(()),
+    }
+}
+
+/// `-r name` / `-r global=name`: require a module and store it in a global
+/// (the last segment of a dotted module name by default).
+fn require_into_global(runtime: &Runtime, spec: &str) -> coppermoon_core::Result<()> {
+    let (global, module) = match spec.split_once('=') {
+        Some((global, module)) => (global, module),
+        None => (spec.rsplit('.').next().unwrap_or(spec), spec),
+    };
+
+    let lua = runtime.lua();
+    let require: mlua::Function = lua.globals().get("require")?;
+    let value: mlua::Value = require.call(module)?;
+    lua.globals().set(global, value)?;
+    Ok(())
+}
+
+/// `-p expr`: evaluate an expression and print its results. Strings are
+/// printed raw so the output can be piped; other values are inspected.
+fn print_expression(runtime: &Runtime, expr: &str) -> coppermoon_core::Result<()> {
+    let lua = runtime.lua();
+    let values: mlua::MultiValue = lua
+        .load(format!("return {}", expr))
+        .set_name(COMMAND_LINE_CHUNK)
+        .call(())?;
+
+    let line = values
+        .iter()
+        .map(|value| match value {
+            mlua::Value::String(s) => s.to_string_lossy(),
+            other => inspect::inspect(lua, other),
+        })
+        .collect::<Vec<_>>()
+        .join("\t");
+    println!("{}", line);
+
+    runtime.run_event_loop()
+}
+
+/// `--check`: compile the script and inline code without running anything.
+fn check(options: &ScriptArgs, file: Option<&str>) -> Result<()> {
+    let mut chunks: Vec<(String, String)> = options
+        .eval
+        .iter()
+        .map(|code| (code.clone(), COMMAND_LINE_CHUNK.to_string()))
+        .collect();
+    if let Some(ref expr) = options.print {
+        chunks.push((format!("return {}", expr), COMMAND_LINE_CHUNK.to_string()));
+    }
+    match file {
+        Some("-") => chunks.push((std::io::read_to_string(std::io::stdin())?, "=stdin".to_string())),
+        Some(file) => {
+            let code = std::fs::read_to_string(file)
+                .with_context(|| format!("Failed to read file '{}'", file))?;
+            chunks.push((code, format!("@{}", file)));
+        }
+        None => {}
+    }
+
+    if chunks.is_empty() {
+        anyhow::bail!("--check needs a script file, `-` or inline code");
+    }
+
+    let lua = mlua::Lua::new();
+    let mut failed = false;
+    for (code, name) in &chunks {
+        let compiled = lua
+            .load(coppermoon_core::runtime::strip_shebang(code))
+            .set_name(name.as_str())
+            .into_function();
+        if let Err(e) = compiled {
+            eprintln!("{}: {}", "error".red().bold(), e);
+            failed = true;
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Run the program embedded in this executable by `coppermoon compile --standalone`.
+fn run_standalone(vfs: Vfs, config: &Config) -> Result<()> {
+    let exe = std::env::current_exe()?;
+    let base_path = exe.parent().unwrap_or(Path::new("."));
+
+    let runtime = create_runtime(base_path, config)?;
+    runtime.set_vfs(vfs);
+
+    let mut argv = std::env::args();
+    let script = argv.next().unwrap_or_default();
+    let args: Vec<String> = argv.collect();
+    set_script_args(&runtime, &script, &args)?;
+
+    if let Err(e) = runtime.exec_embedded() {
         eprintln!("{}: {}", "error".red().bold(), e);
         std::process::exit(1);
     }
@@ -105,5 +395,19 @@ fn print_version() {
         "CopperMoon".bright_yellow().bold(),
         env!("CARGO_PKG_VERSION")
     );
-    println!("Lua 5.4 runtime written in Rust");
+    println!("{} runtime written in Rust", coppermoon_core::vm::NAME);
+}
+
+/// `version --features`: the optional modules compiled into this build.
+fn print_features() {
+    println!();
+    println!("{} {}", "Lua VM:".bold(), coppermoon_core::vm::ID);
+    println!("{}", "Standard library features:".bold());
+    for feature in coppermoon_std::FEATURES {
+        println!("  {}", feature);
+    }
+    println!("{}", "Database modules:".bold());
+    for module in config::COMPILED_DATABASE_MODULES {
+        println!("  {}", module);
+    }
 }
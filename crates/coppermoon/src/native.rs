@@ -0,0 +1,246 @@
+//! `coppermoon native build`
+//!
+//! Builds the Rust crate of a native module package with cargo and installs
+//! the library where `require` looks for it: `native/lib<name>.so` (`.dylib`
+//! on macOS, `<name>.dll` on Windows) next to `harbor.toml`. Driven by the
+//! `[native]` section of `harbor.toml`:
+//!
+//! ```toml
+//! [package]
+//! name = "hello_native"
+//!
+//! [native]
+//! build = true
+//! # All optional:
+//! name = "hello_native"      # module name (defaults to package.name)
+//! manifest = "Cargo.toml"    # crate to build, relative to harbor.toml
+//! profile = "release"        # cargo profile
+//! target = "x86_64-unknown-linux-gnu"
+//! features = ["luajit"]
+//! ```
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const HARBOR_FILE: &str = "harbor.toml";
+
+#[derive(Deserialize)]
+struct Manifest {
+    package: Package,
+    native: Option<NativeSection>,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NativeSection {
+    #[serde(default)]
+    build: bool,
+    name: Option<String>,
+    manifest: Option<String>,
+    profile: Option<String>,
+    target: Option<String>,
+    #[serde(default)]
+    features: Vec<String>,
+}
+
+/// Options for `native build`; command-line values override `harbor.toml`.
+pub struct BuildOptions {
+    pub path: Option<String>,
+    pub profile: Option<String>,
+    pub target: Option<String>,
+}
+
+/// Build the native module of the package at `options.path` (default: the
+/// current directory) and install it into its `native/` directory.
+pub fn build(options: BuildOptions) -> Result<()> {
+    let dir = match &options.path {
+        Some(path) => PathBuf::from(path),
+        None => std::env::current_dir()?,
+    };
+    let harbor_path = dir.join(HARBOR_FILE);
+    let text = std::fs::read_to_string(&harbor_path)
+        .with_context(|| format!("Failed to read '{}'", harbor_path.display()))?;
+    let manifest: Manifest = toml::from_str(&text)
+        .with_context(|| format!("Invalid '{}'", harbor_path.display()))?;
+
+    let native = match manifest.native {
+        Some(native) if native.build => native,
+        _ => bail!("'{}' has no [native] section with build = true", harbor_path.display()),
+    };
+
+    let name = native.name.clone().unwrap_or(manifest.package.name);
+    let cargo_manifest = dir.join(native.manifest.as_deref().unwrap_or("Cargo.toml"));
+    if !cargo_manifest.is_file() {
+        bail!("cargo manifest '{}' not found", cargo_manifest.display());
+    }
+    let profile = options.profile.or(native.profile).unwrap_or_else(|| "release".to_string());
+    let target = options.target.or(native.target);
+
+    println!(
+        "{} {} ({} profile{})",
+        "Building".green().bold(),
+        name,
+        profile,
+        target.as_deref().map(|t| format!(", {}", t)).unwrap_or_default()
+    );
+    let artifact = cargo_build(&cargo_manifest, &profile, target.as_deref(), &native.features)?;
+
+    let native_dir = dir.join("native");
+    std::fs::create_dir_all(&native_dir)
+        .with_context(|| format!("Failed to create '{}'", native_dir.display()))?;
+    let dest = native_dir.join(lib_filename(&name, target.as_deref()));
+    std::fs::copy(&artifact, &dest)
+        .with_context(|| format!("Failed to copy '{}' to '{}'", artifact.display(), dest.display()))?;
+
+    let symbol = coppermoon_core::module::native_entry_symbol(&name);
+    verify_entry_point(&dest, &symbol)?;
+
+    println!("{} {}", "Installed".green().bold(), dest.display());
+    Ok(())
+}
+
+/// Run `cargo build --lib` and return the path of the cdylib it produced.
+fn cargo_build(manifest: &Path, profile: &str, target: Option<&str>, features: &[String]) -> Result<PathBuf> {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut cmd = Command::new(cargo);
+    cmd.arg("build")
+        .arg("--lib")
+        .arg("--manifest-path")
+        .arg(manifest)
+        .arg("--profile")
+        .arg(profile)
+        .arg("--message-format=json-render-diagnostics");
+    if let Some(target) = target {
+        cmd.arg("--target").arg(target);
+    }
+    if !features.is_empty() {
+        cmd.arg("--features").arg(features.join(","));
+    }
+    // Diagnostics go straight to the terminal; stdout carries the JSON messages
+    let output = cmd
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Failed to run cargo; is it installed and on PATH?")?;
+    if !output.status.success() {
+        bail!("cargo build failed ({})", output.status);
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    find_cdylib(&stdout).ok_or_else(|| {
+        anyhow::anyhow!(
+            "cargo produced no cdylib; add `crate-type = [\"cdylib\"]` to [lib] in '{}'",
+            manifest.display()
+        )
+    })
+}
+
+/// The last cdylib among cargo's `compiler-artifact` JSON messages.
+fn find_cdylib(messages: &str) -> Option<PathBuf> {
+    let mut found = None;
+    for line in messages.lines() {
+        let Ok(message) = serde_json::from_str::<serde_json::Value>(line) else {
+            continue;
+        };
+        if message["reason"] != "compiler-artifact" {
+            continue;
+        }
+        let is_cdylib = message["target"]["kind"]
+            .as_array()
+            .is_some_and(|kinds| kinds.iter().any(|k| k == "cdylib"));
+        if !is_cdylib {
+            continue;
+        }
+        // Windows also lists the import library and debug info
+        let library = message["filenames"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|f| f.as_str())
+            .find(|f| [".so", ".dylib", ".dll"].iter().any(|ext| f.ends_with(ext)));
+        if let Some(library) = library {
+            found = Some(PathBuf::from(library));
+        }
+    }
+    found
+}
+
+/// Library file name `require` expects, for the host or a cross `target`.
+fn lib_filename(name: &str, target: Option<&str>) -> String {
+    let Some(target) = target else {
+        return coppermoon_core::module::native_lib_filename(name);
+    };
+    let leaf = name.rsplit('.').next().unwrap_or(name).replace('-', "_");
+    if target.contains("windows") {
+        format!("{}.dll", leaf)
+    } else if target.contains("apple") {
+        format!("lib{}.dylib", leaf)
+    } else {
+        format!("lib{}.so", leaf)
+    }
+}
+
+/// Check that the library exports the module's entry point. The export
+/// table is read rather than the library loaded, so nothing of it runs and
+/// cross-compiled libraries are checked too.
+fn verify_entry_point(path: &Path, symbol: &str) -> Result<()> {
+    use object::Object;
+    let data = std::fs::read(path).with_context(|| format!("Failed to read '{}'", path.display()))?;
+    let file = object::File::parse(&*data)
+        .with_context(|| format!("'{}' is not a shared library", path.display()))?;
+    let exports = file
+        .exports()
+        .with_context(|| format!("Failed to read the exports of '{}'", path.display()))?;
+    // Mach-O prefixes C symbols with an underscore
+    let found = exports.iter().any(|export| {
+        let name = export.name();
+        name == symbol.as_bytes() || name.strip_prefix(b"_") == Some(symbol.as_bytes())
+    });
+    if !found {
+        bail!(
+            "'{}' does not export '{}'; name the #[mlua::lua_module] function after the module",
+            path.display(),
+            symbol
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_cdylib_in_cargo_messages() {
+        let messages = r#"{"reason":"compiler-artifact","target":{"kind":["lib"]},"filenames":["/t/libdep.rlib"]}
+{"reason":"compiler-artifact","target":{"kind":["cdylib"]},"filenames":["/t/release/hello.dll.lib","/t/release/hello.dll"]}
+{"reason":"build-finished","success":true}"#;
+        assert_eq!(find_cdylib(messages), Some(PathBuf::from("/t/release/hello.dll")));
+        assert_eq!(find_cdylib(r#"{"reason":"build-finished"}"#), None);
+    }
+
+    #[test]
+    fn test_lib_filename_for_cross_targets() {
+        assert_eq!(lib_filename("hello-native", Some("x86_64-pc-windows-msvc")), "hello_native.dll");
+        assert_eq!(lib_filename("hello_native", Some("aarch64-apple-darwin")), "libhello_native.dylib");
+        assert_eq!(lib_filename("db.hello", Some("x86_64-unknown-linux-gnu")), "libhello.so");
+    }
+
+    #[test]
+    fn test_verify_entry_point_reads_exports() {
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let err = verify_entry_point(&manifest, "luaopen_x").unwrap_err();
+        assert!(err.to_string().contains("is not a shared library"), "{}", err);
+
+        let exe = std::env::current_exe().unwrap();
+        let err = verify_entry_point(&exe, "luaopen_not_there").unwrap_err();
+        assert!(err.to_string().contains("does not export 'luaopen_not_there'"), "{}", err);
+    }
+}
@@ -0,0 +1,449 @@
+//! Remote REPL
+//!
+//! `--repl-socket` lets a running script accept REPL sessions on a Unix
+//! socket or a localhost TCP port; `coppermoon attach` is the client.
+//!
+//! The protocol is one JSON object per line. The client first sends
+//! `{"token": "..."}`, then `{"code": "..."}` for each entry. Entries are
+//! queued onto the main Lua thread (see
+//! [`coppermoon_core::event_loop::post_main_thread_task`]) and run between
+//! HTTP requests, so they see the script's globals. Each reply carries what
+//! the entry printed plus its result or error:
+//! `{"ok": true, "output": "...", "result": "..."}`.
+
+use crate::cli::RemoteReplArgs;
+use crate::inspect::inspect;
+use anyhow::{bail, Context as _, Result};
+use colored::Colorize;
+use coppermoon_core::event_loop;
+use mlua::{Function, Lua, MultiValue, Value};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Editor, Helper};
+use serde_json::json;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Environment variable holding the session token (server and client)
+const TOKEN_ENV: &str = "COPPERMOON_REPL_TOKEN";
+
+/// Chunk name used for remote entries
+const CHUNK_NAME: &str = "=remote";
+
+/// How long a client waits for the main thread to run an entry
+const EVAL_TIMEOUT_SECS: u64 = 30;
+
+/// Where the remote REPL listens.
+enum Target {
+    /// TCP port on 127.0.0.1
+    Tcp(u16),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl Target {
+    /// A bare number is a localhost TCP port; anything else a socket path.
+    fn parse(s: &str) -> Result<Self> {
+        if let Ok(port) = s.parse::<u16>() {
+            return Ok(Target::Tcp(port));
+        }
+        #[cfg(unix)]
+        {
+            Ok(Target::Unix(std::path::PathBuf::from(s)))
+        }
+        #[cfg(not(unix))]
+        {
+            bail!("'{}' is not a port number (Unix sockets are not supported on this platform)", s)
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Server
+// ---------------------------------------------------------------------------
+
+/// Start accepting remote REPL sessions if `--repl-socket` was given.
+///
+/// The listener runs on a background thread; this returns once it is bound.
+pub fn serve(args: &RemoteReplArgs) -> Result<()> {
+    let Some(ref socket) = args.repl_socket else {
+        return Ok(());
+    };
+
+    let (token, generated) = match args.repl_token.clone().or_else(|| std::env::var(TOKEN_ENV).ok()) {
+        Some(token) if !token.is_empty() => (token, false),
+        _ => (format!("{:032x}", rand::random::<u128>()), true),
+    };
+
+    match Target::parse(socket)? {
+        Target::Tcp(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port))
+                .with_context(|| format!("Failed to bind remote REPL to 127.0.0.1:{}", port))?;
+            let token = token.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let token = token.clone();
+                    std::thread::spawn(move || {
+                        if let Ok(reader) = stream.try_clone() {
+                            let _ = serve_session(BufReader::new(reader), stream, &token);
+                        }
+                    });
+                }
+            });
+        }
+        #[cfg(unix)]
+        Target::Unix(path) => {
+            let listener = bind_unix(&path)?;
+            let token = token.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let token = token.clone();
+                    std::thread::spawn(move || {
+                        if let Ok(reader) = stream.try_clone() {
+                            let _ = serve_session(BufReader::new(reader), stream, &token);
+                        }
+                    });
+                }
+            });
+        }
+    }
+
+    if generated {
+        eprintln!(
+            "{} listening on {} (token: {})",
+            "Remote REPL".bright_yellow().bold(),
+            socket,
+            token
+        );
+    } else {
+        eprintln!("{} listening on {}", "Remote REPL".bright_yellow().bold(), socket);
+    }
+    Ok(())
+}
+
+/// Path of the Unix socket being served, removed again by [`shutdown`]
+#[cfg(unix)]
+static SOCKET_PATH: Mutex<Option<std::path::PathBuf>> = Mutex::new(None);
+
+/// Remove the remote REPL's socket file, if it serves one. Called when the
+/// script ends; SIGINT and SIGTERM do the same before exiting.
+pub fn shutdown() {
+    #[cfg(unix)]
+    {
+        if let Some(path) = SOCKET_PATH.lock().unwrap().take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Bind a Unix socket only the current user can connect to, replacing a
+/// stale socket file left by a previous run.
+///
+/// The socket is bound inside a new 0700 directory, restricted to 0600 and
+/// only then moved into place, so there is no moment at which another user
+/// can connect to it.
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path) -> Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            bail!("'{}' is already in use by another process", path.display());
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket '{}'", path.display()))?;
+    }
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+    let private = parent.join(format!(".coppermoon-repl-{}", std::process::id()));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .with_context(|| format!("Failed to create '{}'", private.display()))?;
+    let staged = private.join("socket");
+    let bound = UnixListener::bind(&staged)
+        .and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged, path)?;
+            Ok(listener)
+        })
+        .with_context(|| format!("Failed to bind remote REPL to '{}'", path.display()));
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&private);
+    let listener = bound?;
+
+    *SOCKET_PATH.lock().unwrap() = Some(path.to_path_buf());
+    remove_on_signal();
+    Ok(listener)
+}
+
+/// Remove the socket file when the process is interrupted or terminated.
+/// Installing the handlers replaces the default action, so exit afterwards
+/// with the status the signal would have given.
+#[cfg(unix)]
+fn remove_on_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    coppermoon_core::spawn(async {
+        let (Ok(mut interrupt), Ok(mut terminate)) =
+            (signal(SignalKind::interrupt()), signal(SignalKind::terminate()))
+        else {
+            return;
+        };
+        let code = tokio::select! {
+            _ = interrupt.recv() => 130,
+            _ = terminate.recv() => 143,
+        };
+        shutdown();
+        std::process::exit(code);
+    });
+}
+
+/// Handle one client: authenticate, then evaluate entries until it hangs up.
+fn serve_session(mut reader: impl BufRead, mut writer: impl Write, token: &str) -> Result<()> {
+    let Some(hello) = read_message(&mut reader)? else {
+        return Ok(());
+    };
+    let presented = hello.get("token").and_then(|t| t.as_str()).unwrap_or("");
+    if !tokens_match(presented, token) {
+        write_message(&mut writer, &json!({ "ok": false, "error": "invalid token" }))?;
+        return Ok(());
+    }
+    write_message(&mut writer, &json!({ "ok": true }))?;
+
+    while let Some(request) = read_message(&mut reader)? {
+        let Some(code) = request.get("code").and_then(|c| c.as_str()) else {
+            write_message(&mut writer, &json!({ "ok": false, "error": "missing 'code'" }))?;
+            continue;
+        };
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let code = code.to_string();
+        event_loop::post_main_thread_task(Box::new(move |lua| {
+            let _ = tx.send(evaluate(lua, &code));
+        }));
+
+        let reply = rx
+            .recv_timeout(Duration::from_secs(EVAL_TIMEOUT_SECS))
+            .unwrap_or_else(|_| json!({
+                "ok": false,
+                "error": "timed out waiting for the main thread (is the script running a server or timers?)",
+            }));
+        write_message(&mut writer, &reply)?;
+    }
+    Ok(())
+}
+
+/// Run an entry on the main thread, capturing `print` output.
+fn evaluate(lua: &Lua, code: &str) -> serde_json::Value {
+    let output = Arc::new(Mutex::new(String::new()));
+    let globals = lua.globals();
+    let original_print: Value = globals.get("print").unwrap_or(Value::Nil);
+
+    let capture = output.clone();
+    let print = lua.create_function(move |lua, args: MultiValue| {
+        let tostring: Function = lua.globals().get("tostring")?;
+        let parts = args
+            .into_iter()
+            .map(|v| tostring.call::<String>(v))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        let mut out = capture.lock().unwrap();
+        out.push_str(&parts.join("\t"));
+        out.push('\n');
+        Ok(())
+    });
+
+    let result = print
+        .and_then(|print| globals.set("print", print))
+        .and_then(|_| {
+            let func = match lua.load(format!("return {}", code)).set_name(CHUNK_NAME).into_function() {
+                Ok(func) => func,
+                Err(_) => lua.load(code).set_name(CHUNK_NAME).into_function()?,
+            };
+            func.call::<MultiValue>(())
+        });
+
+    let _ = globals.set("print", original_print);
+    let output = output.lock().unwrap().clone();
+
+    match result {
+        Ok(values) if values.iter().all(|v| v.is_nil()) => {
+            json!({ "ok": true, "output": output })
+        }
+        Ok(values) => {
+            let _ = globals.set("_", values.iter().next().cloned().unwrap_or(Value::Nil));
+            let result = values
+                .iter()
+                .map(|v| inspect(lua, v))
+                .collect::<Vec<_>>()
+                .join("\t");
+            json!({ "ok": true, "output": output, "result": result })
+        }
+        Err(e) => json!({ "ok": false, "output": output, "error": e.to_string() }),
+    }
+}
+
+/// Compare tokens without short-circuiting on the first mismatch.
+fn tokens_match(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn read_message(reader: &mut impl BufRead) -> Result<Option<serde_json::Value>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&line).context("Malformed message")?))
+}
+
+fn write_message(writer: &mut impl Write, message: &serde_json::Value) -> Result<()> {
+    writeln!(writer, "{}", message)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Client (`coppermoon attach`)
+// ---------------------------------------------------------------------------
+
+/// Connect to a remote REPL and run an interactive session.
+pub fn attach(target: &str, token: Option<String>) -> Result<()> {
+    let token = token
+        .or_else(|| std::env::var(TOKEN_ENV).ok())
+        .with_context(|| format!("No token given (use --token or set {})", TOKEN_ENV))?;
+
+    let (mut reader, mut writer) = connect(target)?;
+
+    write_message(&mut writer, &json!({ "token": token }))?;
+    match read_message(&mut reader)? {
+        Some(reply) if reply["ok"].as_bool() == Some(true) => {}
+        Some(reply) => bail!("{}", reply["error"].as_str().unwrap_or("authentication failed")),
+        None => bail!("Connection closed during authentication"),
+    }
+
+    println!("{} {}", "Attached to".bright_yellow().bold(), target);
+    println!("Type {} to detach", ".exit".cyan());
+    println!();
+
+    let mut editor: Editor<AttachHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(AttachHelper { lua: Lua::new() }));
+
+    loop {
+        let input = match editor.readline("remote> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                println!("{}", "(To detach, press Ctrl+D or type .exit)".bright_black());
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        let trimmed = input.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if matches!(trimmed, ".exit" | ".quit" | ".q") {
+            break;
+        }
+        editor.add_history_entry(input.as_str())?;
+
+        write_message(&mut writer, &json!({ "code": input }))?;
+        let Some(reply) = read_message(&mut reader)? else {
+            bail!("Connection closed by the remote process");
+        };
+
+        if let Some(output) = reply["output"].as_str() {
+            print!("{}", output);
+        }
+        if let Some(result) = reply["result"].as_str() {
+            println!("{}", result.bright_white());
+        }
+        if let Some(error) = reply["error"].as_str() {
+            eprintln!("{}: {}", "error".red().bold(), error);
+        }
+    }
+
+    Ok(())
+}
+
+fn connect(target: &str) -> Result<(Box<dyn BufRead>, Box<dyn Write>)> {
+    match Target::parse(target)? {
+        Target::Tcp(port) => {
+            let stream = TcpStream::connect(("127.0.0.1", port))
+                .with_context(|| format!("Failed to connect to 127.0.0.1:{}", port))?;
+            Ok((Box::new(BufReader::new(stream.try_clone()?)), Box::new(stream)))
+        }
+        #[cfg(unix)]
+        Target::Unix(path) => {
+            let stream = std::os::unix::net::UnixStream::connect(&path)
+                .with_context(|| format!("Failed to connect to '{}'", path.display()))?;
+            Ok((Box::new(BufReader::new(stream.try_clone()?)), Box::new(stream)))
+        }
+    }
+}
+
+/// Line editor helper for `attach`: multi-line entries are detected with a
+/// local parser, since completion would need the remote state.
+struct AttachHelper {
+    lua: Lua,
+}
+
+impl Helper for AttachHelper {}
+
+impl Highlighter for AttachHelper {}
+
+impl Hinter for AttachHelper {
+    type Hint = String;
+}
+
+impl Completer for AttachHelper {
+    type Candidate = String;
+}
+
+impl Validator for AttachHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+        if input.trim_start().starts_with('.') || !crate::repl::is_incomplete(&self.lua, input) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("abc123", "abc123"));
+        assert!(!tokens_match("abc124", "abc123"));
+        assert!(!tokens_match("abc", "abc123"));
+    }
+
+    #[test]
+    fn test_session_rejects_bad_token() {
+        let input = b"{\"token\": \"wrong\"}\n{\"code\": \"1\"}\n";
+        let mut out = Vec::new();
+        serve_session(&input[..], &mut out, "right").unwrap();
+        let reply: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(reply["ok"], false);
+    }
+}
@@ -1,12 +1,59 @@
 //! Interactive REPL for CopperMoon
+//!
+//! Built on `rustyline` for line editing, persistent history and Tab
+//! completion. Whether an entry needs more lines is decided by the Lua
+//! parser itself: input is incomplete only when compiling it fails at
+//! `<eof>`.
+//!
+//! Lines starting with `.` are REPL commands (`.help` lists them); results
+//! are printed with the table inspector in [`crate::inspect`].
 
-use anyhow::Result;
+use crate::config::Config;
+use crate::inspect::inspect;
+use anyhow::{bail, Context as _, Result};
 use colored::Colorize;
+use coppermoon_core::runtime::strip_shebang;
 use coppermoon_core::Runtime;
-use std::io::{self, BufRead, Write};
+use coppermoon_std::docs;
+use mlua::{Lua, MultiValue, Value};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// History file, stored in the user's home directory
+const HISTORY_FILE: &str = ".coppermoon_history";
+
+/// Chunk name used for REPL input (`stdin:1: ...` in error messages)
+const CHUNK_NAME: &str = "=stdin";
+
+pub(crate) const LUA_KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if",
+    "in", "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// REPL commands and their descriptions, for `.help`
+const COMMANDS: &[(&str, &str)] = &[
+    (".exit", "Exit the REPL"),
+    (".help", "Show this help"),
+    (".clear", "Clear the screen"),
+    (".load <file>", "Run a file in the current session"),
+    (".save <file>", "Write this session's entries to a file"),
+    (".time <expr>", "Evaluate and show how long it took"),
+    (".type <expr>", "Show the type of an expression"),
+    (".doc <name>", "Show a stdlib function or module (e.g. .doc fs.read)"),
+    (".reset", "Start over with a fresh runtime"),
+    (".editor", "Multi-line mode; Ctrl+D runs, Ctrl+C cancels"),
+];
 
 /// Start the interactive REPL
-pub fn start() -> Result<()> {
+pub fn start(config: &Config) -> Result<()> {
     println!(
         "{} {} - Interactive Mode",
         "CopperMoon".bright_yellow().bold(),
@@ -15,138 +62,441 @@ pub fn start() -> Result<()> {
     println!("Type {} to exit, {} for help", ".exit".cyan(), ".help".cyan());
     println!();
 
-    let runtime = Runtime::new()?;
-    runtime.setup_module_loader()?;
-    coppermoon_std::register_all(runtime.lua())?;
-    coppermoon_sqlite::register_global(runtime.lua())?;
-    coppermoon_mysql::register_global(runtime.lua())?;
-    coppermoon_postgresql::register_global(runtime.lua())?;
+    let mut session = Session::new(config.clone())?;
 
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
+    let history = history_path();
+    if let Some(ref path) = history {
+        // A missing history file is expected on first run
+        let _ = session.editor.load_history(path);
+    }
 
     loop {
-        // Print prompt
-        print!("{} ", ">".bright_green().bold());
-        stdout.flush()?;
-
-        // Read input
-        let mut input = String::new();
-        let bytes_read = stdin.lock().read_line(&mut input)?;
-
-        // Handle EOF (Ctrl+D)
-        if bytes_read == 0 {
-            println!();
-            break;
-        }
+        let input = match session.editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                println!("{}", "(To exit, press Ctrl+D or type .exit)".bright_black());
+                continue;
+            }
+            Err(ReadlineError::Eof) => {
+                println!();
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
 
-        let input = input.trim();
+        let trimmed = input.trim();
 
         // Skip empty lines
-        if input.is_empty() {
+        if trimmed.is_empty() {
             continue;
         }
 
+        session.editor.add_history_entry(input.as_str())?;
+
         // Handle REPL commands
-        if input.starts_with('.') {
-            match input {
-                ".exit" | ".quit" | ".q" => break,
-                ".help" | ".h" => {
-                    print_help();
-                    continue;
-                }
-                ".clear" | ".cls" => {
-                    // Clear screen (ANSI escape code)
-                    print!("\x1B[2J\x1B[1;1H");
-                    stdout.flush()?;
-                    continue;
-                }
-                _ => {
-                    eprintln!("{}: Unknown command '{}'", "error".red(), input);
-                    continue;
-                }
+        if trimmed.starts_with('.') {
+            match session.command(trimmed) {
+                Ok(Flow::Exit) => break,
+                Ok(Flow::Continue) => {}
+                Err(e) => print_error(&e.to_string()),
             }
+            continue;
         }
 
-        // Handle multi-line input (incomplete statements)
-        let mut code = input.to_string();
-        while is_incomplete(&code) {
-            print!("{} ", "..".bright_black());
-            stdout.flush()?;
+        session.run(&input);
+    }
 
-            let mut continuation = String::new();
-            if stdin.lock().read_line(&mut continuation)? == 0 {
-                break;
-            }
-            code.push('\n');
-            code.push_str(&continuation);
-        }
-
-        // Try to evaluate as expression first (for REPL convenience)
-        let eval_code = if !code.starts_with("return ")
-            && !code.contains('=')
-            && !code.starts_with("local ")
-            && !code.starts_with("function ")
-            && !code.starts_with("if ")
-            && !code.starts_with("for ")
-            && !code.starts_with("while ")
-            && !code.starts_with("repeat ")
-        {
-            format!("return {}", code)
-        } else {
-            code.clone()
+    if let Some(ref path) = history {
+        if let Err(e) = session.editor.save_history(path) {
+            eprintln!("{}: Failed to save history: {}", "warning".yellow(), e);
+        }
+    }
+
+    println!("{}", "Goodbye!".bright_yellow());
+    Ok(())
+}
+
+/// What the main loop does after a REPL command.
+enum Flow {
+    Continue,
+    Exit,
+}
+
+/// The runtime and line editor of one REPL session.
+struct Session {
+    runtime: Runtime,
+    editor: Editor<LuaHelper, DefaultHistory>,
+    /// Project configuration, reapplied by `.reset`
+    config: Config,
+    /// Lua entries evaluated since the session started (or was reset),
+    /// written out by `.save`
+    entries: Vec<String>,
+}
+
+impl Session {
+    fn new(config: Config) -> Result<Self> {
+        let runtime = crate::create_runtime(&std::env::current_dir()?, &config)?;
+
+        let mut editor: Editor<LuaHelper, DefaultHistory> = Editor::new()?;
+        editor.set_helper(Some(LuaHelper {
+            lua: runtime.lua().clone(),
+            raw: false,
+        }));
+
+        Ok(Self { runtime, editor, config, entries: Vec::new() })
+    }
+
+    /// Evaluate an entry and print its results.
+    fn run(&mut self, code: &str) {
+        self.entries.push(code.to_string());
+        match evaluate(&self.runtime, code) {
+            Ok(values) => print_results(self.runtime.lua(), values),
+            Err(e) => print_error(&e.to_string()),
+        }
+    }
+
+    /// Run a `.command [argument]` line.
+    fn command(&mut self, line: &str) -> Result<Flow> {
+        let (cmd, arg) = match line.split_once(char::is_whitespace) {
+            Some((cmd, arg)) => (cmd, arg.trim()),
+            None => (line, ""),
         };
 
-        // Try eval first, then exec
-        match runtime.eval(&eval_code) {
-            Ok(result) if !result.is_empty() && result != "nil" => {
-                println!("{}", result.bright_white());
+        match cmd {
+            ".exit" | ".quit" | ".q" => return Ok(Flow::Exit),
+            ".help" | ".h" => print_help(),
+            ".clear" | ".cls" => {
+                // Clear screen (ANSI escape code)
+                print!("\x1B[2J\x1B[1;1H");
+                std::io::stdout().flush()?;
             }
-            Ok(_) => {
-                // Expression returned nil, try as statement
-                if let Err(e) = runtime.exec(&code) {
-                    print_error(&e.to_string());
-                }
+            ".load" => self.load(required(cmd, arg, "file")?)?,
+            ".save" => self.save(required(cmd, arg, "file")?)?,
+            ".time" => self.time(required(cmd, arg, "expr")?),
+            ".type" => self.type_of(required(cmd, arg, "expr")?),
+            ".doc" => doc(arg)?,
+            ".reset" => self.reset()?,
+            ".editor" => self.editor_mode()?,
+            _ => bail!("Unknown command '{}' (type .help for a list)", cmd),
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// `.load file`: run a file in the current session.
+    fn load(&mut self, file: &str) -> Result<()> {
+        let code = std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read '{}'", file))?;
+
+        let lua = self.runtime.lua();
+        lua.load(strip_shebang(&code)).set_name(format!("@{}", file)).exec()?;
+        self.runtime.run_event_loop()?;
+
+        // Recorded as Lua so a saved session can be replayed
+        self.entries.push(format!("dofile({:?})", file));
+        Ok(())
+    }
+
+    /// `.save file`: write the entries of this session to a file.
+    fn save(&self, file: &str) -> Result<()> {
+        let mut content = self.entries.join("\n");
+        content.push('\n');
+        std::fs::write(file, content).with_context(|| format!("Failed to write '{}'", file))?;
+        println!(
+            "{}",
+            format!("Saved {} entries to {}", self.entries.len(), file).bright_black()
+        );
+        Ok(())
+    }
+
+    /// `.time expr`: evaluate and report how long it took (timers included).
+    fn time(&mut self, code: &str) {
+        self.entries.push(code.to_string());
+
+        let started = Instant::now();
+        let result = evaluate(&self.runtime, code);
+        let elapsed = started.elapsed();
+
+        match result {
+            Ok(values) => print_results(self.runtime.lua(), values),
+            Err(e) => print_error(&e.to_string()),
+        }
+        println!(
+            "{}",
+            format!("({:.3} ms)", elapsed.as_secs_f64() * 1000.0).bright_black()
+        );
+    }
+
+    /// `.type expr`: print the Lua type of each result.
+    fn type_of(&mut self, code: &str) {
+        match evaluate(&self.runtime, code) {
+            Ok(values) => {
+                let types = values
+                    .iter()
+                    .map(|v| v.type_name())
+                    .collect::<Vec<_>>()
+                    .join("\t");
+                println!("{}", types.cyan());
             }
-            Err(_) => {
-                // Eval failed, try as statement
-                if let Err(e) = runtime.exec(&code) {
-                    print_error(&e.to_string());
-                }
+            Err(e) => print_error(&e.to_string()),
+        }
+    }
+
+    /// `.reset`: replace the runtime with a fresh one.
+    fn reset(&mut self) -> Result<()> {
+        self.runtime = crate::create_runtime(&std::env::current_dir()?, &self.config)?;
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.lua = self.runtime.lua().clone();
+        }
+        self.entries.clear();
+        println!("{}", "Runtime reset".bright_black());
+        Ok(())
+    }
+
+    /// `.editor`: read lines until Ctrl+D and run them as one chunk.
+    fn editor_mode(&mut self) -> Result<()> {
+        println!(
+            "{}",
+            "// Entering editor mode (Ctrl+D to run, Ctrl+C to cancel)".bright_black()
+        );
+
+        self.set_raw(true);
+        let mut lines = Vec::new();
+        let finished = loop {
+            match self.editor.readline("") {
+                Ok(line) => lines.push(line),
+                Err(ReadlineError::Eof) => break Ok(true),
+                Err(ReadlineError::Interrupted) => break Ok(false),
+                Err(e) => break Err(e),
             }
+        };
+        self.set_raw(false);
+
+        if !finished? {
+            println!("{}", "(cancelled)".bright_black());
+            return Ok(());
+        }
+
+        let code = lines.join("\n");
+        if !code.trim().is_empty() {
+            self.editor.add_history_entry(code.as_str())?;
+            self.run(&code);
         }
+        Ok(())
     }
 
-    println!("{}", "Goodbye!".bright_yellow());
+    fn set_raw(&mut self, raw: bool) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.raw = raw;
+        }
+    }
+}
+
+/// The argument of a command that needs one.
+fn required<'a>(cmd: &str, arg: &'a str, what: &str) -> Result<&'a str> {
+    if arg.is_empty() {
+        bail!("Usage: {} <{}>", cmd, what);
+    }
+    Ok(arg)
+}
+
+/// `.doc name`: show a function's signature, or every function of a module.
+fn doc(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("Usage: .doc <name> (e.g. .doc fs.read, .doc fs)");
+    }
+
+    if let Some(entry) = docs::lookup(name) {
+        println!("{}", entry.signature.cyan());
+        println!("  {}", entry.summary);
+        return Ok(());
+    }
+
+    let entries = docs::module(name);
+    if entries.is_empty() {
+        bail!("No documentation for '{}'", name);
+    }
+    for entry in entries {
+        println!("{}", entry.signature.cyan());
+        println!("  {}", entry.summary.bright_black());
+    }
     Ok(())
 }
 
-/// Check if the code is incomplete (needs more input)
-fn is_incomplete(code: &str) -> bool {
-    let code = code.trim();
+/// Compile and run one REPL entry.
+///
+/// The input is first tried as an expression (`return <input>`) so that
+/// `1 + 1` prints `2`; if that does not parse it is run as a statement.
+/// Either way the code runs exactly once.
+fn evaluate(runtime: &Runtime, code: &str) -> coppermoon_core::Result<MultiValue> {
+    let lua = runtime.lua();
+    let func = match lua.load(format!("return {}", code)).set_name(CHUNK_NAME).into_function() {
+        Ok(func) => func,
+        Err(_) => lua.load(code).set_name(CHUNK_NAME).into_function()?,
+    };
+
+    let values: MultiValue = func.call(())?;
+
+    // Let timers scheduled by the entry fire before the next prompt
+    runtime.run_event_loop()?;
+
+    Ok(values)
+}
+
+/// Print the results of an entry and store the first one in `_`.
+fn print_results(lua: &Lua, values: MultiValue) {
+    if values.iter().all(|v| v.is_nil()) {
+        return;
+    }
+
+    if let Some(first) = values.iter().next() {
+        let _ = lua.globals().set("_", first.clone());
+    }
+
+    let formatted = values
+        .iter()
+        .map(|v| inspect(lua, v))
+        .collect::<Vec<_>>()
+        .join("\t");
+    println!("{}", formatted.bright_white());
+}
+
+/// Returns `true` if `code` is an unfinished chunk, i.e. the parser stopped
+/// at `<eof>` (an open `function`, `if`, string or table constructor).
+pub(crate) fn is_incomplete(lua: &Lua, code: &str) -> bool {
+    if lua.load(format!("return {}", code)).into_function().is_ok() {
+        return false;
+    }
+    match lua.load(code).into_function() {
+        Err(mlua::Error::SyntaxError { message, incomplete_input }) => {
+            incomplete_input || message.trim_end().ends_with("<eof>")
+        }
+        _ => false,
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(HISTORY_FILE))
+}
+
+// ---------------------------------------------------------------------------
+// Line editor helper (completion + continuation)
+// ---------------------------------------------------------------------------
+
+struct LuaHelper {
+    lua: Lua,
+    /// Accept every line as-is (`.editor` mode)
+    raw: bool,
+}
+
+impl Helper for LuaHelper {}
+
+impl Highlighter for LuaHelper {}
+
+impl Hinter for LuaHelper {
+    type Hint = String;
+}
+
+impl Validator for LuaHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+        if self.raw || input.trim_start().starts_with('.') || !is_incomplete(&self.lua, input) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
+    }
+}
+
+impl Completer for LuaHelper {
+    type Candidate = Pair;
+
+    /// Complete globals, keywords and table fields (`fs.<Tab>`, `s:<Tab>`).
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        let start = before
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !(c.is_alphanumeric() || c == '_' || c == '.' || c == ':'))
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+        let word = &before[start..];
 
-    // Simple heuristics for incomplete statements
-    let opens = code.matches("function").count()
-        + code.matches("if").count()
-        + code.matches("for").count()
-        + code.matches("while").count()
-        + code.matches("repeat").count()
-        + code.matches("do").count();
+        // Split `a.b.pre` into the table path `a.b` and the prefix `pre`
+        let (path, prefix, replace_from) = match word.rfind(|c: char| c == '.' || c == ':') {
+            Some(i) => (&word[..i], &word[i + 1..], start + i + 1),
+            None => ("", word, start),
+        };
 
-    let closes = code.matches("end").count() + code.matches("until").count();
+        let mut names: Vec<String> = match lookup_table(&self.lua, path) {
+            Some(table) => table_keys(&table, prefix, 0),
+            None => Vec::new(),
+        };
+        if path.is_empty() {
+            names.extend(
+                LUA_KEYWORDS
+                    .iter()
+                    .filter(|k| k.starts_with(prefix))
+                    .map(|k| k.to_string()),
+            );
+        }
+        names.sort();
+        names.dedup();
 
-    opens > closes
+        let candidates = names
+            .into_iter()
+            .map(|name| Pair { display: name.clone(), replacement: name })
+            .collect();
+        Ok((replace_from, candidates))
+    }
+}
+
+/// Resolve a dotted path (`""` for globals) to a table.
+fn lookup_table(lua: &Lua, path: &str) -> Option<mlua::Table> {
+    let mut table = lua.globals();
+    if path.is_empty() {
+        return Some(table);
+    }
+    for segment in path.split(|c: char| c == '.' || c == ':') {
+        match table.get::<Value>(segment).ok()? {
+            Value::Table(t) => table = t,
+            _ => return None,
+        }
+    }
+    Some(table)
+}
+
+/// String keys of a table (and of its `__index` chain) starting with `prefix`.
+fn table_keys(table: &mlua::Table, prefix: &str, depth: usize) -> Vec<String> {
+    let mut keys: Vec<String> = table
+        .pairs::<Value, Value>()
+        .filter_map(|pair| match pair.ok()? {
+            (Value::String(s), _) => s.to_str().ok().map(|s| s.to_string()),
+            _ => None,
+        })
+        .filter(|k| k.starts_with(prefix))
+        .collect();
+
+    // Follow `__index` tables a few levels (class-style objects), guarding
+    // against self-referencing metatables
+    if depth < 4 {
+        if let Some(Ok(Value::Table(index))) = table.metatable().map(|mt| mt.get::<Value>("__index")) {
+            keys.extend(table_keys(&index, prefix, depth + 1));
+        }
+    }
+    keys
 }
 
 fn print_help() {
     println!("{}", "REPL Commands:".bright_yellow().bold());
-    println!("  {}  - Exit the REPL", ".exit".cyan());
-    println!("  {}  - Show this help", ".help".cyan());
-    println!("  {} - Clear the screen", ".clear".cyan());
+    for (cmd, description) in COMMANDS {
+        println!("  {}{} - {}", cmd.cyan(), " ".repeat(13 - cmd.len()), description);
+    }
     println!();
     println!("{}", "Tips:".bright_yellow().bold());
-    println!("  - Expressions are automatically printed");
-    println!("  - Multi-line input is supported");
+    println!("  - Expressions are automatically printed; the last result is stored in _");
+    println!("  - Unfinished statements continue on the next line");
+    println!("  - Press Tab to complete globals and table fields (e.g. fs.<Tab>)");
+    println!("  - History is saved to ~/{}", HISTORY_FILE);
     println!("  - Press Ctrl+D to exit");
 }
 
@@ -0,0 +1,276 @@
+//! `--watch`: restart the script when its sources change
+//!
+//! The watcher runs the script in a child process (the same executable and
+//! arguments) and polls file modification times. The child reports every
+//! module file `require` resolves by appending its path to a manifest file
+//! named in `COPPERMOON_WATCH_MANIFEST`, so modules loaded lazily or through
+//! dynamic requires are watched too. On a change the child is killed, which
+//! closes its listeners, and started again.
+
+use crate::cli::WatchArgs;
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use coppermoon_core::Runtime;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Set in the child: path of the manifest to append loaded module paths to
+const MANIFEST_ENV: &str = "COPPERMOON_WATCH_MANIFEST";
+
+/// How often file modification times are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Quiet period after the last change before restarting, so editors that
+/// write several files (or write a file twice) cause a single restart
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Exit status to leave with once SIGINT or SIGTERM arrives (0 until then)
+static STOP_STATUS: AtomicI32 = AtomicI32::new(0);
+
+/// Whether this process is a child started by the watcher.
+pub fn is_child() -> bool {
+    std::env::var_os(MANIFEST_ENV).is_some()
+}
+
+/// In a watched child, report every module file `require` loads to the watcher.
+pub fn report_modules(runtime: &Runtime) {
+    let Some(manifest) = std::env::var_os(MANIFEST_ENV) else {
+        return;
+    };
+    runtime.on_module_load(move |path| {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(&manifest);
+        if let Ok(mut file) = file {
+            let _ = writeln!(file, "{}", path.display());
+        }
+    });
+}
+
+/// Run the current command line in a child process, restarting it on changes.
+pub fn run(file: Option<&str>, options: &WatchArgs) -> Result<()> {
+    let entry = match file {
+        Some(file) if file != "-" => std::env::current_dir()?.join(file),
+        _ => bail!("--watch needs a script file"),
+    };
+
+    // Removed when dropped, so stop signals are caught to get there
+    let manifest_file = tempfile::Builder::new()
+        .prefix("coppermoon-watch-")
+        .suffix(".txt")
+        .tempfile()
+        .context("Failed to create the watch manifest")?;
+    let manifest = manifest_file.path();
+    catch_stop_signals();
+    let exe = std::env::current_exe()?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let mut reason: Option<String> = None;
+    loop {
+        if options.clear {
+            print!("\x1B[2J\x1B[1;1H");
+            let _ = std::io::stdout().flush();
+        }
+        if let Some(ref reason) = reason {
+            eprintln!("{} {}", "[watch]".bright_black(), format!("restarting: {}", reason).yellow());
+        } else {
+            eprintln!("{} watching for changes", "[watch]".bright_black());
+        }
+
+        std::fs::write(&manifest, "")
+            .with_context(|| format!("Failed to create '{}'", manifest.display()))?;
+        let mut child = Command::new(&exe)
+            .args(&args)
+            .env(MANIFEST_ENV, &manifest)
+            .spawn()
+            .context("Failed to start the script")?;
+
+        let change = wait_for_change(&mut child, &entry, manifest, &options.watch_paths);
+        stop(&mut child);
+        match change? {
+            Some(change) => reason = Some(change),
+            None => {
+                drop(manifest_file);
+                std::process::exit(STOP_STATUS.load(Ordering::SeqCst));
+            }
+        }
+    }
+}
+
+/// Record SIGINT (Ctrl-C) and SIGTERM in [`STOP_STATUS`] instead of dying
+/// on them, so the watcher can stop the child and clean up first.
+fn catch_stop_signals() {
+    coppermoon_core::spawn(async {
+        #[cfg(unix)]
+        let status = {
+            use tokio::signal::unix::{signal, SignalKind};
+            let Ok(mut terminate) = signal(SignalKind::terminate()) else {
+                return;
+            };
+            tokio::select! {
+                Ok(()) = tokio::signal::ctrl_c() => 130,
+                _ = terminate.recv() => 143,
+            }
+        };
+        #[cfg(not(unix))]
+        let status = match tokio::signal::ctrl_c().await {
+            Ok(()) => 130,
+            Err(_) => return,
+        };
+        STOP_STATUS.store(status, Ordering::SeqCst);
+    });
+}
+
+/// Poll until a watched file changes, then wait out the debounce period.
+/// Returns a description of what changed, or `None` when the watcher was
+/// told to stop.
+fn wait_for_change(
+    child: &mut Child,
+    entry: &Path,
+    manifest: &Path,
+    globs: &[String],
+) -> Result<Option<String>> {
+    let mut snapshot = scan(entry, manifest, globs);
+    let mut exited = false;
+    let mut changed: Option<(String, Instant)> = None;
+
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        if STOP_STATUS.load(Ordering::SeqCst) != 0 {
+            return Ok(None);
+        }
+
+        if !exited {
+            if let Some(status) = child.try_wait()? {
+                exited = true;
+                let status = match status.code() {
+                    Some(code) => format!("code {}", code),
+                    None => "a signal".to_string(),
+                };
+                eprintln!(
+                    "{} script exited with {}, waiting for changes",
+                    "[watch]".bright_black(),
+                    status
+                );
+            }
+        }
+
+        let current = scan(entry, manifest, globs);
+        if let Some(what) = diff(&snapshot, &current) {
+            // Keep the first reason; later changes only extend the quiet period
+            let first = changed.map_or(what, |(first, _)| first);
+            changed = Some((first, Instant::now()));
+        }
+        snapshot = current;
+
+        if let Some((ref what, at)) = changed {
+            if at.elapsed() >= DEBOUNCE {
+                return Ok(Some(what.clone()));
+            }
+        }
+    }
+}
+
+/// The watched files at one point in time.
+#[derive(Debug, Clone, Default)]
+struct Scan {
+    /// Modification time of every watched file (`None` if missing)
+    files: BTreeMap<PathBuf, Option<SystemTime>>,
+    /// The files matched by `--watch-path` patterns
+    matched: BTreeSet<PathBuf>,
+}
+
+fn scan(entry: &Path, manifest: &Path, globs: &[String]) -> Scan {
+    let mut files: BTreeSet<PathBuf> = BTreeSet::new();
+    files.insert(entry.to_path_buf());
+
+    if let Ok(loaded) = std::fs::read_to_string(manifest) {
+        files.extend(loaded.lines().filter(|l| !l.is_empty()).map(PathBuf::from));
+    }
+
+    let mut matched = BTreeSet::new();
+    for pattern in globs {
+        if let Ok(paths) = glob::glob(pattern) {
+            matched.extend(paths.flatten().filter(|p| p.is_file()));
+        }
+    }
+    files.extend(matched.iter().cloned());
+
+    let files = files
+        .into_iter()
+        .map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+            (path, modified)
+        })
+        .collect();
+    Scan { files, matched }
+}
+
+/// Describe the first difference between two scans. A file new to the
+/// manifest is not a change (the script has only just required it), but a
+/// new match of a `--watch-path` pattern is.
+fn diff(before: &Scan, after: &Scan) -> Option<String> {
+    for (path, modified) in &after.files {
+        let previous = match before.files.get(path) {
+            Some(previous) => previous,
+            None if after.matched.contains(path) => &None,
+            None => continue,
+        };
+        if previous != modified {
+            let what = match modified {
+                Some(_) if previous.is_none() => "created",
+                Some(_) => "changed",
+                None => "removed",
+            };
+            return Some(format!("{} {}", display_path(path), what));
+        }
+    }
+    None
+}
+
+/// Path relative to the current directory when possible.
+fn display_path(path: &Path) -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.to_path_buf())
+        .display()
+        .to_string()
+}
+
+fn stop(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_ignores_new_files() {
+        let t0 = SystemTime::UNIX_EPOCH;
+        let t1 = t0 + Duration::from_secs(1);
+
+        let before = Scan {
+            files: BTreeMap::from([(PathBuf::from("a.lua"), Some(t0))]),
+            ..Default::default()
+        };
+        let mut after = before.clone();
+        after.files.insert(PathBuf::from("b.lua"), Some(t0));
+        assert_eq!(diff(&before, &after), None);
+
+        // Unless a --watch-path pattern matched it
+        after.matched.insert(PathBuf::from("b.lua"));
+        assert_eq!(diff(&before, &after), Some("b.lua created".to_string()));
+        after.matched.clear();
+
+        after.files.insert(PathBuf::from("a.lua"), Some(t1));
+        assert_eq!(diff(&before, &after), Some("a.lua changed".to_string()));
+
+        after.files.insert(PathBuf::from("a.lua"), None);
+        assert_eq!(diff(&before, &after), Some("a.lua removed".to_string()));
+    }
+}
@@ -4,9 +4,12 @@
 //! Timer callbacks are stored in a global registry and fired via
 //! a channel-based event system. The main Lua thread processes
 //! events after script execution or between HTTP request dispatches.
+//!
+//! Other threads can also queue work for the main Lua thread with
+//! [`post_main_thread_task`]; it runs at the same points as timer callbacks.
 
-use mlua::RegistryKey;
-use std::collections::{HashMap, HashSet};
+use mlua::{Lua, RegistryKey};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Mutex, OnceLock};
 use std::time::Duration;
@@ -43,10 +46,16 @@ pub enum TimerEvent {
 
 static TIMER_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
 static PENDING_TIMER_COUNT: AtomicUsize = AtomicUsize::new(0);
+static PENDING_WORK_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 static TIMER_CALLBACKS: OnceLock<Mutex<HashMap<u64, TimerCallback>>> = OnceLock::new();
 static CANCELLED_TIMERS: OnceLock<Mutex<HashSet<u64>>> = OnceLock::new();
 
+/// Work queued by other threads for the main Lua thread.
+pub type MainThreadTask = Box<dyn FnOnce(&Lua) + Send>;
+
+static MAIN_THREAD_TASKS: OnceLock<Mutex<VecDeque<MainThreadTask>>> = OnceLock::new();
+
 /// Channel pair for timer events (sender, receiver).
 static TIMER_CHANNEL: OnceLock<(
     std::sync::mpsc::Sender<TimerEvent>,
@@ -65,6 +74,10 @@ fn cancelled() -> &'static Mutex<HashSet<u64>> {
     CANCELLED_TIMERS.get_or_init(|| Mutex::new(HashSet::new()))
 }
 
+fn main_thread_tasks() -> &'static Mutex<VecDeque<MainThreadTask>> {
+    MAIN_THREAD_TASKS.get_or_init(|| Mutex::new(VecDeque::new()))
+}
+
 fn channel() -> &'static (
     std::sync::mpsc::Sender<TimerEvent>,
     Mutex<std::sync::mpsc::Receiver<TimerEvent>>,
@@ -109,6 +122,12 @@ pub fn has_pending_timers() -> bool {
     PENDING_TIMER_COUNT.load(Ordering::SeqCst) > 0
 }
 
+/// Number of timers that have not yet fired or been cancelled (an interval
+/// counts once).
+pub fn pending_timer_count() -> usize {
+    PENDING_TIMER_COUNT.load(Ordering::SeqCst)
+}
+
 // ---------------------------------------------------------------------------
 // Public API — event channel
 // ---------------------------------------------------------------------------
@@ -176,3 +195,59 @@ pub fn remove_timer_callback(id: u64) {
     }
     cancelled().lock().unwrap().remove(&id);
 }
+
+// ---------------------------------------------------------------------------
+// Public API — main-thread tasks
+// ---------------------------------------------------------------------------
+
+/// Queue a task to run on the main Lua thread.
+///
+/// Tasks run between HTTP request dispatches and while the event loop waits
+/// for timers, so they see the same Lua state as the script.
+pub fn post_main_thread_task(task: MainThreadTask) {
+    main_thread_tasks().lock().unwrap().push_back(task);
+}
+
+/// Report an error raised by a callback the event loop ran (a timer, a
+/// main-thread task, a server or native module callback), which has no
+/// caller to return it to. `what` names the callback, e.g. "Timer callback".
+pub fn report_error(what: &str, error: &mlua::Error) {
+    eprintln!("{} error: {}", what, error);
+}
+
+/// Keep the event loop running until a matching [`release_work`], e.g.
+/// while background work that will post a main-thread task is in flight.
+pub fn hold_work() {
+    PENDING_WORK_COUNT.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Undo one [`hold_work`].
+pub fn release_work() {
+    let _ = PENDING_WORK_COUNT.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+}
+
+/// Number of outstanding [`hold_work`] calls.
+pub fn pending_work_count() -> usize {
+    PENDING_WORK_COUNT.load(Ordering::SeqCst)
+}
+
+/// Returns `true` while there are pending timers, held work or queued
+/// main-thread tasks, i.e. while the event loop has to keep running.
+pub fn has_pending_work() -> bool {
+    has_pending_timers()
+        || PENDING_WORK_COUNT.load(Ordering::SeqCst) > 0
+        || !main_thread_tasks().lock().unwrap().is_empty()
+}
+
+/// Run every queued main-thread task. Must be called from the thread that
+/// owns `lua`.
+pub fn run_main_thread_tasks(lua: &Lua) {
+    loop {
+        // Pop one at a time so a task can post further tasks without deadlocking
+        let task = main_thread_tasks().lock().unwrap().pop_front();
+        match task {
+            Some(task) => task(lua),
+            None => break,
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! Hot module replacement
+//!
+//! Adds two functions to `package`; `require` itself stays a plain function:
+//!
+//! * `package.reload(name)` re-executes a loaded module and swaps its entry
+//!   in `package.loaded`.
+//! * `package.hot(enabled)` turns on automatic mode, in which modules whose
+//!   files change on disk are reloaded from the event loop (between HTTP
+//!   requests, or while waiting for timers).
+//!
+//! A module can carry state across a reload with two optional hooks on the
+//! table it returns: `__hot_dispose()` is called on the old module before the
+//! new one runs and may return a value, which is passed to
+//! `__hot_accept(state, old_module)` on the new one. If the new version fails
+//! to load, the old module stays in `package.loaded` and gets the state back
+//! through its own `__hot_accept`.
+//!
+//! Code that kept a reference to the old module table or to its functions
+//! (`local m = require("m")`, `server:get("/", m.index)`) would keep running
+//! old code. To avoid that, the function fields of the old table are replaced
+//! with the new ones, and every replaced function is recorded so holders such
+//! as `http.server` can look up the current version with [`replacement`].
+
+use crate::Result;
+use mlua::{Function, Lua, Table, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Registry key of the table mapping replaced functions to their successors
+const SWAPS_KEY: &str = "coppermoon.hot.swaps";
+
+/// Minimum time between two checks for changed files in automatic mode
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Longest chain of replacements followed by [`replacement`]
+const MAX_SWAP_CHAIN: usize = 64;
+
+/// Files of loaded modules and the automatic-mode settings.
+struct HotState {
+    modules: HashMap<String, Tracked>,
+    auto: bool,
+    last_poll: Instant,
+    /// Incremented on every successful reload
+    generation: u64,
+}
+
+struct Tracked {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+/// Add `package.reload` and `package.hot`. Calling it again keeps the
+/// tracked modules and recorded swaps.
+pub fn install(lua: &Lua) -> Result<()> {
+    if lua.app_data_ref::<HotState>().is_none() {
+        lua.set_app_data(HotState {
+            modules: HashMap::new(),
+            auto: false,
+            last_poll: Instant::now(),
+            generation: 0,
+        });
+
+        let swaps = lua.create_table()?;
+        let weak = lua.create_table()?;
+        weak.set("__mode", "k")?;
+        swaps.set_metatable(Some(weak));
+        lua.set_named_registry_value(SWAPS_KEY, swaps)?;
+    }
+
+    let package: Table = lua.globals().get("package")?;
+    // package.reload(name) -> module
+    package.set("reload", lua.create_function(|lua, name: String| reload(lua, &name))?)?;
+    // package.hot(enabled?) -> boolean
+    package.set("hot", lua.create_function(|lua, enabled: Option<bool>| {
+        if let Some(enabled) = enabled {
+            set_auto(lua, enabled);
+        }
+        Ok(lua.app_data_ref::<HotState>().is_some_and(|state| state.auto))
+    })?)?;
+    Ok(())
+}
+
+/// Enable or disable automatic reloading of changed module files.
+pub fn set_auto(lua: &Lua, enabled: bool) {
+    if let Some(mut state) = lua.app_data_mut::<HotState>() {
+        state.auto = enabled;
+    }
+}
+
+/// Remember the file a module was loaded from (called by the module loader).
+pub(crate) fn record(lua: &Lua, name: &str, path: &Path) {
+    if let Some(mut state) = lua.app_data_mut::<HotState>() {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        state.modules.insert(name.to_string(), Tracked { path: path.to_path_buf(), modified });
+    }
+}
+
+/// Number of successful reloads so far; changes whenever module code was
+/// replaced.
+pub fn generation(lua: &Lua) -> u64 {
+    lua.app_data_ref::<HotState>().map_or(0, |state| state.generation)
+}
+
+/// The newest version of a function replaced by a reload, if any.
+pub fn replacement(lua: &Lua, func: &Function) -> Option<Function> {
+    let swaps: Table = lua.named_registry_value(SWAPS_KEY).ok()?;
+    let mut current = func.clone();
+    let mut replaced = false;
+    for _ in 0..MAX_SWAP_CHAIN {
+        match swaps.raw_get::<Option<Function>>(current.clone()).ok().flatten() {
+            Some(next) => {
+                current = next;
+                replaced = true;
+            }
+            None => break,
+        }
+    }
+    replaced.then_some(current)
+}
+
+/// Re-execute module `name` and swap it into `package.loaded`.
+pub fn reload(lua: &Lua, name: &str) -> mlua::Result<Value> {
+    let package: Table = lua.globals().get("package")?;
+    let loaded: Table = package.get("loaded")?;
+    let searchers: Table = package.get(crate::vm::SEARCHERS)?;
+
+    // Find the loader the same way `require` does
+    let mut found = None;
+    let mut not_found = String::new();
+    for searcher in searchers.sequence_values::<Function>() {
+        let (loader, data): (Value, Value) = searcher?.call(name)?;
+        match loader {
+            Value::Function(loader) => {
+                found = Some((loader, data));
+                break;
+            }
+            // Lua's searchers return the message first, ours second
+            Value::String(msg) => not_found.push_str(&msg.to_string_lossy()),
+            _ => {
+                if let Value::String(msg) = data {
+                    not_found.push_str(&msg.to_string_lossy());
+                }
+            }
+        }
+    }
+    let (loader, data) = found.ok_or_else(|| {
+        mlua::Error::runtime(format!("module '{}' not found:{}", name, not_found))
+    })?;
+
+    // Dispose the old version first so its timers and listeners are gone
+    // before the new top-level code starts its own
+    let old: Value = loaded.get(name)?;
+    let state = match hook(&old, "__hot_dispose") {
+        Some(dispose) => dispose.call::<Value>(())?,
+        None => Value::Nil,
+    };
+
+    let new = match loader.call::<Value>((name, data)) {
+        Ok(Value::Nil) => Value::Boolean(true),
+        Ok(new) => new,
+        Err(e) => {
+            // Keep the old module, which may have been overwritten by the loader
+            loaded.set(name, old.clone())?;
+            if let Some(accept) = hook(&old, "__hot_accept") {
+                accept.call::<()>((state, old.clone()))?;
+            }
+            return Err(e);
+        }
+    };
+
+    loaded.set(name, new.clone())?;
+
+    if let (Value::Table(old_table), Value::Table(new_table)) = (&old, &new) {
+        patch_functions(lua, old_table, new_table)?;
+    }
+
+    if let Some(accept) = hook(&new, "__hot_accept") {
+        accept.call::<()>((state, old))?;
+    }
+
+    if let Some(mut state) = lua.app_data_mut::<HotState>() {
+        state.generation += 1;
+    }
+
+    Ok(new)
+}
+
+/// In automatic mode, reload every module whose file changed since it was
+/// loaded. Errors are reported and the old module is kept. Cheap to call
+/// often: files are checked at most every [`POLL_INTERVAL`].
+pub fn poll(lua: &Lua) {
+    let changed: Vec<String> = {
+        let Some(mut state) = lua.app_data_mut::<HotState>() else {
+            return;
+        };
+        if !state.auto || state.last_poll.elapsed() < POLL_INTERVAL {
+            return;
+        }
+        state.last_poll = Instant::now();
+
+        state
+            .modules
+            .iter_mut()
+            .filter_map(|(name, tracked)| {
+                let modified = std::fs::metadata(&tracked.path).and_then(|m| m.modified()).ok();
+                if modified.is_some() && modified != tracked.modified {
+                    tracked.modified = modified;
+                    Some(name.clone())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    };
+
+    for name in changed {
+        match reload(lua, &name) {
+            Ok(_) => eprintln!("[hot] reloaded '{}'", name),
+            Err(e) => eprintln!("[hot] failed to reload '{}': {}", name, e),
+        }
+    }
+}
+
+/// `module[name]` if the module is a table and the field is a function.
+fn hook(module: &Value, name: &str) -> Option<Function> {
+    match module {
+        Value::Table(t) => t.raw_get::<Option<Function>>(name).ok().flatten(),
+        _ => None,
+    }
+}
+
+/// Point the old table's functions at the new ones and record the swaps.
+fn patch_functions(lua: &Lua, old: &Table, new: &Table) -> mlua::Result<()> {
+    let swaps: Table = lua.named_registry_value(SWAPS_KEY)?;
+
+    for pair in old.clone().pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        let Value::Function(old_fn) = value else {
+            continue;
+        };
+        match new.raw_get::<Value>(key.clone())? {
+            Value::Function(new_fn) => {
+                if old_fn != new_fn {
+                    swaps.raw_set(old_fn, new_fn.clone())?;
+                    old.raw_set(key, new_fn)?;
+                }
+            }
+            // Removed in the new version
+            Value::Nil => old.raw_set(key, Value::Nil)?,
+            _ => {}
+        }
+    }
+
+    // Functions added in the new version
+    for (key, func) in new.clone().pairs::<Value, Function>().flatten() {
+        if old.raw_get::<Value>(key.clone())?.is_nil() {
+            old.raw_set(key, func)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Runtime;
+    use mlua::Function;
+
+    fn write_module(dir: &std::path::Path, body: &str) {
+        std::fs::write(dir.join("counter.lua"), body).unwrap();
+    }
+
+    #[test]
+    fn test_reload_swaps_module_and_keeps_state() {
+        let dir = tempfile::tempdir().unwrap();
+        write_module(dir.path(), "local M = { hits = 0 }
+            function M.version() return 1 end
+            function M.__hot_dispose() return M.hits end
+            return M");
+
+        let runtime = Runtime::with_base_path(dir.path()).unwrap();
+        runtime.setup_module_loader().unwrap();
+        runtime.exec("m = require('counter'); m.hits = 5; handler = m.version").unwrap();
+
+        write_module(dir.path(), "local M = { hits = 0 }
+            function M.version() return 2 end
+            function M.__hot_accept(hits) M.hits = hits end
+            return M");
+        runtime.exec("package.reload('counter')").unwrap();
+
+        let lua = runtime.lua();
+        let result: (i64, i64, i64) = lua
+            .load("return require('counter').version(), require('counter').hits, m.version()")
+            .eval()
+            .unwrap();
+        assert_eq!(result, (2, 5, 2));
+
+        // Functions captured before the reload resolve to the new version
+        let handler: Function = lua.globals().get("handler").unwrap();
+        let current = super::replacement(lua, &handler).unwrap();
+        assert_eq!(current.call::<i64>(()).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_old_module() {
+        let dir = tempfile::tempdir().unwrap();
+        write_module(dir.path(), "return { version = 1 }");
+
+        let runtime = Runtime::with_base_path(dir.path()).unwrap();
+        runtime.setup_module_loader().unwrap();
+        runtime.exec("require('counter')").unwrap();
+
+        write_module(dir.path(), "error('broken')");
+        assert!(runtime.exec("package.reload('counter')").is_err());
+
+        let version: i64 = runtime.lua().load("return require('counter').version").eval().unwrap();
+        assert_eq!(version, 1);
+    }
+
+    #[test]
+    fn test_reload_of_missing_module_lists_every_searcher() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = Runtime::with_base_path(dir.path()).unwrap();
+        runtime.setup_module_loader().unwrap();
+        crate::module::disable_native_modules(runtime.lua(), "disabled for the test").unwrap();
+
+        let err = runtime.exec("package.reload('missing')").unwrap_err().to_string();
+        assert!(err.contains("module 'missing' not found:"), "{}", err);
+        // From a searcher that returns its message first, and one that returns it second
+        assert!(err.contains("no native module 'missing' (disabled for the test)"), "{}", err);
+        assert!(err.contains("no file"), "{}", err);
+    }
+
+    #[test]
+    fn test_dispose_runs_before_new_code_and_require_stays_a_function() {
+        let dir = tempfile::tempdir().unwrap();
+        write_module(dir.path(), "events = (events or '') .. 'load1 '
+            return { __hot_dispose = function() events = events .. 'dispose1 ' end }");
+
+        let runtime = Runtime::with_base_path(dir.path()).unwrap();
+        runtime.setup_module_loader().unwrap();
+        runtime.setup_module_loader().unwrap();
+        runtime.exec("require('counter')").unwrap();
+
+        write_module(dir.path(), "events = events .. 'load2 ' return {}");
+        runtime.exec("package.reload('counter')").unwrap();
+
+        let (events, kind): (String, String) =
+            runtime.lua().load("return events, type(require)").eval().unwrap();
+        assert_eq!(events, "load1 dispose1 load2 ");
+        assert_eq!(kind, "function");
+    }
+}
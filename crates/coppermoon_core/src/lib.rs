@@ -2,13 +2,22 @@
 //!
 //! This crate provides the core functionality for running Lua code,
 //! including the Lua VM integration, module system, and async bridge.
+//!
+//! The Lua VM is selected with the `lua54` (default), `luajit` or `luau`
+//! feature; see [`vm`].
 
 pub mod error;
 pub mod runtime;
 pub mod module;
 pub mod async_runtime;
 pub mod event_loop;
+pub mod hot;
+pub mod native_host;
+pub mod permissions;
+pub mod vfs;
+pub mod vm;
 
 pub use error::{Error, Result};
 pub use runtime::Runtime;
+pub use vfs::Vfs;
 pub use async_runtime::{block_on, spawn, get_runtime};
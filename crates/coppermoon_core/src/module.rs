@@ -1,7 +1,9 @@
 //! Custom module loader for CopperMoon
 
 use crate::Result;
+use crate::vfs::Vfs;
 use mlua::{Lua, Function, Value, Table};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use tracing::debug;
@@ -10,7 +12,7 @@ use tracing::debug;
 /// When a native module is loaded via `libloading`, the `Library` handle must remain
 /// alive for as long as the Lua functions referencing its code exist.
 pub struct NativeLibStore {
-    libs: Mutex<Vec<libloading::Library>>,
+    libs: Mutex<Vec<(PathBuf, libloading::Library)>>,
 }
 
 impl NativeLibStore {
@@ -19,8 +21,58 @@ impl NativeLibStore {
             libs: Mutex::new(Vec::new()),
         }
     }
+
+    /// Paths of the loaded libraries, in load order
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.libs.lock().unwrap().iter().map(|(path, _)| path.clone()).collect()
+    }
+}
+
+/// Slots of our searchers in `package.searchers`: after the preload
+/// searcher, replacing the stock Lua and C searchers. mlua's Luau
+/// `package.loaders` has no preload entry, so ours come first there.
+#[cfg(not(feature = "luau"))]
+const LUA_SEARCHER: i64 = 2;
+#[cfg(not(feature = "luau"))]
+const NATIVE_SEARCHER: i64 = 3;
+#[cfg(feature = "luau")]
+const LUA_SEARCHER: i64 = 1;
+#[cfg(feature = "luau")]
+const NATIVE_SEARCHER: i64 = 2;
+
+/// Extra module search roots and name aliases, installed with
+/// [`crate::Runtime::set_module_search`].
+#[derive(Debug, Clone, Default)]
+pub struct ModuleSearch {
+    /// Directories searched after the base path, in order
+    pub paths: Vec<PathBuf>,
+    /// Module name -> the module name it stands for (`db` -> `app.database`)
+    pub aliases: HashMap<String, String>,
+}
+
+impl ModuleSearch {
+    /// The module name `name` stands for, after aliases.
+    fn resolve_alias(&self, name: &str) -> String {
+        self.aliases.get(name).cloned().unwrap_or_else(|| name.to_string())
+    }
+}
+
+/// The aliased module name and the search roots (base path first).
+fn search_roots(lua: &Lua, base_path: &Path, module_name: &str) -> (String, Vec<PathBuf>) {
+    let mut roots = vec![base_path.to_path_buf()];
+    match lua.app_data_ref::<ModuleSearch>() {
+        Some(search) => {
+            roots.extend(search.paths.iter().cloned());
+            (search.resolve_alias(module_name), roots)
+        }
+        None => (module_name.to_string(), roots),
+    }
 }
 
+/// Callback invoked with the path of every Lua module file the loader
+/// resolves from disk (used by `--watch` to learn what to watch).
+pub struct ModuleLoadHook(pub Box<dyn Fn(&Path) + Send>);
+
 /// Pre-load lua54.dll on Windows so native modules can resolve Lua symbols.
 /// Modules compiled with mlua's "module" feature use raw_dylib linking that
 /// expects lua54.dll to be available at runtime.
@@ -44,7 +96,7 @@ fn preload_lua_shared_lib(store: &NativeLibStore) {
             match unsafe { libloading::Library::new(path) } {
                 Ok(lib) => {
                     debug!("Pre-loaded lua54.dll from {}", path.display());
-                    store.libs.lock().unwrap().push(lib);
+                    store.libs.lock().unwrap().push((path.clone(), lib));
                     return;
                 }
                 Err(e) => {
@@ -70,8 +122,25 @@ pub fn setup_loader(lua: &Lua, base_path: &Path) -> Result<()> {
     let base_path_for_native = base_path_owned.clone();
 
     // Create our custom Lua file searcher
-    let searcher = lua.create_function(move |lua, module_name: String| {
-        let path = resolve_module_path(&base_path_for_lua, &module_name);
+    let searcher = lua.create_function(move |lua, requested: String| {
+        let (module_name, roots) = search_roots(lua, &base_path_for_lua, &requested);
+
+        // Standalone executables serve modules from the embedded payload first
+        let embedded = lua.app_data_ref::<Vfs>().and_then(|vfs| {
+            vfs.resolve_module(&module_name)
+                .map(|(path, code)| (path.to_string(), code.to_vec()))
+        });
+        if let Some((path, code)) = embedded {
+            debug!("Loading module '{}' from embedded payload ({})", module_name, path);
+            let loader: Function = lua.load(&code[..]).set_name(path.as_str()).into_function()?;
+            return Ok((Value::Function(loader), Value::String(lua.create_string(&path)?)));
+        }
+
+        let path = roots
+            .iter()
+            .filter_map(|root| resolve_module_path(root, &module_name))
+            .find(|path| path.exists())
+            .or_else(|| resolve_module_path(&base_path_for_lua, &module_name));
 
         debug!("Searching for module '{}' at {:?}", module_name, path);
 
@@ -80,6 +149,11 @@ pub fn setup_loader(lua: &Lua, base_path: &Path) -> Result<()> {
                 let code = std::fs::read_to_string(&path)
                     .map_err(|e| mlua::Error::runtime(format!("Failed to read module: {}", e)))?;
 
+                if let Some(hook) = lua.app_data_ref::<ModuleLoadHook>() {
+                    (hook.0)(&path);
+                }
+                crate::hot::record(lua, &requested, &path);
+
                 let chunk = lua.load(&code)
                     .set_name(path.to_string_lossy());
 
@@ -98,18 +172,15 @@ pub fn setup_loader(lua: &Lua, base_path: &Path) -> Result<()> {
     })?;
 
     // Create native module searcher
-    let native_searcher = lua.create_function(move |lua, module_name: String| {
-        let native_path = resolve_native_path(&base_path_for_native, &module_name);
+    let native_searcher = lua.create_function(move |lua, requested: String| {
+        let (module_name, roots) = search_roots(lua, &base_path_for_native, &requested);
+        let native_path = roots.iter().find_map(|root| resolve_native_path(root, &module_name));
 
         debug!("Searching for native module '{}' at {:?}", module_name, native_path);
 
         if let Some(ref path) = native_path {
             if path.exists() {
-                // Build the entry point symbol name: luaopen_<name_with_underscores>
-                let symbol_name = format!(
-                    "luaopen_{}",
-                    module_name.replace('.', "_").replace('-', "_")
-                );
+                let symbol_name = native_entry_symbol(&module_name);
                 let symbol_name_null = format!("{}\0", symbol_name);
 
                 debug!("Loading native module '{}' from {:?}, symbol: {}", module_name, path, symbol_name);
@@ -128,10 +199,13 @@ pub fn setup_loader(lua: &Lua, base_path: &Path) -> Result<()> {
 
                     let func_ptr = *func;
 
+                    // Hand SDK modules the host vtable before luaopen runs
+                    crate::native_host::init_module(&lib, path)?;
+
                     // Store library handle to keep it alive for the Lua state's lifetime
                     let store = lua.app_data_ref::<NativeLibStore>()
                         .ok_or_else(|| mlua::Error::runtime("NativeLibStore not initialized"))?;
-                    store.libs.lock().unwrap().push(lib);
+                    store.libs.lock().unwrap().push((path.clone(), lib));
 
                     // Wrap the C function as a Lua function
                     let loader = lua.create_c_function(func_ptr)?;
@@ -149,15 +223,15 @@ pub fn setup_loader(lua: &Lua, base_path: &Path) -> Result<()> {
         }
     })?;
 
-    // Get package.searchers table
+    // Get package.searchers table (package.loaders on LuaJIT / Luau)
     let package: Table = lua.globals().get("package")?;
-    let searchers: Table = package.get("searchers")?;
+    let searchers: Table = package.get(crate::vm::SEARCHERS)?;
 
-    // Insert our Lua searcher at position 2 (after the preload searcher)
-    searchers.set(2, searcher)?;
+    // Insert our Lua searcher (after the preload searcher)
+    searchers.set(LUA_SEARCHER, searcher)?;
 
-    // Insert native searcher at position 3 (after Lua searcher, so .lua files take precedence)
-    searchers.set(3, native_searcher)?;
+    // Insert native searcher after the Lua searcher, so .lua files take precedence
+    searchers.set(NATIVE_SEARCHER, native_searcher)?;
 
     // Set package.path to include our paths
     let lua_path = format!(
@@ -166,23 +240,45 @@ pub fn setup_loader(lua: &Lua, base_path: &Path) -> Result<()> {
     );
     package.set("path", lua_path)?;
 
+    // Reloadable modules (`package.reload`, `package.hot`)
+    crate::hot::install(lua)?;
+
     Ok(())
 }
 
-/// Resolve a module name to a Lua file path
-fn resolve_module_path(base_path: &Path, module_name: &str) -> Option<PathBuf> {
+/// Make `require` refuse native modules, reporting `reason` in the
+/// "module not found" message.
+pub fn disable_native_modules(lua: &Lua, reason: &str) -> Result<()> {
+    let package: Table = lua.globals().get("package")?;
+    let searchers: Table = package.get(crate::vm::SEARCHERS)?;
+    let reason = reason.to_string();
+    searchers.set(NATIVE_SEARCHER, lua.create_function(move |_, name: String| {
+        Ok(format!("\n\tno native module '{}' ({})", name, reason))
+    })?)?;
+    Ok(())
+}
+
+/// Candidate relative paths for a module name, in resolution order.
+pub(crate) fn module_candidates(module_name: &str) -> [String; 4] {
     // Convert module name to path (e.g., "foo.bar" -> "foo/bar")
     let module_path = module_name.replace('.', "/");
 
-    // Try different patterns
-    let patterns = [
+    [
         format!("{}.lua", module_path),
         format!("{}/init.lua", module_path),
         format!("harbor_modules/{}.lua", module_path),
         format!("harbor_modules/{}/init.lua", module_path),
-    ];
+    ]
+}
 
-    for pattern in patterns {
+/// Resolve a module name to a Lua file path.
+///
+/// Returns the first existing candidate, or the `<name>.lua` path (which may
+/// not exist) for error reporting.
+pub fn resolve_module_path(base_path: &Path, module_name: &str) -> Option<PathBuf> {
+    let module_path = module_name.replace('.', "/");
+
+    for pattern in module_candidates(module_name) {
         let path = base_path.join(&pattern);
         if path.exists() {
             return Some(path);
@@ -193,11 +289,10 @@ fn resolve_module_path(base_path: &Path, module_name: &str) -> Option<PathBuf> {
     Some(base_path.join(format!("{}.lua", module_path)))
 }
 
-/// Resolve a module name to a native library path
-fn resolve_native_path(base_path: &Path, module_name: &str) -> Option<PathBuf> {
-    let module_path = module_name.replace('.', "/");
-
-    // Platform-specific library naming
+/// File name of the native library for a module on this platform:
+/// `lib<leaf>.so`, `lib<leaf>.dylib` or `<leaf>.dll`, where the leaf is the
+/// last dotted segment with hyphens replaced by underscores.
+pub fn native_lib_filename(module_name: &str) -> String {
     let (prefix, ext) = if cfg!(windows) {
         ("", "dll")
     } else if cfg!(target_os = "macos") {
@@ -205,14 +300,25 @@ fn resolve_native_path(base_path: &Path, module_name: &str) -> Option<PathBuf> {
     } else {
         ("lib", "so")
     };
-
-    // The leaf name (last segment after dots), with hyphens replaced by underscores
     let leaf = module_name
         .rsplit('.')
         .next()
         .unwrap_or(module_name)
         .replace('-', "_");
-    let lib_filename = format!("{}{}.{}", prefix, leaf, ext);
+    format!("{}{}.{}", prefix, leaf, ext)
+}
+
+/// Entry point a native module must export: `luaopen_<name>` with dots and
+/// hyphens replaced by underscores.
+pub fn native_entry_symbol(module_name: &str) -> String {
+    format!("luaopen_{}", module_name.replace(['.', '-'], "_"))
+}
+
+/// Resolve a module name to a native library path
+fn resolve_native_path(base_path: &Path, module_name: &str) -> Option<PathBuf> {
+    let module_path = module_name.replace('.', "/");
+
+    let lib_filename = native_lib_filename(module_name);
 
     // Search patterns:
     // 1. harbor_modules/<path>/native/<lib>  (installed packages where dir matches module name)
@@ -312,4 +418,28 @@ mod tests {
         assert!(path.is_some());
         assert!(path.unwrap().exists());
     }
+
+    #[test]
+    fn test_search_paths_and_aliases() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("app");
+        let lib = dir.path().join("lib");
+        fs::create_dir_all(&base).unwrap();
+        fs::create_dir_all(lib.join("db")).unwrap();
+        fs::write(lib.join("db/sqlite.lua"), "return 'from lib'").unwrap();
+
+        let runtime = crate::Runtime::with_base_path(&base).unwrap();
+        runtime.set_module_search(ModuleSearch {
+            paths: vec![lib],
+            aliases: HashMap::from([("database".to_string(), "db.sqlite".to_string())]),
+        });
+        runtime.setup_module_loader().unwrap();
+
+        let result: (String, String) = runtime
+            .lua()
+            .load("return require('db.sqlite'), require('database')")
+            .eval()
+            .unwrap();
+        assert_eq!(result, ("from lib".to_string(), "from lib".to_string()));
+    }
 }
@@ -0,0 +1,132 @@
+//! Host side of the native module SDK ABI
+//!
+//! Modules built with `coppermoon_sdk` export `coppermoon_sdk_init`; the
+//! native searcher calls it with [`HOST_API`] before `luaopen_<name>`, which
+//! gives the module access to the Tokio runtime and the event loop. Modules
+//! built without the SDK don't export it and load as before.
+
+use crate::async_runtime::get_runtime;
+use crate::event_loop;
+use coppermoon_sdk::abi::{HostApi, InitFn, LuaTask, Task, ABI_VERSION, INIT_SYMBOL};
+use std::ffi::{c_char, c_void};
+use std::path::Path;
+use std::time::Duration;
+
+const HOST_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+
+#[cfg(feature = "lua54")]
+const VM_NAME: &str = "Lua 5.4\0";
+#[cfg(feature = "luajit")]
+const VM_NAME: &str = "LuaJIT\0";
+#[cfg(feature = "luau")]
+const VM_NAME: &str = "Luau\0";
+
+/// The vtable handed to every SDK module.
+pub static HOST_API: HostApi = HostApi {
+    abi_version: ABI_VERSION,
+    size: std::mem::size_of::<HostApi>(),
+    host_version: HOST_VERSION.as_ptr() as *const c_char,
+    vm: VM_NAME.as_ptr() as *const c_char,
+    spawn,
+    post,
+    post_after,
+    hold,
+    release,
+    report_error,
+};
+
+/// Owns a task until it runs; dropping it unrun hands `data` back to the
+/// module to free.
+struct Owned<T: Payload>(Option<T>);
+
+trait Payload {
+    fn release(self);
+}
+
+impl Payload for Task {
+    fn release(self) {
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
+impl Payload for LuaTask {
+    fn release(self) {
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
+impl<T: Payload> Owned<T> {
+    fn take(mut self) -> T {
+        self.0.take().expect("task already taken")
+    }
+}
+
+impl<T: Payload> Drop for Owned<T> {
+    fn drop(&mut self) {
+        if let Some(task) = self.0.take() {
+            task.release();
+        }
+    }
+}
+
+unsafe extern "C" fn spawn(task: Task) {
+    let task = Owned(Some(task));
+    get_runtime().spawn_blocking(move || {
+        let task = task.take();
+        unsafe { (task.run)(task.data) }
+    });
+}
+
+unsafe extern "C" fn post(task: LuaTask) {
+    let task = Owned(Some(task));
+    event_loop::post_main_thread_task(Box::new(move |lua| {
+        let task = task.take();
+        let result = unsafe {
+            lua.exec_raw::<()>((), |state| (task.run)(task.data, state as *mut c_void))
+        };
+        if let Err(e) = result {
+            event_loop::report_error("Native module callback", &e);
+        }
+    }));
+}
+
+unsafe extern "C" fn post_after(ms: u64, task: LuaTask) {
+    let task = Owned(Some(task));
+    event_loop::hold_work();
+    get_runtime().spawn(async move {
+        tokio::time::sleep(Duration::from_millis(ms)).await;
+        unsafe { post(task.take()) };
+        event_loop::release_work();
+    });
+}
+
+unsafe extern "C" fn hold() {
+    event_loop::hold_work();
+}
+
+unsafe extern "C" fn release() {
+    event_loop::release_work();
+}
+
+unsafe extern "C" fn report_error(message: *const u8, len: usize) {
+    let message = String::from_utf8_lossy(unsafe { std::slice::from_raw_parts(message, len) });
+    event_loop::report_error("Native module callback", &mlua::Error::runtime(message));
+}
+
+/// Pass [`HOST_API`] to a freshly loaded module if it was built with the SDK.
+pub(crate) fn init_module(lib: &libloading::Library, path: &Path) -> mlua::Result<()> {
+    let init = match unsafe { lib.get::<InitFn>(INIT_SYMBOL.as_bytes()) } {
+        Ok(init) => init,
+        Err(_) => return Ok(()),
+    };
+    let code = unsafe { init(&HOST_API) };
+    if code != 0 {
+        return Err(mlua::Error::runtime(format!(
+            "Native module '{}' rejected the host (code {}); rebuild it against coppermoon_sdk {}",
+            path.display(),
+            code,
+            env!("CARGO_PKG_VERSION")
+        )));
+    }
+    Ok(())
+}
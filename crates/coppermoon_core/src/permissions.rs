@@ -0,0 +1,24 @@
+//! Permission switches checked by the modules themselves
+//!
+//! An embedder usually enforces a permission by replacing the Lua functions
+//! that need it. Some writes cannot be reached that way: they happen inside
+//! userdata methods (`zip:extract`) or behind options (`server:listen`'s
+//! `uploads`). Those entry points call [`check_fs_write`] instead.
+
+use mlua::Lua;
+
+struct ReadOnlyFs(String);
+
+/// Make [`check_fs_write`] fail for this Lua state, reporting `reason`.
+pub fn deny_fs_write(lua: &Lua, reason: &str) {
+    lua.set_app_data(ReadOnlyFs(reason.to_string()));
+}
+
+/// Fail with "permission denied: `what` is `reason`" if file writes were
+/// disabled with [`deny_fs_write`].
+pub fn check_fs_write(lua: &Lua, what: &str) -> mlua::Result<()> {
+    match lua.app_data_ref::<ReadOnlyFs>() {
+        Some(denied) => Err(mlua::Error::runtime(format!("permission denied: {} is {}", what, denied.0))),
+        None => Ok(()),
+    }
+}
@@ -2,6 +2,7 @@
 
 use crate::{Error, Result, event_loop};
 use crate::event_loop::{TimerEvent, TimerType};
+use crate::vfs::Vfs;
 use mlua::{Lua, Function, MultiValue, Value, StdLib};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
@@ -22,6 +23,9 @@ impl Runtime {
         // Open standard libraries
         lua.load_std_libs(StdLib::ALL_SAFE)?;
 
+        // Lua 5.4 functions missing from LuaJIT / Luau
+        crate::vm::install_shims(&lua)?;
+
         // Initialize native module library store
         lua.set_app_data(crate::module::NativeLibStore::new());
 
@@ -44,6 +48,14 @@ impl Runtime {
         self.base_path = path.as_ref().to_path_buf();
     }
 
+    /// Enable or disable Luau's sandbox mode: built-in libraries become
+    /// read-only and globals are isolated per script.
+    #[cfg(feature = "luau")]
+    pub fn sandbox(&self, enabled: bool) -> Result<()> {
+        self.lua.sandbox(enabled)?;
+        Ok(())
+    }
+
     /// Get a reference to the Lua state
     pub fn lua(&self) -> &Lua {
         &self.lua
@@ -89,9 +101,16 @@ impl Runtime {
             ))
         })?;
 
+        self.exec_chunk(&code, &absolute_path.to_string_lossy())
+    }
+
+    /// Execute Lua source under the given chunk name, then run the event loop
+    ///
+    /// A leading `#!` line is skipped, as the standalone `lua` interpreter does.
+    pub fn exec_chunk(&self, code: &str, name: &str) -> Result<()> {
         let chunk = self.lua
-            .load(&code)
-            .set_name(absolute_path.to_string_lossy());
+            .load(strip_shebang(code))
+            .set_name(name);
 
         chunk.exec()?;
 
@@ -101,18 +120,57 @@ impl Runtime {
         Ok(())
     }
 
+    /// Add module search directories and aliases used by `require`
+    pub fn set_module_search(&self, search: crate::module::ModuleSearch) {
+        self.lua.set_app_data(search);
+    }
+
+    /// Call `hook` with the path of every Lua module file loaded by `require`
+    pub fn on_module_load<F: Fn(&Path) + Send + 'static>(&self, hook: F) {
+        self.lua.set_app_data(crate::module::ModuleLoadHook(Box::new(hook)));
+    }
+
+    /// Install an embedded filesystem, used by standalone executables to
+    /// serve `require` and read-only `fs` calls from their payload
+    pub fn set_vfs(&self, vfs: Vfs) {
+        self.lua.set_app_data(vfs);
+    }
+
+    /// Execute the entry script of the installed embedded filesystem
+    pub fn exec_embedded(&self) -> Result<()> {
+        let (entry, code) = {
+            let vfs = self.lua.app_data_ref::<Vfs>()
+                .ok_or_else(|| Error::Runtime("No embedded payload installed".into()))?;
+            let entry = vfs.entry().to_string();
+            let code = vfs.get(&entry)
+                .ok_or_else(|| Error::Runtime(format!("Embedded entry '{}' not found", entry)))?
+                .to_vec();
+            (entry, code)
+        };
+
+        info!("Executing embedded entry: {}", entry);
+
+        let code = String::from_utf8(code)
+            .map_err(|_| Error::Runtime(format!("Embedded entry '{}' is not valid UTF-8", entry)))?;
+        self.exec_chunk(&code, &entry)
+    }
+
     /// Run the event loop to drain pending timer callbacks.
     ///
-    /// This keeps the process alive as long as there are pending timers,
+    /// This keeps the process alive as long as there are pending timers or
+    /// background work that will post back (see [`event_loop::hold_work`]),
     /// similar to how Node.js keeps running while timers are active.
     pub fn run_event_loop(&self) -> Result<()> {
-        while event_loop::has_pending_timers() {
+        while event_loop::has_pending_work() {
+            event_loop::run_main_thread_tasks(&self.lua);
+            crate::hot::poll(&self.lua);
+
             match event_loop::try_recv_timer_event(Duration::from_millis(50)) {
                 Some(TimerEvent::Ready(id)) => {
                     if let Some(cb) = event_loop::take_timer_callback(id) {
                         let func: Function = self.lua.registry_value(&cb.registry_key)?;
                         if let Err(e) = func.call::<()>(()) {
-                            eprintln!("Timer callback error: {}", e);
+                            event_loop::report_error("Timer callback", &e);
                         }
                         match cb.timer_type {
                             TimerType::Timeout => {
@@ -156,8 +214,18 @@ impl Default for Runtime {
     }
 }
 
+/// Skip a `#!` first line, keeping its line break so line numbers in error
+/// messages still match the file.
+pub fn strip_shebang(code: &str) -> &str {
+    if code.starts_with('#') {
+        &code[code.find('\n').unwrap_or(code.len())..]
+    } else {
+        code
+    }
+}
+
 /// Format a Lua value for display
-fn format_value(value: &Value) -> String {
+pub fn format_value(value: &Value) -> String {
     match value {
         Value::Nil => "nil".to_string(),
         Value::Boolean(b) => b.to_string(),
@@ -219,6 +287,15 @@ mod tests {
         assert_eq!(result, "\"hello\"");
     }
 
+    #[test]
+    fn test_shebang_keeps_line_numbers() {
+        let runtime = Runtime::new().unwrap();
+        assert!(runtime.exec_chunk("#!/usr/bin/env coppermoon\nx = 1", "script").is_ok());
+
+        let err = runtime.exec_chunk("#!/usr/bin/env coppermoon\n\nerror('boom')", "script").unwrap_err();
+        assert!(err.to_string().contains(":3:"), "{}", err);
+    }
+
     #[test]
     fn test_eval_multiple_values() {
         let runtime = Runtime::new().unwrap();
@@ -0,0 +1,319 @@
+//! Embedded virtual filesystem for standalone executables
+//!
+//! `coppermoon compile --standalone` appends a payload archive to a copy of
+//! the runtime binary. At startup the runtime checks its own executable for
+//! that payload and, when present, serves `require` and read-only `fs` calls
+//! from it so the binary can run with no files next to it.
+//!
+//! Payload layout (all integers little-endian):
+//!
+//! ```text
+//! [entry_len: u32][entry path]
+//! [file_count: u32]
+//! repeated: [path_len: u32][path][data_len: u64][data]
+//! [payload_len: u64][MAGIC: 8 bytes]
+//! ```
+
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Trailer magic marking a binary with an appended payload.
+pub const PAYLOAD_MAGIC: &[u8; 8] = b"CMPAYLD1";
+
+/// Size of the trailer: payload length (u64) followed by the magic.
+const TRAILER_LEN: u64 = 16;
+
+/// A read-only, in-memory file tree keyed by `/`-separated relative paths.
+#[derive(Debug, Default, Clone)]
+pub struct Vfs {
+    entry: String,
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Vfs {
+    /// Create an empty filesystem whose entry script is `entry`.
+    pub fn new(entry: &str) -> Self {
+        Self {
+            entry: normalize(entry).unwrap_or_else(|| entry.to_string()),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Path of the entry script inside the payload.
+    pub fn entry(&self) -> &str {
+        &self.entry
+    }
+
+    /// Add (or replace) a file.
+    pub fn insert(&mut self, path: &str, data: Vec<u8>) {
+        if let Some(key) = normalize(path) {
+            self.files.insert(key, data);
+        }
+    }
+
+    /// Number of files in the payload.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns `true` if the payload contains no files.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Iterate over all `(path, contents)` pairs.
+    pub fn files(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.files.iter().map(|(k, v)| (k.as_str(), v.as_slice()))
+    }
+
+    /// Get the contents of a file.
+    pub fn get(&self, path: &str) -> Option<&[u8]> {
+        let key = normalize(path)?;
+        self.files.get(&key).map(|v| v.as_slice())
+    }
+
+    /// Returns `true` if `path` is a file in the payload.
+    pub fn is_file(&self, path: &str) -> bool {
+        self.get(path).is_some()
+    }
+
+    /// Returns `true` if `path` is a directory prefix of any file.
+    pub fn is_dir(&self, path: &str) -> bool {
+        match normalize(path) {
+            Some(key) if key.is_empty() => !self.files.is_empty(),
+            Some(key) => {
+                let prefix = format!("{}/", key);
+                self.files.keys().any(|k| k.starts_with(&prefix))
+            }
+            None => false,
+        }
+    }
+
+    /// List the immediate children (files and directories) of `path`.
+    pub fn read_dir(&self, path: &str) -> Option<Vec<String>> {
+        if !self.is_dir(path) {
+            return None;
+        }
+        let key = normalize(path)?;
+        let prefix = if key.is_empty() { String::new() } else { format!("{}/", key) };
+
+        let mut names: Vec<String> = self
+            .files
+            .keys()
+            .filter_map(|k| k.strip_prefix(&prefix))
+            .map(|rest| rest.split('/').next().unwrap_or(rest).to_string())
+            .collect();
+        names.dedup();
+        Some(names)
+    }
+
+    /// Resolve a module name using the same patterns as the filesystem
+    /// loader. Returns the matched path and the module source.
+    pub fn resolve_module(&self, module_name: &str) -> Option<(&str, &[u8])> {
+        crate::module::module_candidates(module_name)
+            .iter()
+            .find_map(|candidate| {
+                self.files
+                    .get_key_value(candidate.as_str())
+                    .map(|(k, v)| (k.as_str(), v.as_slice()))
+            })
+    }
+
+    /// Serialize the filesystem into a payload (including the trailer).
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_bytes_u32(&mut out, self.entry.as_bytes());
+        out.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+        for (path, data) in &self.files {
+            write_bytes_u32(&mut out, path.as_bytes());
+            out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            out.extend_from_slice(data);
+        }
+        let payload_len = out.len() as u64;
+        out.extend_from_slice(&payload_len.to_le_bytes());
+        out.extend_from_slice(PAYLOAD_MAGIC);
+        out
+    }
+
+    /// Parse a payload body (without the trailer).
+    pub fn from_payload(data: &[u8]) -> Result<Self> {
+        let mut cursor = PayloadReader { data, pos: 0 };
+        let entry = cursor.read_string()?;
+        let count = cursor.read_u32()?;
+        let mut files = BTreeMap::new();
+        for _ in 0..count {
+            let path = cursor.read_string()?;
+            let len = cursor.read_u64()? as usize;
+            let bytes = cursor.take(len)?.to_vec();
+            files.insert(path, bytes);
+        }
+        Ok(Self { entry, files })
+    }
+
+    /// Read the payload appended to an executable, if there is one.
+    pub fn from_executable<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        let mut file = std::fs::File::open(path.as_ref())?;
+        let Some(payload_len) = read_trailer(&mut file)? else {
+            return Ok(None);
+        };
+
+        let file_len = file.seek(SeekFrom::End(0))?;
+        let start = file_len
+            .checked_sub(TRAILER_LEN + payload_len)
+            .ok_or_else(|| Error::Runtime("Corrupt standalone payload".into()))?;
+        file.seek(SeekFrom::Start(start))?;
+
+        let mut body = vec![0u8; payload_len as usize];
+        file.read_exact(&mut body)?;
+        Self::from_payload(&body).map(Some)
+    }
+
+    /// Read the payload appended to the running executable, if there is one.
+    pub fn from_current_exe() -> Option<Self> {
+        let exe = std::env::current_exe().ok()?;
+        Self::from_executable(exe).ok().flatten()
+    }
+}
+
+/// Return the size of `binary` with any appended payload stripped off.
+///
+/// Used when compiling from an executable that is itself standalone.
+pub fn runtime_len(binary: &[u8]) -> usize {
+    let len = binary.len();
+    if len < TRAILER_LEN as usize || &binary[len - 8..] != PAYLOAD_MAGIC {
+        return len;
+    }
+    let mut size = [0u8; 8];
+    size.copy_from_slice(&binary[len - 16..len - 8]);
+    let payload_len = u64::from_le_bytes(size) as usize;
+    len.saturating_sub(TRAILER_LEN as usize + payload_len)
+}
+
+/// Convert a relative path into a payload key (`/`-separated, no `.`
+/// segments). Returns `None` for absolute paths or paths escaping the root.
+pub fn normalize(path: &str) -> Option<String> {
+    let path = path.replace('\\', "/");
+    if path.starts_with('/') || path.contains(':') {
+        return None;
+    }
+
+    let mut parts: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            s => parts.push(s),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+fn read_trailer(file: &mut std::fs::File) -> Result<Option<u64>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if len < TRAILER_LEN {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+    let mut trailer = [0u8; TRAILER_LEN as usize];
+    file.read_exact(&mut trailer)?;
+    if &trailer[8..] != PAYLOAD_MAGIC {
+        return Ok(None);
+    }
+    let mut size = [0u8; 8];
+    size.copy_from_slice(&trailer[..8]);
+    Ok(Some(u64::from_le_bytes(size)))
+}
+
+fn write_bytes_u32(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+struct PayloadReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.data.len())
+            .ok_or_else(|| Error::Runtime("Corrupt standalone payload".into()))?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        let len = self.read_u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| Error::Runtime("Corrupt standalone payload".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("./lib/foo.lua").as_deref(), Some("lib/foo.lua"));
+        assert_eq!(normalize("lib\\foo.lua").as_deref(), Some("lib/foo.lua"));
+        assert_eq!(normalize("a/../b").as_deref(), Some("b"));
+        assert_eq!(normalize("../escape"), None);
+        assert_eq!(normalize("/abs/path"), None);
+    }
+
+    #[test]
+    fn test_payload_roundtrip() {
+        let mut vfs = Vfs::new("./main.lua");
+        vfs.insert("main.lua", b"print('hi')".to_vec());
+        vfs.insert("public/index.html", b"<h1>hi</h1>".to_vec());
+
+        let mut binary = b"fake runtime".to_vec();
+        binary.extend_from_slice(&vfs.to_payload());
+        assert_eq!(runtime_len(&binary), "fake runtime".len());
+
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("app");
+        std::fs::write(&exe, &binary).unwrap();
+
+        let loaded = Vfs::from_executable(&exe).unwrap().unwrap();
+        assert_eq!(loaded.entry(), "main.lua");
+        assert_eq!(loaded.get("public/index.html"), Some(&b"<h1>hi</h1>"[..]));
+        assert!(loaded.is_dir("public"));
+        assert_eq!(loaded.read_dir("").unwrap(), vec!["main.lua", "public"]);
+    }
+
+    #[test]
+    fn test_plain_binary_has_no_payload() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("plain");
+        std::fs::write(&exe, b"just a binary").unwrap();
+        assert!(Vfs::from_executable(&exe).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_resolve_module() {
+        let mut vfs = Vfs::new("main.lua");
+        vfs.insert("harbor_modules/honeymoon/init.lua", b"return {}".to_vec());
+        let (path, _) = vfs.resolve_module("honeymoon").unwrap();
+        assert_eq!(path, "harbor_modules/honeymoon/init.lua");
+    }
+}
@@ -0,0 +1,288 @@
+//! Lua VM selection and compatibility shims
+//!
+//! The VM is chosen at build time with exactly one of the `lua54`
+//! (default), `luajit` or `luau` cargo features, which select the matching
+//! mlua backend. The standard library is written against Lua 5.4; this
+//! module papers over the differences it relies on:
+//!
+//! * LuaJIT and Luau have no integer subtype, so integral numbers arrive as
+//!   `Value::Number`. Use [`to_integer`] to read them and [`integer`] to
+//!   create them (Luau integers are also only 32 bits wide in mlua).
+//! * `package.searchers` is `package.loaders` on LuaJIT and Luau
+//!   ([`SEARCHERS`]).
+//! * LuaJIT has no `utf8` library and neither has `math.type`; [`install_shims`]
+//!   adds Lua 5.4 compatible versions, plus `table.pack`/`table.unpack` on
+//!   LuaJIT.
+
+use crate::Result;
+use mlua::{Lua, MultiValue, Table, Value, Variadic};
+
+#[cfg(not(any(feature = "lua54", feature = "luajit", feature = "luau")))]
+compile_error!("enable one Lua VM feature: `lua54`, `luajit` or `luau`");
+
+#[cfg(any(
+    all(feature = "lua54", feature = "luajit"),
+    all(feature = "lua54", feature = "luau"),
+    all(feature = "luajit", feature = "luau"),
+))]
+compile_error!("the `lua54`, `luajit` and `luau` features are mutually exclusive; use `default-features = false` to pick a non-default VM");
+
+/// Human-readable name of the VM this build runs on
+#[cfg(feature = "lua54")]
+pub const NAME: &str = "Lua 5.4";
+#[cfg(feature = "luajit")]
+pub const NAME: &str = "LuaJIT";
+#[cfg(feature = "luau")]
+pub const NAME: &str = "Luau";
+
+/// Cargo feature name of the VM (`lua54`, `luajit` or `luau`)
+#[cfg(feature = "lua54")]
+pub const ID: &str = "lua54";
+#[cfg(feature = "luajit")]
+pub const ID: &str = "luajit";
+#[cfg(feature = "luau")]
+pub const ID: &str = "luau";
+
+/// Field of `package` holding the module searchers
+#[cfg(feature = "lua54")]
+pub const SEARCHERS: &str = "searchers";
+#[cfg(not(feature = "lua54"))]
+pub const SEARCHERS: &str = "loaders";
+
+/// Whether the VM distinguishes integers from floats.
+pub const HAS_INTEGERS: bool = cfg!(feature = "lua54");
+
+/// Widen a Lua integer (`i64` on Lua 5.4 and LuaJIT, `i32` on Luau).
+#[allow(clippy::useless_conversion)]
+pub fn widen(i: mlua::Integer) -> i64 {
+    i64::from(i)
+}
+
+/// The integer value of a number, accepting integral floats so that code
+/// behaves the same on VMs without an integer subtype.
+pub fn to_integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(i) => Some(widen(*i)),
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < 9.007_199_254_740_992e15 => Some(*n as i64),
+        _ => None,
+    }
+}
+
+/// A Lua value for `n`: an integer on Lua 5.4, a number elsewhere.
+pub fn integer(n: i64) -> Value {
+    if HAS_INTEGERS {
+        Value::Integer(n as mlua::Integer)
+    } else {
+        Value::Number(n as f64)
+    }
+}
+
+/// Add the Lua 5.4 functions the VM lacks. Called by [`crate::Runtime::new`].
+pub fn install_shims(lua: &Lua) -> Result<()> {
+    let globals = lua.globals();
+
+    if !HAS_INTEGERS {
+        // math.type: integral numbers report "integer", as they would on 5.4
+        let math: Table = globals.get("math")?;
+        if math.get::<Value>("type")?.is_nil() {
+            math.set("type", lua.create_function(|_, value: Value| {
+                Ok(match value {
+                    Value::Integer(_) => Some("integer"),
+                    Value::Number(n) if n.fract() == 0.0 => Some("integer"),
+                    Value::Number(_) => Some("float"),
+                    _ => None,
+                })
+            })?)?;
+        }
+    }
+
+    if globals.get::<Value>("utf8")?.is_nil() {
+        globals.set("utf8", utf8::register(lua)?)?;
+    }
+
+    let table: Table = globals.get("table")?;
+    if table.get::<Value>("unpack")?.is_nil() {
+        table.set("unpack", globals.get::<Value>("unpack")?)?;
+    }
+    if table.get::<Value>("pack")?.is_nil() {
+        table.set("pack", lua.create_function(|lua, args: MultiValue| {
+            let packed = lua.create_sequence_from(args.iter().cloned())?;
+            packed.set("n", args.len())?;
+            Ok(packed)
+        })?)?;
+    }
+
+    Ok(())
+}
+
+/// The Lua 5.4 `utf8` library, for VMs that lack it.
+mod utf8 {
+    use super::*;
+
+    /// Pattern matching exactly one UTF-8 byte sequence
+    const CHARPATTERN: &[u8] = b"[\x00-\x7F\xC2-\xFD][\x80-\xBF]*";
+
+    pub fn register(lua: &Lua) -> mlua::Result<Table> {
+        let utf8 = lua.create_table()?;
+        utf8.set("charpattern", lua.create_string(CHARPATTERN)?)?;
+
+        // utf8.char(...) -> string
+        utf8.set("char", lua.create_function(|lua, codes: Variadic<u32>| {
+            let mut out = String::new();
+            for code in codes {
+                let c = char::from_u32(code)
+                    .ok_or_else(|| mlua::Error::runtime(format!("value out of range: {}", code)))?;
+                out.push(c);
+            }
+            lua.create_string(&out)
+        })?)?;
+
+        // utf8.codepoint(s, i?, j?) -> ...
+        utf8.set("codepoint", lua.create_function(|_, (s, i, j): (mlua::String, Option<i64>, Option<i64>)| {
+            let bytes = s.as_bytes();
+            let i = position(i.unwrap_or(1), bytes.len());
+            let j = position(j.unwrap_or(i as i64), bytes.len());
+            let mut codes = Vec::new();
+            let mut pos = i.max(1) - 1;
+            while pos < j {
+                let (code, len) = decode(&bytes[pos..])
+                    .ok_or_else(|| mlua::Error::runtime("invalid UTF-8 code"))?;
+                codes.push(code);
+                pos += len;
+            }
+            Ok(Variadic::from_iter(codes))
+        })?)?;
+
+        // utf8.len(s, i?, j?) -> count | nil, position
+        utf8.set("len", lua.create_function(|_, (s, i, j): (mlua::String, Option<i64>, Option<i64>)| {
+            let bytes = s.as_bytes();
+            let mut pos = position(i.unwrap_or(1), bytes.len()).max(1) - 1;
+            let end = position(j.unwrap_or(-1), bytes.len());
+            let mut count = 0;
+            while pos < end {
+                match decode(&bytes[pos..]) {
+                    Some((_, len)) => {
+                        pos += len;
+                        count += 1;
+                    }
+                    None => return Ok((None, Some(pos as i64 + 1))),
+                }
+            }
+            Ok((Some(count), None))
+        })?)?;
+
+        // utf8.offset(s, n, i?) -> position
+        utf8.set("offset", lua.create_function(|_, (s, n, i): (mlua::String, i64, Option<i64>)| {
+            let bytes = s.as_bytes();
+            let is_cont = |p: usize| p < bytes.len() && bytes[p] & 0xC0 == 0x80;
+            let default = if n >= 0 { 1 } else { bytes.len() as i64 + 1 };
+            let mut pos = position(i.unwrap_or(default), bytes.len()) as i64 - 1;
+            if pos < 0 || pos > bytes.len() as i64 {
+                return Err(mlua::Error::runtime("position out of bounds"));
+            }
+            let mut n = n;
+            if n == 0 {
+                while pos > 0 && is_cont(pos as usize) {
+                    pos -= 1;
+                }
+                return Ok(Some(pos + 1));
+            }
+            if is_cont(pos as usize) {
+                return Err(mlua::Error::runtime("initial position is a continuation byte"));
+            }
+            if n < 0 {
+                while n < 0 && pos > 0 {
+                    pos -= 1;
+                    while pos > 0 && is_cont(pos as usize) {
+                        pos -= 1;
+                    }
+                    n += 1;
+                }
+            } else {
+                n -= 1;
+                while n > 0 && pos < bytes.len() as i64 {
+                    pos += 1;
+                    while is_cont(pos as usize) {
+                        pos += 1;
+                    }
+                    n -= 1;
+                }
+            }
+            Ok((n == 0).then_some(pos + 1))
+        })?)?;
+
+        // utf8.codes(s) -> iterator yielding position, code
+        utf8.set("codes", lua.create_function(|lua, s: mlua::String| {
+            let iter = lua.create_function(|_, (s, pos): (mlua::String, i64)| {
+                let bytes = s.as_bytes();
+                let mut next = pos.max(0) as usize;
+                // Skip the current character
+                if next > 0 {
+                    while next < bytes.len() && bytes[next] & 0xC0 == 0x80 {
+                        next += 1;
+                    }
+                }
+                if next >= bytes.len() {
+                    return Ok((None, None));
+                }
+                let (code, _) = decode(&bytes[next..])
+                    .ok_or_else(|| mlua::Error::runtime("invalid UTF-8 code"))?;
+                Ok((Some(next as i64 + 1), Some(code)))
+            })?;
+            Ok((iter, s, 0))
+        })?)?;
+
+        Ok(utf8)
+    }
+
+    /// Lua string position (1-based, negative from the end) to a byte count.
+    fn position(pos: i64, len: usize) -> usize {
+        if pos >= 0 {
+            (pos as usize).min(len)
+        } else {
+            (len as i64 + pos + 1).max(0) as usize
+        }
+    }
+
+    /// Decode one UTF-8 sequence: (code point, byte length).
+    fn decode(bytes: &[u8]) -> Option<(u32, usize)> {
+        let len = match *bytes.first()? {
+            0x00..=0x7F => 1,
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return None,
+        };
+        let s = std::str::from_utf8(bytes.get(..len)?).ok()?;
+        Some((s.chars().next()? as u32, len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_integer_accepts_integral_floats() {
+        assert_eq!(to_integer(&Value::Number(3.0)), Some(3));
+        assert_eq!(to_integer(&Value::Number(3.5)), None);
+        assert_eq!(to_integer(&integer(42)), Some(42));
+        assert_eq!(to_integer(&Value::Nil), None);
+    }
+
+    #[test]
+    fn test_utf8_shim_matches_lua54() {
+        let lua = Lua::new();
+        lua.globals().set("utf8", utf8::register(&lua).unwrap()).unwrap();
+        let result: (i64, String, i64, i64, i64) = lua
+            .load(r#"
+                local s = "héllo"
+                local count = 0
+                for _ in utf8.codes(s) do count = count + 1 end
+                return utf8.len(s), utf8.char(104, 233), utf8.codepoint(s, 2), utf8.offset(s, 3), count
+            "#)
+            .eval()
+            .unwrap();
+        assert_eq!(result, (5, "hé".to_string(), 233, 4, 5));
+    }
+}
@@ -0,0 +1,80 @@
+//! The C ABI between the CopperMoon host and native modules
+//!
+//! These types are shared verbatim by the host (`coppermoon_core`) and by
+//! modules built with this crate, which may have been compiled by a
+//! different Rust version. Everything is `#[repr(C)]` and only plain
+//! pointers and `extern "C"` functions cross the boundary.
+//!
+//! Compatibility rules: fields are only ever appended to [`HostApi`], each
+//! addition bumps [`ABI_VERSION`], and `size` tells a module which fields
+//! the host actually provides.
+
+use std::ffi::{c_char, c_int, c_void};
+
+/// Version of [`HostApi`] this crate was built against
+pub const ABI_VERSION: u32 = 2;
+
+/// Name of the function a module exports to receive the [`HostApi`]:
+/// `int coppermoon_sdk_init(const HostApi *api)`. The host calls it right
+/// before `luaopen_<name>`; a non-zero return aborts loading.
+pub const INIT_SYMBOL: &str = "coppermoon_sdk_init";
+
+/// Signature of [`INIT_SYMBOL`]
+pub type InitFn = unsafe extern "C" fn(api: *const HostApi) -> c_int;
+
+/// Work to run on a host worker thread. `run` takes ownership of `data`;
+/// `drop` releases it if the task never runs (e.g. at shutdown).
+#[repr(C)]
+pub struct Task {
+    pub data: *mut c_void,
+    pub run: unsafe extern "C" fn(data: *mut c_void),
+    pub drop: unsafe extern "C" fn(data: *mut c_void),
+}
+
+/// Work to run on the main Lua thread, with the `lua_State` of the script.
+/// Ownership of `data` works as for [`Task`].
+#[repr(C)]
+pub struct LuaTask {
+    pub data: *mut c_void,
+    pub run: unsafe extern "C" fn(data: *mut c_void, state: *mut c_void),
+    pub drop: unsafe extern "C" fn(data: *mut c_void),
+}
+
+// The pointers are owned by the task and only touched by whoever runs it
+unsafe impl Send for Task {}
+unsafe impl Send for LuaTask {}
+
+/// Runtime services the host offers to native modules.
+///
+/// All functions may be called from any thread unless noted.
+#[repr(C)]
+pub struct HostApi {
+    /// [`ABI_VERSION`] of the host
+    pub abi_version: u32,
+    /// `size_of::<HostApi>()` in the host
+    pub size: usize,
+    /// NUL-terminated CopperMoon version, e.g. `"0.1.61"`
+    pub host_version: *const c_char,
+    /// NUL-terminated Lua VM name, e.g. `"Lua 5.4"`
+    pub vm: *const c_char,
+
+    /// Run a task on the host's Tokio runtime (blocking thread pool).
+    pub spawn: unsafe extern "C" fn(task: Task),
+    /// Run a task on the main Lua thread at the next event loop turn.
+    pub post: unsafe extern "C" fn(task: LuaTask),
+    /// Run a task on the main Lua thread after `ms` milliseconds. Keeps the
+    /// event loop alive until then.
+    pub post_after: unsafe extern "C" fn(ms: u64, task: LuaTask),
+    /// Keep the event loop running (e.g. while background work is pending).
+    pub hold: unsafe extern "C" fn(),
+    /// Undo one `hold`.
+    pub release: unsafe extern "C" fn(),
+
+    /// Report an error raised by a module callback, the way the host reports
+    /// errors of its own callbacks. `message` is `len` bytes of UTF-8.
+    /// Added in ABI version 2.
+    pub report_error: unsafe extern "C" fn(message: *const u8, len: usize),
+}
+
+// Only immutable data and function pointers
+unsafe impl Sync for HostApi {}
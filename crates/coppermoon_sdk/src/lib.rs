@@ -0,0 +1,260 @@
+//! CopperMoon native module SDK
+//!
+//! Native modules are shared libraries loaded by `require`. They are built
+//! separately from the runtime, so they cannot link against its event loop
+//! or Tokio runtime directly. Instead, when the host loads a module built
+//! with this crate it hands over a [`abi::HostApi`] vtable (through the
+//! exported `coppermoon_sdk_init` function, right before `luaopen_<name>`),
+//! and this crate wraps it in safe functions:
+//!
+//! * [`spawn`] runs blocking or long-running work on the host's Tokio
+//!   runtime and hands the result back to Lua on the main thread.
+//! * [`post`] and [`post_after`] queue Lua work on the main thread from any
+//!   thread, optionally after a delay.
+//! * [`hold`] keeps the script's event loop alive while work is pending.
+//! * [`set_timeout`], [`create_buffer`] and [`buffer_bytes`] use the
+//!   runtime's own `setTimeout` and `buffer` module, so timers and buffers
+//!   behave exactly like those created from Lua.
+//!
+//! Errors returned by (and panics in) `then` and [`post`] callbacks have no
+//! Lua caller; they go to the host, which reports them like errors of its
+//! own timers and callbacks.
+//!
+//! ```ignore
+//! use coppermoon_sdk::mlua::prelude::*;
+//!
+//! #[mlua::lua_module]
+//! fn my_module(lua: &Lua) -> LuaResult<LuaTable> {
+//!     let module = lua.create_table()?;
+//!     module.set("read", lua.create_function(|lua, (path, callback): (String, LuaFunction)| {
+//!         // Lua values stay on the main thread; `then` gets the function back
+//!         let callback = lua.create_registry_value(callback)?;
+//!         coppermoon_sdk::spawn(
+//!             move || std::fs::read(&path),
+//!             move |lua, result| {
+//!                 let callback: LuaFunction = lua.registry_value(&callback)?;
+//!                 let data = coppermoon_sdk::create_buffer(lua, &result.map_err(LuaError::external)?)?;
+//!                 callback.call::<()>(data)
+//!             },
+//!         )
+//!     })?)?;
+//!     Ok(module)
+//! }
+//! ```
+//!
+//! Pick the Lua VM with the `lua54` (default), `luajit` or `luau` feature;
+//! it must match the runtime that loads the module. Without a VM feature
+//! only the [`abi`] types are built, which is what the host uses.
+
+pub mod abi;
+
+#[cfg(feature = "lua")]
+pub use mlua;
+
+#[cfg(feature = "lua")]
+pub use module::*;
+
+#[cfg(feature = "lua")]
+mod module {
+    use crate::abi::{HostApi, LuaTask, Task, ABI_VERSION};
+    use mlua::{Function, Lua, Value};
+    use std::ffi::{c_int, c_void, CStr};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicPtr, Ordering};
+
+    static HOST: AtomicPtr<HostApi> = AtomicPtr::new(std::ptr::null_mut());
+
+    /// Called by the host with its vtable before `luaopen_<name>`.
+    ///
+    /// # Safety
+    ///
+    /// `api` must be null or point to a `HostApi` that lives for the rest
+    /// of the process.
+    #[no_mangle]
+    pub unsafe extern "C" fn coppermoon_sdk_init(api: *const HostApi) -> c_int {
+        if api.is_null() {
+            return 1;
+        }
+        let host = &*api;
+        // The host must provide at least the fields this module was built with
+        if host.abi_version < ABI_VERSION || host.size < std::mem::size_of::<HostApi>() {
+            return 2;
+        }
+        HOST.store(api as *mut HostApi, Ordering::Release);
+        0
+    }
+
+    /// The host's services, or `None` if the module was loaded by a plain
+    /// Lua interpreter.
+    pub fn host() -> Option<&'static HostApi> {
+        // Safety: set once by coppermoon_sdk_init to a pointer that outlives the process
+        unsafe { HOST.load(Ordering::Acquire).as_ref() }
+    }
+
+    fn require_host() -> mlua::Result<&'static HostApi> {
+        host().ok_or_else(|| mlua::Error::runtime("this native module needs the CopperMoon runtime"))
+    }
+
+    /// Version of the CopperMoon runtime that loaded the module.
+    pub fn host_version() -> Option<String> {
+        let host = host()?;
+        // Safety: the host provides a NUL-terminated static string
+        Some(unsafe { CStr::from_ptr(host.host_version) }.to_string_lossy().into_owned())
+    }
+
+    /// Name of the Lua VM the runtime uses (`"Lua 5.4"`, `"LuaJIT"`, `"Luau"`).
+    pub fn vm() -> Option<String> {
+        let host = host()?;
+        // Safety: as above
+        Some(unsafe { CStr::from_ptr(host.vm) }.to_string_lossy().into_owned())
+    }
+
+    /// Keeps the event loop running until dropped.
+    pub struct Hold(&'static HostApi);
+
+    impl Drop for Hold {
+        fn drop(&mut self) {
+            // Safety: host function pointers are valid for the process lifetime
+            unsafe { (self.0.release)() }
+        }
+    }
+
+    /// Keep the script running (as a pending timer would) until the returned
+    /// guard is dropped.
+    pub fn hold() -> mlua::Result<Hold> {
+        let host = require_host()?;
+        unsafe { (host.hold)() };
+        Ok(Hold(host))
+    }
+
+    /// Run `work` on the host's Tokio runtime, then `then(lua, result)` on
+    /// the main Lua thread. The script keeps running until `then` has run.
+    ///
+    /// `then` has to be `Send`, so it can't capture Lua values; capture a
+    /// [`mlua::RegistryKey`] from `lua.create_registry_value` instead.
+    pub fn spawn<T, W, C>(work: W, then: C) -> mlua::Result<()>
+    where
+        T: Send + 'static,
+        W: FnOnce() -> T + Send + 'static,
+        C: FnOnce(&Lua, T) -> mlua::Result<()> + Send + 'static,
+    {
+        let host = require_host()?;
+        let guard = hold()?;
+        let task = into_task(move || {
+            let result = work();
+            // Post before releasing so the event loop never sees a gap
+            let _ = post(move |lua| then(lua, result));
+            drop(guard);
+        });
+        unsafe { (host.spawn)(task) };
+        Ok(())
+    }
+
+    /// Run `f` on the main Lua thread at the next event loop turn. Can be
+    /// called from any thread.
+    pub fn post<F>(f: F) -> mlua::Result<()>
+    where
+        F: FnOnce(&Lua) -> mlua::Result<()> + Send + 'static,
+    {
+        let host = require_host()?;
+        unsafe { (host.post)(into_lua_task(f)) };
+        Ok(())
+    }
+
+    /// Run `f` on the main Lua thread after `ms` milliseconds.
+    pub fn post_after<F>(ms: u64, f: F) -> mlua::Result<()>
+    where
+        F: FnOnce(&Lua) -> mlua::Result<()> + Send + 'static,
+    {
+        let host = require_host()?;
+        unsafe { (host.post_after)(ms, into_lua_task(f)) };
+        Ok(())
+    }
+
+    /// `setTimeout(callback, ms)`: returns the timer id for `clearTimeout`.
+    pub fn set_timeout(lua: &Lua, ms: u64, callback: Function) -> mlua::Result<Value> {
+        let set_timeout: Function = lua.globals().get("setTimeout")?;
+        set_timeout.call((callback, ms))
+    }
+
+    /// A `buffer` from the standard library holding `bytes`.
+    pub fn create_buffer(lua: &Lua, bytes: &[u8]) -> mlua::Result<Value> {
+        let from: Function = buffer_module(lua)?.get("from")?;
+        from.call(lua.create_string(bytes)?)
+    }
+
+    /// The bytes of a Lua string or a standard library `buffer`.
+    pub fn buffer_bytes(lua: &Lua, value: &Value) -> mlua::Result<Vec<u8>> {
+        match value {
+            Value::String(s) => Ok(s.as_bytes().to_vec()),
+            Value::UserData(ud) => {
+                let is_buffer: Function = buffer_module(lua)?.get("isBuffer")?;
+                if !is_buffer.call::<bool>(value.clone())? {
+                    return Err(mlua::Error::runtime("expected a string or buffer"));
+                }
+                let data: mlua::String = ud.call_method("toString", ())?;
+                Ok(data.as_bytes().to_vec())
+            }
+            other => Err(mlua::Error::runtime(format!(
+                "expected a string or buffer, got {}",
+                other.type_name()
+            ))),
+        }
+    }
+
+    fn buffer_module(lua: &Lua) -> mlua::Result<mlua::Table> {
+        lua.globals()
+            .get::<Option<mlua::Table>>("buffer")?
+            .ok_or_else(|| mlua::Error::runtime("the buffer module is not available"))
+    }
+
+    /// Hand an error the host can't otherwise see to its error reporting.
+    fn report_error(message: &str) {
+        match host() {
+            // Safety: host function pointers are valid for the process lifetime
+            Some(host) => unsafe { (host.report_error)(message.as_ptr(), message.len()) },
+            None => eprintln!("native module callback error: {}", message),
+        }
+    }
+
+    fn into_task<F: FnOnce() + Send + 'static>(f: F) -> Task {
+        unsafe extern "C" fn run<F: FnOnce()>(data: *mut c_void) {
+            let f = Box::from_raw(data as *mut F);
+            // Never unwind into the host
+            if catch_unwind(AssertUnwindSafe(f)).is_err() {
+                report_error("task panicked");
+            }
+        }
+        unsafe extern "C" fn drop_box<F>(data: *mut c_void) {
+            drop(Box::from_raw(data as *mut F));
+        }
+        Task {
+            data: Box::into_raw(Box::new(f)) as *mut c_void,
+            run: run::<F>,
+            drop: drop_box::<F>,
+        }
+    }
+
+    fn into_lua_task<F>(f: F) -> LuaTask
+    where
+        F: FnOnce(&Lua) -> mlua::Result<()> + Send + 'static,
+    {
+        unsafe extern "C" fn run<F: FnOnce(&Lua) -> mlua::Result<()>>(data: *mut c_void, state: *mut c_void) {
+            let f = Box::from_raw(data as *mut F);
+            let lua = Lua::init_from_ptr(state as *mut mlua::ffi::lua_State);
+            match catch_unwind(AssertUnwindSafe(|| f(&lua))) {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => report_error(&e.to_string()),
+                Err(_) => report_error("panicked"),
+            }
+        }
+        unsafe extern "C" fn drop_box<F>(data: *mut c_void) {
+            drop(Box::from_raw(data as *mut F));
+        }
+        LuaTask {
+            data: Box::into_raw(Box::new(f)) as *mut c_void,
+            run: run::<F>,
+            drop: drop_box::<F>,
+        }
+    }
+}
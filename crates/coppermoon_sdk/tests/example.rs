@@ -0,0 +1,21 @@
+//! The example module in `examples/native-async` must keep compiling
+//! against this crate. It is a `cdylib` whose Lua symbols are resolved by
+//! the host at load time, so it is type-checked rather than linked.
+
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn test_native_async_example_compiles() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../examples/native-async/Cargo.toml");
+    let output = Command::new(env!("CARGO"))
+        .args(["check", "--quiet", "--manifest-path"])
+        .arg(&manifest)
+        .output()
+        .expect("failed to run cargo");
+    assert!(
+        output.status.success(),
+        "examples/native-async does not compile:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -3,6 +3,7 @@
 //! Provides compression and archive operations: ZIP, TAR/TAR.GZ, and raw GZIP.
 
 use crate::buffer::Buffer;
+use coppermoon_core::permissions::check_fs_write;
 use coppermoon_core::Result;
 use mlua::{Lua, Table, UserData, UserDataMethods, Value};
 use std::io::{Read, Write};
@@ -126,7 +127,8 @@ impl UserData for ZipReader {
         });
 
         // z:extract(output_dir, filter?)
-        methods.add_method("extract", |_, this, (output_dir, filter): (String, Option<Table>)| {
+        methods.add_method("extract", |lua, this, (output_dir, filter): (String, Option<Table>)| {
+            check_fs_write(lua, "zip:extract")?;
             let mut guard = this.inner.lock()
                 .map_err(|e| mlua::Error::runtime(format!("Lock error: {}", e)))?;
             let archive = guard.as_mut()
@@ -450,7 +452,8 @@ impl UserData for TarReader {
         });
 
         // t:extract(output_dir)
-        methods.add_method("extract", |_, this, output_dir: String| {
+        methods.add_method("extract", |lua, this, output_dir: String| {
+            check_fs_write(lua, "tar:extract")?;
             let mut archive = open_tar_archive(&this.path, this.is_gzipped)?;
             archive.unpack(&output_dir)
                 .map_err(|e| mlua::Error::runtime(format!("Failed to extract tar to '{}': {}", output_dir, e)))?;
@@ -723,7 +726,8 @@ fn zip_from_data(_: &Lua, data: Value) -> mlua::Result<ZipReader> {
     })
 }
 
-fn zip_create(_: &Lua, path: String) -> mlua::Result<ZipWriterObj> {
+fn zip_create(lua: &Lua, path: String) -> mlua::Result<ZipWriterObj> {
+    check_fs_write(lua, "archive.zip.create")?;
     let file = std::fs::File::create(&path)
         .map_err(|e| mlua::Error::runtime(format!("Failed to create '{}': {}", path, e)))?;
     let writer = zip::ZipWriter::new(file);
@@ -743,7 +747,8 @@ fn tar_open(_: &Lua, path: String) -> mlua::Result<TarReader> {
     Ok(TarReader { path, is_gzipped })
 }
 
-fn tar_create(_: &Lua, path: String) -> mlua::Result<TarWriterObj> {
+fn tar_create(lua: &Lua, path: String) -> mlua::Result<TarWriterObj> {
+    check_fs_write(lua, "archive.tar.create")?;
     let lower = path.to_lowercase();
     let is_gzipped = lower.ends_with(".tar.gz") || lower.ends_with(".tgz");
 
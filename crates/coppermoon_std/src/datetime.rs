@@ -7,6 +7,7 @@ use chrono::{
     DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime,
     Timelike, Utc, Weekday,
 };
+use coppermoon_core::vm;
 use mlua::prelude::*;
 use mlua::{MetaMethod, Table, UserData, UserDataMethods, Value};
 
@@ -20,7 +21,7 @@ fn dt_err(msg: impl std::fmt::Display) -> LuaError {
 
 fn value_to_i32(v: &Value) -> LuaResult<i32> {
     match v {
-        Value::Integer(n) => Ok(*n as i32),
+        Value::Integer(n) => Ok(vm::widen(*n) as i32),
         Value::Number(n) => Ok(*n as i32),
         _ => Err(dt_err("expected number")),
     }
@@ -28,7 +29,7 @@ fn value_to_i32(v: &Value) -> LuaResult<i32> {
 
 fn value_to_u32(v: &Value) -> LuaResult<u32> {
     match v {
-        Value::Integer(n) => Ok(*n as u32),
+        Value::Integer(n) => Ok(vm::widen(*n) as u32),
         Value::Number(n) => Ok(*n as u32),
         _ => Err(dt_err("expected number")),
     }
@@ -591,7 +592,7 @@ impl UserData for CopperDateTime {
                 }
                 Value::Integer(n) => {
                     let u = unit.ok_or_else(|| dt_err("add: unit string required as second argument"))?;
-                    let result = apply_duration(this.inner, n, &u)?;
+                    let result = apply_duration(this.inner, vm::widen(n), &u)?;
                     Ok(CopperDateTime { inner: result })
                 }
                 Value::Number(n) => {
@@ -611,7 +612,7 @@ impl UserData for CopperDateTime {
                 }
                 Value::Integer(n) => {
                     let u = unit.ok_or_else(|| dt_err("sub: unit string required as second argument"))?;
-                    let result = apply_duration(this.inner, -n, &u)?;
+                    let result = apply_duration(this.inner, -vm::widen(n), &u)?;
                     Ok(CopperDateTime { inner: result })
                 }
                 Value::Number(n) => {
@@ -778,7 +779,7 @@ fn datetime_factory(_lua: &Lua, args: mlua::MultiValue, is_utc: bool) -> LuaResu
         }
         1 => {
             match &args[0] {
-                Value::Integer(n) => CopperDateTime::from_timestamp(*n as f64),
+                Value::Integer(n) => CopperDateTime::from_timestamp(vm::widen(*n) as f64),
                 Value::Number(n) => CopperDateTime::from_timestamp(*n),
                 Value::String(s) => {
                     let str_ref = s.to_str().map_err(|e| dt_err(e))?;
@@ -0,0 +1,248 @@
+//! Function reference for the standard library
+//!
+//! A static registry of signatures and one-line summaries for the functions
+//! exposed to Lua. Used by the REPL's `.doc` command.
+
+/// Reference entry for one standard library function.
+#[derive(Debug)]
+pub struct FunctionDoc {
+    /// Name as seen from Lua, e.g. `fs.read` or `string.split`
+    pub name: &'static str,
+    pub signature: &'static str,
+    pub summary: &'static str,
+}
+
+macro_rules! docs {
+    ($($name:literal, $signature:literal, $summary:literal;)*) => {
+        &[$(FunctionDoc { name: $name, signature: $signature, summary: $summary },)*]
+    };
+}
+
+/// All documented functions, grouped by module.
+pub static FUNCTIONS: &[FunctionDoc] = docs! {
+    // ---- globals ----
+    "print", "print(...)", "Print values separated by tabs, formatting tables inline";
+    "setTimeout", "setTimeout(fn, ms) -> timer_id", "Call fn once after ms milliseconds";
+    "setInterval", "setInterval(fn, ms) -> timer_id", "Call fn every ms milliseconds";
+    "clearTimeout", "clearTimeout(timer_id)", "Cancel a timeout or interval";
+    "clearInterval", "clearInterval(timer_id)", "Alias for clearTimeout";
+    "package.reload", "package.reload(name) -> module", "Re-execute a module and swap it into package.loaded";
+    "package.hot", "package.hot(enabled?) -> boolean", "Reload modules automatically when their files change";
+
+    // ---- fs ----
+    "fs.read", "fs.read(path) -> string", "Read a whole file as a string";
+    "fs.read_bytes", "fs.read_bytes(path) -> Buffer", "Read a whole file as a Buffer";
+    "fs.write", "fs.write(path, content) -> boolean", "Write a string to a file, replacing it";
+    "fs.write_bytes", "fs.write_bytes(path, data) -> boolean", "Write a string or Buffer to a file";
+    "fs.append", "fs.append(path, content) -> boolean", "Append a string to a file, creating it if needed";
+    "fs.exists", "fs.exists(path) -> boolean", "Check whether a path exists";
+    "fs.is_file", "fs.is_file(path) -> boolean", "Check whether a path is a regular file";
+    "fs.is_dir", "fs.is_dir(path) -> boolean", "Check whether a path is a directory";
+    "fs.is_symlink", "fs.is_symlink(path) -> boolean", "Check whether a path is a symbolic link";
+    "fs.remove", "fs.remove(path) -> boolean", "Delete a file";
+    "fs.copy", "fs.copy(src, dest) -> bytes", "Copy a file";
+    "fs.rename", "fs.rename(src, dest) -> boolean", "Rename a file or directory";
+    "fs.move", "fs.move(src, dest) -> boolean", "Move a file or directory, across filesystems if needed";
+    "fs.touch", "fs.touch(path) -> boolean", "Create an empty file or update its modification time";
+    "fs.size", "fs.size(path) -> number", "Size of a file in bytes";
+    "fs.mkdir", "fs.mkdir(path) -> boolean", "Create a directory";
+    "fs.mkdir_all", "fs.mkdir_all(path) -> boolean", "Create a directory and all missing parents";
+    "fs.rmdir", "fs.rmdir(path) -> boolean", "Remove an empty directory";
+    "fs.rmdir_all", "fs.rmdir_all(path) -> boolean", "Remove a directory and its contents";
+    "fs.readdir", "fs.readdir(path) -> table", "List the entry names of a directory";
+    "fs.copy_dir", "fs.copy_dir(src, dest) -> boolean", "Recursively copy a directory";
+    "fs.stat", "fs.stat(path) -> { size, is_file, is_dir, is_symlink, readonly, modified, created, accessed }", "File metadata";
+    "fs.abs", "fs.abs(path) -> string", "Canonical absolute path";
+    "fs.join", "fs.join(...) -> string", "Join path segments";
+    "fs.basename", "fs.basename(path) -> string", "Last component of a path";
+    "fs.dirname", "fs.dirname(path) -> string", "Parent directory of a path";
+    "fs.ext", "fs.ext(path) -> string", "File extension without the dot";
+    "fs.glob", "fs.glob(pattern) -> table", "Paths matching a glob pattern";
+    "fs.cwd", "fs.cwd() -> string", "Current working directory";
+    "fs.temp_dir", "fs.temp_dir() -> string", "System temporary directory";
+
+    // ---- path ----
+    "path.join", "path.join(...) -> string", "Join path segments";
+    "path.dirname", "path.dirname(path) -> string | nil", "Parent directory of a path";
+    "path.basename", "path.basename(path) -> string | nil", "Last component of a path";
+    "path.extname", "path.extname(path) -> string | nil", "File extension without the dot";
+    "path.resolve", "path.resolve(path) -> string", "Absolute path relative to the current directory";
+    "path.normalize", "path.normalize(path) -> string", "Collapse `.` and `..` segments";
+    "path.is_absolute", "path.is_absolute(path) -> boolean", "Check whether a path is absolute";
+    "path.is_relative", "path.is_relative(path) -> boolean", "Check whether a path is relative";
+
+    // ---- os_ext ----
+    "os_ext.env", "os_ext.env(key) -> string | nil", "Read an environment variable";
+    "os_ext.setenv", "os_ext.setenv(key, value)", "Set an environment variable";
+    "os_ext.unsetenv", "os_ext.unsetenv(key)", "Remove an environment variable";
+    "os_ext.env_table", "os_ext.env_table() -> table", "Snapshot of the whole environment";
+    "os_ext.load_env", "os_ext.load_env(path?, { override = false }) -> table", "Load a .env file (default .env); returns the variables set";
+    "os_ext.cwd", "os_ext.cwd() -> string", "Current working directory";
+    "os_ext.chdir", "os_ext.chdir(path) -> boolean", "Change the current working directory";
+    "os_ext.platform", "os_ext.platform() -> string", "Operating system name (linux, macos, windows)";
+    "os_ext.arch", "os_ext.arch() -> string", "CPU architecture (x86_64, aarch64, ...)";
+    "os_ext.homedir", "os_ext.homedir() -> string", "Home directory of the current user";
+    "os_ext.tmpdir", "os_ext.tmpdir() -> string", "System temporary directory";
+    "os_ext.hostname", "os_ext.hostname() -> string", "Host name of the machine";
+    "os_ext.cpus", "os_ext.cpus() -> number", "Number of available CPU cores";
+
+    // ---- process ----
+    "process.exit", "process.exit(code)", "Exit the process";
+    "process.pid", "process.pid() -> number", "Current process id";
+    "process.exec", "process.exec(cmd) -> { stdout, stderr, status, success }", "Run a shell command and capture its output";
+    "process.spawn", "process.spawn(cmd, args) -> { stdout, stderr, status, success }", "Run a program with arguments and capture its output";
+
+    // ---- json ----
+    "json.encode", "json.encode(value) -> string", "Serialize a Lua value to JSON";
+    "json.decode", "json.decode(string) -> value", "Parse JSON into Lua values";
+    "json.pretty", "json.pretty(value) -> string", "Serialize a Lua value to indented JSON";
+
+    // ---- crypto ----
+    "crypto.sha256", "crypto.sha256(data) -> string", "SHA-256 digest as hex";
+    "crypto.sha1", "crypto.sha1(data) -> string", "SHA-1 digest as hex";
+    "crypto.md5", "crypto.md5(data) -> string", "MD5 digest as hex";
+    "crypto.hmac", "crypto.hmac(algo, key, data) -> string", "HMAC (sha256, sha1 or md5) as hex";
+    "crypto.random_bytes", "crypto.random_bytes(n) -> string", "n cryptographically secure random bytes";
+    "crypto.uuid", "crypto.uuid() -> string", "Random (v4) UUID";
+    "crypto.base64_encode", "crypto.base64_encode(data) -> string", "Base64-encode a string";
+    "crypto.base64_decode", "crypto.base64_decode(data) -> string", "Decode a Base64 string";
+    "crypto.hex_encode", "crypto.hex_encode(data) -> string", "Hex-encode a string";
+    "crypto.hex_decode", "crypto.hex_decode(data) -> string", "Decode a hex string";
+
+    // ---- time ----
+    "time.sleep", "time.sleep(ms)", "Block for ms milliseconds";
+    "time.now", "time.now() -> number", "Unix timestamp in seconds (fractional)";
+    "time.now_ms", "time.now_ms() -> number", "Unix timestamp in milliseconds";
+    "time.monotonic", "time.monotonic() -> number", "Monotonic clock in seconds, for measuring durations";
+    "time.monotonic_ms", "time.monotonic_ms() -> number", "Monotonic clock in milliseconds";
+    "time.format", "time.format(timestamp, format) -> string", "Format a timestamp with strftime syntax";
+    "time.parse", "time.parse(str, format) -> number", "Parse a time string into a timestamp";
+    "time.date", "time.date(...) -> DateTime", "Local date/time from now, a timestamp, a string or fields";
+    "time.utc", "time.utc(...) -> DateTime", "UTC date/time from now, a timestamp, a string or fields";
+    "time.isLeapYear", "time.isLeapYear(year) -> boolean", "Check whether a year is a leap year";
+    "time.daysInMonth", "time.daysInMonth(year, month) -> number", "Number of days in a month";
+
+    // ---- http ----
+    "http.get", "http.get(url, options?) -> response", "Send a GET request";
+    "http.post", "http.post(url, body, options?) -> response", "Send a POST request";
+    "http.put", "http.put(url, body, options?) -> response", "Send a PUT request";
+    "http.delete", "http.delete(url, options?) -> response", "Send a DELETE request";
+    "http.patch", "http.patch(url, body, options?) -> response", "Send a PATCH request";
+    "http.request", "http.request(options) -> response", "Send a request described by { method, url, body, headers, timeout }";
+    "http.create_session", "http.create_session() -> session", "HTTP client session with a cookie jar";
+    "http.server.new", "http.server.new() -> server", "Create an HTTP server; register routes with server:get(path, fn) etc.";
+
+    // ---- net ----
+    "net.tcp.connect", "net.tcp.connect(host, port) -> connection", "Open a TCP connection";
+    "net.tcp.listen", "net.tcp.listen(host?, port) -> server", "Listen for TCP connections";
+    "net.udp.bind", "net.udp.bind(host?, port) -> socket", "Bind a UDP socket";
+    "net.resolve", "net.resolve(hostname) -> table", "Resolve a host name to IP addresses";
+    "net.ws.connect", "net.ws.connect(url, options?) -> connection", "Open a WebSocket client connection";
+    "net.ws.listen", "net.ws.listen(host?, port) -> server", "Listen for WebSocket connections";
+
+    // ---- runtime ----
+    "runtime.memory", "runtime.memory() -> table", "Lua heap bytes, registry entries, live timers and loaded native libraries";
+    "runtime.heap_snapshot", "runtime.heap_snapshot(path) -> table", "Write a JSON graph of reachable objects with retained sizes";
+    "runtime.heap_diff", "runtime.heap_diff(before, after, limit?) -> table", "Per-type changes and the largest new objects between two snapshots";
+    "runtime.gc.collect", "runtime.gc.collect() -> bytes", "Run a full collection, returning the bytes freed";
+    "runtime.gc.step", "runtime.gc.step(kbytes?) -> boolean", "Run one GC step; true if it finished a cycle";
+    "runtime.gc.stop", "runtime.gc.stop()", "Stop the garbage collector";
+    "runtime.gc.restart", "runtime.gc.restart()", "Restart the garbage collector";
+    "runtime.gc.count", "runtime.gc.count() -> bytes", "Bytes allocated by the Lua VM";
+    "runtime.gc.pause", "runtime.gc.pause(percent)", "Heap growth (percent) to wait for before starting a cycle";
+    "runtime.gc.mode", "runtime.gc.mode(mode, options?) -> previous", "Switch between incremental and generational collection";
+
+    // ---- buffer ----
+    "buffer.new", "buffer.new(size) -> Buffer", "Zero-filled buffer of the given size";
+    "buffer.from", "buffer.from(string) -> Buffer", "Buffer holding a copy of a string's bytes";
+    "buffer.fromHex", "buffer.fromHex(hex) -> Buffer", "Buffer from a hex string";
+    "buffer.fromBase64", "buffer.fromBase64(data) -> Buffer", "Buffer from a Base64 string";
+    "buffer.alloc", "buffer.alloc(size, fill?) -> Buffer", "Buffer filled with a byte value";
+    "buffer.concat", "buffer.concat(...) -> Buffer", "Concatenate Buffers and strings";
+    "buffer.isBuffer", "buffer.isBuffer(value) -> boolean", "Check whether a value is a Buffer";
+
+    // ---- term ----
+    "term.rgb", "term.rgb(r, g, b) -> string", "ANSI escape for a 24-bit foreground color";
+    "term.bg_rgb", "term.bg_rgb(r, g, b) -> string", "ANSI escape for a 24-bit background color";
+    "term.strip", "term.strip(text) -> string", "Remove ANSI escape sequences";
+    "term.clear", "term.clear()", "Clear the screen";
+    "term.size", "term.size() -> cols, rows", "Terminal size";
+    "term.is_tty", "term.is_tty() -> boolean", "Check whether stdout is a terminal";
+    "term.cursor_to", "term.cursor_to(x, y)", "Move the cursor";
+
+    // ---- console ----
+    "console.prompt", "console.prompt(message, default?) -> string", "Ask for a line of input";
+    "console.password", "console.password(message) -> string", "Ask for input without echo";
+    "console.confirm", "console.confirm(message, default?) -> boolean", "Ask a yes/no question";
+    "console.select", "console.select(message, options) -> index, value", "Pick one option from a list";
+    "console.multiselect", "console.multiselect(message, options) -> indices, values", "Pick several options from a list";
+
+    // ---- archive ----
+    "archive.zip.open", "archive.zip.open(path) -> reader", "Open a zip archive for reading";
+    "archive.zip.create", "archive.zip.create(path) -> writer", "Create a zip archive";
+    "archive.tar.open", "archive.tar.open(path) -> reader", "Open a tar or tar.gz archive for reading";
+    "archive.tar.create", "archive.tar.create(path) -> writer", "Create a tar or tar.gz archive";
+    "archive.gzip.compress", "archive.gzip.compress(data, options?) -> string", "Gzip-compress a string or Buffer";
+    "archive.gzip.decompress", "archive.gzip.decompress(data) -> string", "Decompress gzip data";
+
+    // ---- re ----
+    "re.compile", "re.compile(pattern, flags?) -> Pattern", "Compile a regular expression";
+    "re.test", "re.test(pattern, text, flags?) -> boolean", "Check whether a pattern matches";
+    "re.match", "re.match(pattern, text, flags?) -> table | nil", "First match with capture groups";
+    "re.find", "re.find(pattern, text, flags?) -> table | nil", "Alias for re.match";
+    "re.findAll", "re.findAll(pattern, text, flags?) -> table", "All matches";
+    "re.replace", "re.replace(pattern, text, replacement, flags?) -> string", "Replace the first match";
+    "re.replaceAll", "re.replaceAll(pattern, text, replacement, flags?) -> string", "Replace every match";
+    "re.split", "re.split(pattern, text, flags?) -> table", "Split text on a pattern";
+    "re.escape", "re.escape(text) -> string", "Escape regex metacharacters";
+
+    // ---- string extensions ----
+    "string.split", "string.split(s, sep) -> table", "Split a string on a separator";
+    "string.trim", "string.trim(s) -> string", "Remove leading and trailing whitespace";
+    "string.ltrim", "string.ltrim(s) -> string", "Remove leading whitespace";
+    "string.rtrim", "string.rtrim(s) -> string", "Remove trailing whitespace";
+    "string.starts_with", "string.starts_with(s, prefix) -> boolean", "Check a prefix";
+    "string.ends_with", "string.ends_with(s, suffix) -> boolean", "Check a suffix";
+    "string.contains", "string.contains(s, substr) -> boolean", "Check for a substring";
+    "string.pad_left", "string.pad_left(s, width, fill?) -> string", "Left-pad to a width";
+    "string.pad_right", "string.pad_right(s, width, fill?) -> string", "Right-pad to a width";
+    "string.pad_center", "string.pad_center(s, width, fill?) -> string", "Center within a width";
+    "string.truncate", "string.truncate(s, max_len, suffix?) -> string", "Shorten with a suffix such as ...";
+    "string.lines", "string.lines(s) -> table", "Split into lines";
+    "string.chars", "string.chars(s) -> table", "Split into characters";
+    "string.replace_all", "string.replace_all(s, old, new) -> string", "Replace every occurrence (plain text)";
+    "string.count", "string.count(s, substr) -> number", "Count occurrences of a substring";
+    "string.slug", "string.slug(s) -> string", "URL-friendly slug";
+
+    // ---- table extensions ----
+    "table.keys", "table.keys(t) -> table", "Array of keys";
+    "table.values", "table.values(t) -> table", "Array of values";
+    "table.merge", "table.merge(...) -> table", "Shallow merge of several tables";
+    "table.map", "table.map(t, fn) -> table", "Apply fn(value, key) to every element";
+    "table.filter", "table.filter(t, fn) -> table", "Elements for which fn(value, key) is true";
+    "table.find", "table.find(t, fn) -> value", "First element for which fn(value, key) is true";
+    "table.reduce", "table.reduce(t, fn, init?) -> value", "Fold elements with fn(acc, value)";
+    "table.contains", "table.contains(t, value) -> boolean", "Check whether a value is present";
+    "table.slice", "table.slice(t, from, to?) -> table", "Sub-array (1-based, inclusive)";
+    "table.reverse", "table.reverse(t) -> table", "Reversed copy of an array";
+    "table.count", "table.count(t) -> number", "Number of entries, including non-array keys";
+    "table.clone", "table.clone(t) -> table", "Shallow copy";
+    "table.is_empty", "table.is_empty(t) -> boolean", "Check whether a table has no entries";
+    "table.flat", "table.flat(t, depth?) -> table", "Flatten nested arrays";
+    "table.freeze", "table.freeze(t) -> table", "Read-only proxy of a table";
+    "table.is_frozen", "table.is_frozen(t) -> boolean", "Check whether a table is frozen";
+};
+
+/// Look up a function by its Lua name (e.g. `fs.read`).
+pub fn lookup(name: &str) -> Option<&'static FunctionDoc> {
+    FUNCTIONS.iter().find(|doc| doc.name == name)
+}
+
+/// All functions of a module (e.g. `fs` or `net.tcp`).
+pub fn module(name: &str) -> Vec<&'static FunctionDoc> {
+    let prefix = format!("{}.", name);
+    FUNCTIONS
+        .iter()
+        .filter(|doc| doc.name.starts_with(&prefix))
+        .collect()
+}
@@ -0,0 +1,322 @@
+//! `.env` file parsing
+//!
+//! Supports `KEY=value` lines with an optional `export` prefix, `#`
+//! comments, single-quoted (literal) and double-quoted (escaped) values that
+//! may span several lines, and `${VAR}` / `$VAR` / `${VAR:-default}`
+//! expansion in double-quoted and unquoted values. A variable expands to
+//! the value defined earlier in the same file, else to the environment.
+//!
+//! Used by `os_ext.load_env` and the CLI's `--env-file`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// A syntax error in a `.env` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// 1-based line where the offending entry starts
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Parse `.env` source into `(key, value)` pairs in file order.
+/// `lookup` resolves variables not defined earlier in the file.
+pub fn parse(src: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<Vec<(String, String)>, ParseError> {
+    let mut parser = Parser { chars: src.chars().collect(), pos: 0, line: 1 };
+    let mut entries: Vec<(String, String)> = Vec::new();
+    let mut defined: HashMap<String, String> = HashMap::new();
+
+    loop {
+        parser.skip_blank_lines();
+        if parser.at_end() {
+            break;
+        }
+
+        let line = parser.line;
+        let err = |message: String| ParseError { line, message };
+
+        let mut key = parser.take_key();
+        if key == "export" && parser.peek().is_some_and(|c| c == ' ' || c == '\t') {
+            parser.skip_spaces();
+            key = parser.take_key();
+        }
+        if key.is_empty() {
+            let found = parser.peek().map_or("end of file".to_string(), |c| format!("'{}'", c));
+            return Err(err(format!("expected a variable name, found {}", found)));
+        }
+
+        parser.skip_spaces();
+        if parser.peek() != Some('=') {
+            return Err(err(format!("expected '=' after '{}'", key)));
+        }
+        parser.pos += 1;
+        parser.skip_spaces();
+
+        let resolve = |name: &str| defined.get(name).cloned().or_else(|| lookup(name));
+        let value = match parser.peek() {
+            Some('\'') => parser.single_quoted().ok_or_else(|| err("unterminated single-quoted value".into()))?,
+            Some('"') => {
+                let raw = parser.double_quoted().ok_or_else(|| err("unterminated double-quoted value".into()))?;
+                expand(&raw, &resolve).map_err(err)?
+            }
+            _ => expand(&parser.unquoted(), &resolve).map_err(err)?,
+        };
+
+        // Only a comment may follow a quoted value
+        parser.skip_spaces();
+        match parser.peek() {
+            None | Some('\n') | Some('\r') => {}
+            Some('#') => parser.skip_line(),
+            Some(c) => return Err(err(format!("unexpected '{}' after value of '{}'", c, key))),
+        }
+
+        defined.insert(key.clone(), value.clone());
+        entries.push((key, value));
+    }
+
+    Ok(entries)
+}
+
+/// Read and parse a `.env` file, expanding variables from the environment.
+/// Errors are formatted as `path:line: message`.
+pub fn parse_file(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let src = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    parse(&src, |name| std::env::var(name).ok())
+        .map_err(|e| format!("{}:{}: {}", path.display(), e.line, e.message))
+}
+
+/// Load a `.env` file into the process environment. Variables that are
+/// already set are kept unless `override_existing` is true. Returns the
+/// variables that were set.
+///
+/// Setting variables is only sound while no other thread reads the
+/// environment; once servers or background tasks have started, a
+/// concurrent `getenv` (DNS resolution, TLS setup, ...) may see freed
+/// memory. Call this at startup, before anything spawns threads.
+pub fn load(path: &Path, override_existing: bool) -> Result<Vec<(String, String)>, String> {
+    let mut applied = Vec::new();
+    for (key, value) in parse_file(path)? {
+        if override_existing || std::env::var_os(&key).is_none() {
+            // Unsound if another thread reads the environment now; see the doc comment
+            unsafe {
+                std::env::set_var(&key, &value);
+            }
+            applied.push((key, value));
+        }
+    }
+    Ok(applied)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn at_end(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.bump() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    /// Skip empty lines, whitespace and comment lines.
+    fn skip_blank_lines(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\r') | Some('\n') => {
+                    self.bump();
+                }
+                Some('#') => self.skip_line(),
+                _ => break,
+            }
+        }
+    }
+
+    fn take_key(&mut self) -> String {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            let valid = if self.pos == start {
+                c.is_ascii_alphabetic() || c == '_'
+            } else {
+                c.is_ascii_alphanumeric() || c == '_' || c == '.'
+            };
+            if !valid {
+                break;
+            }
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// `'...'`: taken literally, may span lines.
+    fn single_quoted(&mut self) -> Option<String> {
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.bump()? {
+                '\'' => return Some(value),
+                c => value.push(c),
+            }
+        }
+    }
+
+    /// `"..."`: escapes are decoded, `\$` is kept escaped for [`expand`].
+    fn double_quoted(&mut self) -> Option<String> {
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.bump()? {
+                '"' => return Some(value),
+                '\\' => match self.bump()? {
+                    'n' => value.push('\n'),
+                    'r' => value.push('\r'),
+                    't' => value.push('\t'),
+                    '$' => value.push_str("\\$"),
+                    c => value.push(c),
+                },
+                c => value.push(c),
+            }
+        }
+    }
+
+    /// Unquoted: up to the end of the line or a ` #` comment, trimmed.
+    fn unquoted(&mut self) -> String {
+        let mut value = String::new();
+        while let Some(c) = self.peek() {
+            if c == '\n' || (c == '#' && value.ends_with(|c: char| c == ' ' || c == '\t')) {
+                break;
+            }
+            value.push(c);
+            self.pos += 1;
+        }
+        value.trim().to_string()
+    }
+}
+
+/// Expand `${VAR}`, `${VAR:-default}` and `$VAR`; `\$` is a literal `$`.
+fn expand(raw: &str, resolve: &impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let chars: Vec<char> = raw.chars().collect();
+    let mut out = String::with_capacity(raw.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if chars.get(i + 1) == Some(&'$') => {
+                out.push('$');
+                i += 2;
+            }
+            '$' if chars.get(i + 1) == Some(&'{') => {
+                let close = chars[i + 2..]
+                    .iter()
+                    .position(|&c| c == '}')
+                    .ok_or_else(|| "unterminated '${' in value".to_string())?;
+                let inner: String = chars[i + 2..i + 2 + close].iter().collect();
+                let (name, default) = match inner.split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (inner.as_str(), None),
+                };
+                let value = resolve(name).filter(|v| !v.is_empty() || default.is_none());
+                out.push_str(&value.or(default.map(str::to_string)).unwrap_or_default());
+                i += close + 3;
+            }
+            '$' if chars.get(i + 1).is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') => {
+                let start = i + 1;
+                let mut end = start;
+                while chars.get(end).is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    end += 1;
+                }
+                let name: String = chars[start..end].iter().collect();
+                out.push_str(&resolve(&name).unwrap_or_default());
+                i = end;
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_map(src: &str) -> Vec<(String, String)> {
+        parse(src, |name| (name == "HOME").then(|| "/home/me".to_string())).unwrap()
+    }
+
+    fn pair(k: &str, v: &str) -> (String, String) {
+        (k.to_string(), v.to_string())
+    }
+
+    #[test]
+    fn test_values_and_quoting() {
+        let src = "# comment\nexport A=1\nB = two words # note\nC='lit ${A}'\nD=\"tab\\there\"\nE=\nF=a#b\n";
+        assert_eq!(parse_map(src), vec![
+            pair("A", "1"),
+            pair("B", "two words"),
+            pair("C", "lit ${A}"),
+            pair("D", "tab\there"),
+            pair("E", ""),
+            pair("F", "a#b"),
+        ]);
+    }
+
+    #[test]
+    fn test_multiline_and_expansion() {
+        let src = "KEY=\"line1\nline2\"\nDIR=${HOME}/app\nURL=\"$DIR/x \\$HOME\"\nX=${MISSING:-fallback}\n";
+        assert_eq!(parse_map(src), vec![
+            pair("KEY", "line1\nline2"),
+            pair("DIR", "/home/me/app"),
+            pair("URL", "/home/me/app/x $HOME"),
+            pair("X", "fallback"),
+        ]);
+    }
+
+    #[test]
+    fn test_errors_have_line_numbers() {
+        let err = parse("A=1\n\nB=\"open\nC=3", |_| None).unwrap_err();
+        assert_eq!(err.line, 3);
+
+        let err = parse("A=1\nnot valid", |_| None).unwrap_err();
+        assert_eq!(err, ParseError { line: 2, message: "expected '=' after 'not'".into() });
+
+        let err = parse("A='x' y", |_| None).unwrap_err();
+        assert_eq!(err.line, 1);
+    }
+}
@@ -3,9 +3,13 @@
 //! Provides file and directory operations backed by Tokio's async I/O.
 //! From Lua's perspective the API is synchronous; under the hood each
 //! operation runs on the Tokio runtime via `block_on`.
+//!
+//! In standalone executables the read-only calls (`read`, `read_bytes`,
+//! `exists`, `is_file`, `is_dir`, `readdir`, `size`, `stat`) fall back to the
+//! assets embedded in the binary when a path does not exist on disk.
 
 use crate::buffer::Buffer;
-use coppermoon_core::Result;
+use coppermoon_core::{Result, Vfs};
 use mlua::{Lua, MultiValue, Table, Value};
 use std::path::Path;
 
@@ -71,12 +75,19 @@ pub fn register(lua: &Lua) -> Result<Table> {
 // Read / Write
 // ---------------------------------------------------------------------------
 
-fn fs_read(_: &Lua, path: String) -> mlua::Result<String> {
+fn fs_read(lua: &Lua, path: String) -> mlua::Result<String> {
+    if let Some(data) = embedded_file(lua, &path) {
+        return String::from_utf8(data)
+            .map_err(|e| mlua::Error::runtime(format!("Failed to read file '{}': {}", path, e)));
+    }
     block_on(tokio::fs::read_to_string(&path))
         .map_err(|e| mlua::Error::runtime(format!("Failed to read file '{}': {}", path, e)))
 }
 
-fn fs_read_bytes(_: &Lua, path: String) -> mlua::Result<Buffer> {
+fn fs_read_bytes(lua: &Lua, path: String) -> mlua::Result<Buffer> {
+    if let Some(data) = embedded_file(lua, &path) {
+        return Ok(Buffer::from_bytes(data));
+    }
     let data = block_on(tokio::fs::read(&path))
         .map_err(|e| mlua::Error::runtime(format!("Failed to read file '{}': {}", path, e)))?;
     Ok(Buffer::from_bytes(data))
@@ -117,16 +128,17 @@ fn fs_append(_: &Lua, (path, content): (String, String)) -> mlua::Result<bool> {
 // Existence / type checks  (cheap sync Path checks — no I/O benefit from async)
 // ---------------------------------------------------------------------------
 
-fn fs_exists(_: &Lua, path: String) -> mlua::Result<bool> {
-    Ok(Path::new(&path).exists())
+fn fs_exists(lua: &Lua, path: String) -> mlua::Result<bool> {
+    Ok(Path::new(&path).exists()
+        || with_embedded(lua, |vfs| vfs.is_file(&path) || vfs.is_dir(&path)).unwrap_or(false))
 }
 
-fn fs_is_file(_: &Lua, path: String) -> mlua::Result<bool> {
-    Ok(Path::new(&path).is_file())
+fn fs_is_file(lua: &Lua, path: String) -> mlua::Result<bool> {
+    Ok(Path::new(&path).is_file() || with_embedded(lua, |vfs| vfs.is_file(&path)).unwrap_or(false))
 }
 
-fn fs_is_dir(_: &Lua, path: String) -> mlua::Result<bool> {
-    Ok(Path::new(&path).is_dir())
+fn fs_is_dir(lua: &Lua, path: String) -> mlua::Result<bool> {
+    Ok(Path::new(&path).is_dir() || with_embedded(lua, |vfs| vfs.is_dir(&path)).unwrap_or(false))
 }
 
 fn fs_is_symlink(_: &Lua, path: String) -> mlua::Result<bool> {
@@ -218,7 +230,10 @@ fn fs_touch(_: &Lua, path: String) -> mlua::Result<bool> {
     })
 }
 
-fn fs_size(_: &Lua, path: String) -> mlua::Result<u64> {
+fn fs_size(lua: &Lua, path: String) -> mlua::Result<u64> {
+    if let Some(data) = embedded_file(lua, &path) {
+        return Ok(data.len() as u64);
+    }
     let metadata = block_on(tokio::fs::metadata(&path))
         .map_err(|e| mlua::Error::runtime(format!("Failed to get size of '{}': {}", path, e)))?;
     Ok(metadata.len())
@@ -253,6 +268,16 @@ fn fs_rmdir_all(_: &Lua, path: String) -> mlua::Result<bool> {
 }
 
 fn fs_readdir(lua: &Lua, path: String) -> mlua::Result<Table> {
+    if !Path::new(&path).exists() {
+        if let Some(names) = with_embedded(lua, |vfs| vfs.read_dir(&path)).flatten() {
+            let result = lua.create_table()?;
+            for (i, name) in names.into_iter().enumerate() {
+                result.set(i + 1, name)?;
+            }
+            return Ok(result);
+        }
+    }
+
     block_on(async {
         let mut entries = tokio::fs::read_dir(&path)
             .await
@@ -288,6 +313,23 @@ fn fs_copy_dir(_: &Lua, (src, dest): (String, String)) -> mlua::Result<bool> {
 // ---------------------------------------------------------------------------
 
 fn fs_stat(lua: &Lua, path: String) -> mlua::Result<Table> {
+    if !Path::new(&path).exists() {
+        let embedded = with_embedded(lua, |vfs| {
+            (vfs.get(&path).map(|d| d.len() as u64), vfs.is_dir(&path))
+        });
+        if let Some((size, is_dir)) = embedded {
+            if size.is_some() || is_dir {
+                let result = lua.create_table()?;
+                result.set("size", size.unwrap_or(0))?;
+                result.set("is_file", size.is_some())?;
+                result.set("is_dir", is_dir)?;
+                result.set("readonly", true)?;
+                result.set("is_symlink", false)?;
+                return Ok(result);
+            }
+        }
+    }
+
     block_on(async {
         let metadata = tokio::fs::metadata(&path)
             .await
@@ -421,6 +463,20 @@ fn fs_temp_dir(_: &Lua, _: ()) -> mlua::Result<String> {
 // Helpers
 // ---------------------------------------------------------------------------
 
+/// Run `f` against the embedded payload of a standalone executable, if any.
+fn with_embedded<T>(lua: &Lua, f: impl FnOnce(&Vfs) -> T) -> Option<T> {
+    lua.app_data_ref::<Vfs>().map(|vfs| f(&vfs))
+}
+
+/// Read a file from the embedded payload when it does not exist on disk.
+/// Files on disk take precedence so scripts can still read what they write.
+fn embedded_file(lua: &Lua, path: &str) -> Option<Vec<u8>> {
+    if Path::new(path).exists() {
+        return None;
+    }
+    with_embedded(lua, |vfs| vfs.get(path).map(|d| d.to_vec())).flatten()
+}
+
 /// Extract bytes from a Lua string or Buffer value.
 fn extract_bytes(value: Value) -> mlua::Result<Vec<u8>> {
     match &value {
@@ -0,0 +1,568 @@
+//! Lua heap snapshots
+//!
+//! [`snapshot`] walks every object reachable from the globals table and the
+//! registry (tables, functions and their upvalues, userdata and their
+//! metatables and user values, coroutines and their stacks, strings) and
+//! builds an object graph. Sizes are estimates of the VM's internal object
+//! sizes, not exact allocations; the exact total is in `memory`.
+//!
+//! Each node records its retained size: the bytes that would be freed if
+//! it became unreachable, computed from the graph's dominator tree. Object
+//! addresses are stable for the life of the process, so [`diff`] can match
+//! nodes across two snapshots taken by the same process.
+//!
+//! Used by `runtime.heap_snapshot` and `runtime.heap_diff`.
+
+use mlua::ffi;
+use mlua::Lua;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{c_int, c_void, CStr};
+
+/// Snapshot format version
+pub const VERSION: u32 = 1;
+
+// Approximate object sizes on a 64-bit Lua 5.4
+const TABLE_SIZE: usize = 56;
+const TABLE_ENTRY_SIZE: usize = 32;
+const FUNCTION_SIZE: usize = 48;
+const UPVALUE_SIZE: usize = 40;
+const USERDATA_SIZE: usize = 40;
+const THREAD_SIZE: usize = 200;
+const STRING_SIZE: usize = 25;
+
+/// Longest object path recorded; deeper paths end in "..."
+const MAX_PATH: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Root,
+    Table,
+    Function,
+    Userdata,
+    Thread,
+    String,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Root => "root",
+            Kind::Table => "table",
+            Kind::Function => "function",
+            Kind::Userdata => "userdata",
+            Kind::Thread => "thread",
+            Kind::String => "string",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// One object in a snapshot. Node 0 is the virtual root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Node {
+    pub id: usize,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Estimated size of the object itself
+    pub size: usize,
+    /// Size of everything only reachable through this object, itself included
+    pub retained: usize,
+    /// Node through which every path from the roots passes (its immediate dominator)
+    pub dominator: usize,
+    /// First path found from the roots, e.g. `_G.app.cache[3]`
+    pub path: String,
+    pub address: String,
+}
+
+/// A reference from one object to another.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    /// Table key, `<upvalue name>`, `<metatable>`, `<key>`, `<uservalue n>` or `<stack n>`
+    pub name: String,
+}
+
+/// Totals for one object type.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TypeSummary {
+    pub count: usize,
+    pub size: usize,
+    /// Bytes retained by objects of this type, each byte counted once
+    pub retained: usize,
+}
+
+/// A heap snapshot, serialized as the JSON written by `runtime.heap_snapshot`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub vm: String,
+    /// Exact bytes allocated by the Lua VM when the snapshot was taken
+    pub memory: usize,
+    pub summary: BTreeMap<String, TypeSummary>,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+struct RawNode {
+    kind: Kind,
+    size: usize,
+    path: String,
+    address: usize,
+}
+
+struct Walker {
+    state: *mut ffi::lua_State,
+    /// Absolute stack index of the table holding queued objects
+    work: c_int,
+    work_ptr: *const c_void,
+    /// Node id of each entry in the work table, in queue order
+    queue: Vec<usize>,
+    nodes: Vec<RawNode>,
+    edges: Vec<(usize, usize, String)>,
+    ids: HashMap<*const c_void, usize>,
+}
+
+/// Take a snapshot of the heap reachable from the globals and the registry.
+pub fn snapshot(lua: &Lua) -> mlua::Result<Snapshot> {
+    let memory = lua.used_memory();
+    let mut walked = None;
+
+    // Safety: the walk only reads objects and keeps the stack balanced;
+    // queued objects stay anchored in the work table while they are scanned.
+    unsafe {
+        lua.exec_raw::<()>(lua.globals(), |state| {
+            let globals = ffi::lua_gettop(state);
+            ffi::lua_checkstack(state, 16);
+            ffi::lua_createtable(state, 0, 0);
+            let work = ffi::lua_gettop(state);
+            let mut walker = Walker {
+                state,
+                work,
+                work_ptr: ffi::lua_topointer(state, work),
+                queue: Vec::new(),
+                nodes: vec![RawNode { kind: Kind::Root, size: 0, path: String::new(), address: 0 }],
+                edges: Vec::new(),
+                ids: HashMap::new(),
+            };
+            walker.edge(0, globals, "_G".to_string());
+            walker.edge(0, ffi::LUA_REGISTRYINDEX, "registry".to_string());
+            walker.run();
+            ffi::lua_settop(state, globals - 1);
+            walked = Some((walker.nodes, walker.edges));
+        })?;
+    }
+
+    let (nodes, edges) = walked.expect("heap walk did not run");
+    Ok(build(nodes, edges, memory))
+}
+
+/// Number of entries in the registry table.
+pub fn registry_size(lua: &Lua) -> mlua::Result<usize> {
+    let mut count = 0;
+    // Safety: a raw traversal that leaves the stack as it found it
+    unsafe {
+        lua.exec_raw::<()>((), |state| {
+            ffi::lua_checkstack(state, 3);
+            ffi::lua_pushnil(state);
+            while ffi::lua_next(state, ffi::LUA_REGISTRYINDEX) != 0 {
+                count += 1;
+                ffi::lua_pop(state, 1);
+            }
+        })?;
+    }
+    Ok(count)
+}
+
+impl Walker {
+    /// Record a reference from `from` to the value at `idx`, queueing the
+    /// value for scanning the first time it is seen.
+    unsafe fn edge(&mut self, from: usize, idx: c_int, name: String) {
+        let l = self.state;
+        let kind = match ffi::lua_type(l, idx) {
+            ffi::LUA_TTABLE => Kind::Table,
+            ffi::LUA_TFUNCTION => Kind::Function,
+            ffi::LUA_TUSERDATA => Kind::Userdata,
+            ffi::LUA_TTHREAD => Kind::Thread,
+            ffi::LUA_TSTRING => Kind::String,
+            _ => return,
+        };
+
+        let mut len = 0;
+        let ptr = if kind == Kind::String {
+            // Identify strings by their (interned) data, which every VM exposes
+            ffi::lua_tolstring(l, idx, &mut len) as *const c_void
+        } else {
+            ffi::lua_topointer(l, idx)
+        };
+        if ptr.is_null() || ptr == self.work_ptr {
+            return;
+        }
+
+        let to = match self.ids.get(&ptr) {
+            Some(&id) => id,
+            None => {
+                let id = self.nodes.len();
+                let path = join_path(&self.nodes[from].path, &name);
+                let size = if kind == Kind::String { STRING_SIZE + len } else { 0 };
+                self.nodes.push(RawNode { kind, size, path, address: ptr as usize });
+                self.ids.insert(ptr, id);
+                if kind != Kind::String {
+                    ffi::lua_pushvalue(l, idx);
+                    self.queue.push(id);
+                    ffi::lua_rawseti(l, self.work, self.queue.len() as _);
+                }
+                id
+            }
+        };
+        self.edges.push((from, to, name));
+    }
+
+    /// Scan queued objects until the queue is exhausted.
+    unsafe fn run(&mut self) {
+        let mut next = 0;
+        while next < self.queue.len() {
+            let id = self.queue[next];
+            next += 1;
+            ffi::lua_rawgeti(self.state, self.work, next as _);
+            self.scan(id);
+            ffi::lua_pop(self.state, 1);
+        }
+    }
+
+    /// Record the references held by the object on top of the stack.
+    unsafe fn scan(&mut self, id: usize) {
+        let l = self.state;
+        let obj = ffi::lua_gettop(l);
+        match self.nodes[id].kind {
+            Kind::Table => {
+                let mut entries = 0;
+                ffi::lua_pushnil(l);
+                while ffi::lua_next(l, obj) != 0 {
+                    entries += 1;
+                    let name = key_name(l, -2);
+                    // String keys are names, not references worth tracking
+                    if ffi::lua_type(l, -2) != ffi::LUA_TSTRING {
+                        self.edge(id, -2, "<key>".to_string());
+                    }
+                    self.edge(id, -1, name);
+                    ffi::lua_pop(l, 1);
+                }
+                self.nodes[id].size = TABLE_SIZE + entries * TABLE_ENTRY_SIZE;
+                self.metatable(id, obj);
+            }
+            Kind::Function => {
+                let mut n = 1;
+                loop {
+                    let name = ffi::lua_getupvalue(l, obj, n);
+                    if name.is_null() {
+                        break;
+                    }
+                    let name = CStr::from_ptr(name).to_string_lossy();
+                    let name = if name.is_empty() {
+                        format!("<upvalue {}>", n)
+                    } else {
+                        format!("<upvalue {}>", name)
+                    };
+                    self.edge(id, -1, name);
+                    ffi::lua_pop(l, 1);
+                    n += 1;
+                }
+                self.nodes[id].size = FUNCTION_SIZE + (n as usize - 1) * UPVALUE_SIZE;
+            }
+            Kind::Userdata => {
+                self.nodes[id].size = USERDATA_SIZE + ffi::lua_rawlen(l, obj) as usize;
+                self.metatable(id, obj);
+                #[cfg(feature = "lua54")]
+                {
+                    let mut n = 1;
+                    while ffi::lua_getiuservalue(l, obj, n) != ffi::LUA_TNONE {
+                        self.edge(id, -1, format!("<uservalue {}>", n));
+                        ffi::lua_pop(l, 1);
+                        n += 1;
+                    }
+                    ffi::lua_pop(l, 1);
+                }
+            }
+            Kind::Thread => {
+                self.nodes[id].size = THREAD_SIZE;
+                let co = ffi::lua_tothread(l, obj);
+                // The running thread's stack holds this walk, not script values
+                if !co.is_null() && co != l {
+                    for i in 1..=ffi::lua_gettop(co) {
+                        if ffi::lua_checkstack(co, 1) == 0 {
+                            break;
+                        }
+                        ffi::lua_pushvalue(co, i);
+                        ffi::lua_xmove(co, l, 1);
+                        self.edge(id, -1, format!("<stack {}>", i));
+                        ffi::lua_pop(l, 1);
+                    }
+                }
+            }
+            Kind::Root | Kind::String => {}
+        }
+    }
+
+    unsafe fn metatable(&mut self, id: usize, obj: c_int) {
+        if ffi::lua_getmetatable(self.state, obj) != 0 {
+            self.edge(id, -1, "<metatable>".to_string());
+            ffi::lua_pop(self.state, 1);
+        }
+    }
+}
+
+/// Edge name for a table key: the string itself, `[n]` for numbers and
+/// `[type]` for anything else. Never converts the key in place.
+unsafe fn key_name(l: *mut ffi::lua_State, idx: c_int) -> String {
+    match ffi::lua_type(l, idx) {
+        ffi::LUA_TSTRING => {
+            let mut len = 0;
+            let ptr = ffi::lua_tolstring(l, idx, &mut len) as *const u8;
+            let bytes = std::slice::from_raw_parts(ptr, len);
+            String::from_utf8_lossy(&bytes[..len.min(64)]).into_owned()
+        }
+        ffi::LUA_TNUMBER => {
+            let n = ffi::lua_tonumberx(l, idx, std::ptr::null_mut());
+            if n.fract() == 0.0 {
+                format!("[{}]", n as i64)
+            } else {
+                format!("[{}]", n)
+            }
+        }
+        ffi::LUA_TBOOLEAN => format!("[{}]", ffi::lua_toboolean(l, idx) != 0),
+        t => format!("[{}]", CStr::from_ptr(ffi::lua_typename(l, t)).to_string_lossy()),
+    }
+}
+
+fn join_path(parent: &str, name: &str) -> String {
+    if parent.len() >= MAX_PATH {
+        return parent.to_string();
+    }
+    let mut path = if parent.is_empty() {
+        name.to_string()
+    } else if name.starts_with('[') {
+        format!("{}{}", parent, name)
+    } else {
+        format!("{}.{}", parent, name)
+    };
+    if path.len() >= MAX_PATH {
+        let mut end = MAX_PATH;
+        while !path.is_char_boundary(end) {
+            end -= 1;
+        }
+        path.truncate(end);
+        path.push_str("...");
+    }
+    path
+}
+
+/// Compute dominators and retained sizes and assemble the snapshot.
+fn build(raw: Vec<RawNode>, raw_edges: Vec<(usize, usize, String)>, memory: usize) -> Snapshot {
+    let n = raw.len();
+    let mut succ = vec![Vec::new(); n];
+    let mut preds = vec![Vec::new(); n];
+    for &(from, to, _) in &raw_edges {
+        succ[from].push(to);
+        preds[to].push(from);
+    }
+
+    // Reverse postorder from the root (every node is reachable from it)
+    let mut order = Vec::with_capacity(n);
+    let mut visited = vec![false; n];
+    let mut stack = vec![(0usize, 0usize)];
+    visited[0] = true;
+    while let Some(&mut (node, ref mut child)) = stack.last_mut() {
+        if let Some(&next) = succ[node].get(*child) {
+            *child += 1;
+            if !visited[next] {
+                visited[next] = true;
+                stack.push((next, 0));
+            }
+        } else {
+            order.push(node);
+            stack.pop();
+        }
+    }
+    order.reverse();
+    let mut rank = vec![usize::MAX; n];
+    for (i, &node) in order.iter().enumerate() {
+        rank[node] = i;
+    }
+
+    // Cooper, Harvey & Kennedy, "A Simple, Fast Dominance Algorithm"
+    let mut idom = vec![usize::MAX; n];
+    idom[0] = 0;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in order.iter().skip(1) {
+            let mut new_idom = usize::MAX;
+            for &pred in &preds[node] {
+                if idom[pred] == usize::MAX {
+                    continue;
+                }
+                new_idom = if new_idom == usize::MAX {
+                    pred
+                } else {
+                    let (mut a, mut b) = (pred, new_idom);
+                    while a != b {
+                        while rank[a] > rank[b] {
+                            a = idom[a];
+                        }
+                        while rank[b] > rank[a] {
+                            b = idom[b];
+                        }
+                    }
+                    a
+                };
+            }
+            if idom[node] != new_idom {
+                idom[node] = new_idom;
+                changed = true;
+            }
+        }
+    }
+
+    // Dominator tree children come after their parent in reverse postorder
+    let mut retained: Vec<usize> = raw.iter().map(|node| node.size).collect();
+    for &node in order.iter().rev().filter(|&&node| node != 0) {
+        retained[idom[node]] += retained[node];
+    }
+
+    // A type's retained bytes: objects not dominated by another of the same type
+    let mut summary: BTreeMap<String, TypeSummary> = BTreeMap::new();
+    let mut dominating = vec![0u8; n];
+    for &node in order.iter().filter(|&&node| node != 0) {
+        let parent = idom[node];
+        dominating[node] = dominating[parent] | raw[parent].kind.bit();
+        let kind = raw[node].kind;
+        let entry = summary.entry(kind.as_str().to_string()).or_default();
+        entry.count += 1;
+        entry.size += raw[node].size;
+        if dominating[node] & kind.bit() == 0 {
+            entry.retained += retained[node];
+        }
+    }
+
+    let nodes = raw
+        .into_iter()
+        .enumerate()
+        .map(|(id, node)| Node {
+            id,
+            kind: node.kind.as_str().to_string(),
+            size: node.size,
+            retained: retained[id],
+            dominator: idom[id],
+            path: node.path,
+            address: format!("{:#x}", node.address),
+        })
+        .collect();
+    let edges = raw_edges.into_iter().map(|(from, to, name)| Edge { from, to, name }).collect();
+
+    Snapshot {
+        version: VERSION,
+        vm: coppermoon_core::vm::NAME.to_string(),
+        memory,
+        summary,
+        nodes,
+        edges,
+    }
+}
+
+/// Change in one type's totals between two snapshots.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeDelta {
+    pub count: i64,
+    pub size: i64,
+    pub retained: i64,
+}
+
+/// What changed between two snapshots of the same process.
+#[derive(Debug, Clone)]
+pub struct Diff {
+    pub memory: i64,
+    pub types: BTreeMap<String, TypeDelta>,
+    /// New objects not retained by another new object, largest retained first
+    pub added: Vec<Node>,
+}
+
+/// Compare two snapshots, matching objects by address.
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Diff {
+    let mut types: BTreeMap<String, TypeDelta> = BTreeMap::new();
+    for (kind, summary) in &after.summary {
+        let delta = types.entry(kind.clone()).or_default();
+        delta.count += summary.count as i64;
+        delta.size += summary.size as i64;
+        delta.retained += summary.retained as i64;
+    }
+    for (kind, summary) in &before.summary {
+        let delta = types.entry(kind.clone()).or_default();
+        delta.count -= summary.count as i64;
+        delta.size -= summary.size as i64;
+        delta.retained -= summary.retained as i64;
+    }
+
+    let old: HashSet<&str> = before.nodes.iter().map(|node| node.address.as_str()).collect();
+    let is_new = |node: &Node| node.id != 0 && !old.contains(node.address.as_str());
+    let mut added: Vec<Node> = after
+        .nodes
+        .iter()
+        .filter(|node| is_new(node) && !after.nodes.get(node.dominator).is_some_and(is_new))
+        .cloned()
+        .collect();
+    added.sort_by(|a, b| b.retained.cmp(&a.retained));
+
+    Diff {
+        memory: after.memory as i64 - before.memory as i64,
+        types,
+        added,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_retained_sizes() {
+        let lua = Lua::new();
+        lua.load(r#"
+            cache = { items = {} }
+            for i = 1, 100 do cache.items[i] = { id = i } end
+            local secret = { "captured" }
+            function getter() return secret end
+        "#)
+        .exec()
+        .unwrap();
+        let snap = snapshot(&lua).unwrap();
+
+        let items = snap.nodes.iter().find(|n| n.path == "_G.cache.items").unwrap();
+        assert_eq!(items.kind, "table");
+        // The items table alone retains its 100 entries
+        assert!(items.retained >= items.size + 100 * (TABLE_SIZE + TABLE_ENTRY_SIZE));
+
+        let secret = snap.nodes.iter().find(|n| n.path == "_G.getter.<upvalue secret>").unwrap();
+        let getter = snap.nodes.iter().find(|n| n.path == "_G.getter").unwrap();
+        assert_eq!(secret.dominator, getter.id);
+        assert!(snap.summary["table"].count > 100);
+    }
+
+    #[test]
+    fn test_diff_reports_new_objects() {
+        let lua = Lua::new();
+        lua.load("leak = {}").exec().unwrap();
+        let before = snapshot(&lua).unwrap();
+        lua.load("for i = 1, 50 do leak[i] = { i } end").exec().unwrap();
+        let after = snapshot(&lua).unwrap();
+
+        let d = diff(&before, &after);
+        assert_eq!(d.types["table"].count, 50);
+        assert_eq!(d.added.len(), 50);
+        assert!(d.added[0].path.starts_with("_G.leak["));
+    }
+}
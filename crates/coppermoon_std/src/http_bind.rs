@@ -0,0 +1,376 @@
+//! Listening sockets for `http.server`
+//!
+//! Resolves the target given to `server:listen` (a port, an address string
+//! or an options table) and binds it: TCP on IPv4 or IPv6 (dual-stack by
+//! default), a Unix domain socket, or a socket inherited from the parent
+//! process (systemd socket activation).
+
+use coppermoon_core::vm;
+use mlua::{Table, Value};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_BACKLOG: i32 = 1024;
+
+/// Where to listen.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    /// Unix domain socket, with the file mode to set once bound
+    Unix { path: PathBuf, mode: Option<u32> },
+    /// First socket passed by systemd (`LISTEN_FDS`)
+    Systemd,
+    /// An already-listening socket inherited as this file descriptor
+    Fd(i32),
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "{}", addr),
+            Address::Unix { path, .. } => write!(f, "unix:{}", path.display()),
+            Address::Systemd => write!(f, "systemd"),
+            Address::Fd(fd) => write!(f, "fd:{}", fd),
+        }
+    }
+}
+
+/// A resolved `listen` target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BindOptions {
+    pub address: Address,
+    /// Accept only IPv6 on an IPv6 address; false also accepts IPv4
+    pub ipv6_only: bool,
+    pub backlog: i32,
+}
+
+impl BindOptions {
+    /// Resolve `server:listen`'s target and options. The target is a port,
+    /// an address string (`"0.0.0.0:8080"`, `"[::]:8080"`, `"unix:/run/app.sock"`,
+    /// `"systemd"`, `"fd:3"`) or nil; options may add `host`, `port`, `unix`,
+    /// `mode`, `fd`, `ipv6_only` and `backlog`.
+    pub fn from_lua(target: &Value, options: Option<&Table>) -> mlua::Result<Self> {
+        let mut host = DEFAULT_HOST.to_string();
+        let mut port = DEFAULT_PORT;
+        let mut address = None;
+        match target {
+            Value::Nil => {}
+            Value::String(s) => address = Some(parse_address(&s.to_str()?).map_err(mlua::Error::runtime)?),
+            other => match vm::to_integer(other).and_then(|p| u16::try_from(p).ok()) {
+                Some(p) => port = p,
+                None => return Err(mlua::Error::runtime("listen: expected a port, an address or an options table")),
+            },
+        }
+
+        let mut ipv6_only = false;
+        let mut backlog = DEFAULT_BACKLOG;
+        if let Some(options) = options {
+            if let Some(h) = options.get::<Option<String>>("host")? {
+                host = h;
+            }
+            if let Some(p) = options.get::<Option<u16>>("port")? {
+                port = p;
+            }
+            if let Some(path) = options.get::<Option<String>>("unix")? {
+                address = Some(Address::Unix { path: path.into(), mode: None });
+            }
+            match options.get::<Value>("fd")? {
+                Value::Nil => {}
+                Value::String(s) if s.to_str()? == "systemd" => address = Some(Address::Systemd),
+                other => match vm::to_integer(&other).and_then(|fd| i32::try_from(fd).ok()) {
+                    Some(fd) => address = Some(Address::Fd(fd)),
+                    None => return Err(mlua::Error::runtime("listen: fd must be a descriptor number or \"systemd\"")),
+                },
+            }
+            if let Some(Address::Unix { mode, .. }) = &mut address {
+                *mode = parse_mode(options.get::<Value>("mode")?)?;
+            }
+            ipv6_only = options.get::<Option<bool>>("ipv6_only")?.unwrap_or(false);
+            backlog = options.get::<Option<i32>>("backlog")?.unwrap_or(DEFAULT_BACKLOG);
+        }
+
+        let address = match address {
+            Some(address) => address,
+            None => Address::Tcp(resolve(&host, port).map_err(mlua::Error::runtime)?),
+        };
+        Ok(BindOptions { address, ipv6_only, backlog })
+    }
+}
+
+/// Parse an address string; a host without a port listens on 3000.
+pub fn parse_address(s: &str) -> Result<Address, String> {
+    if let Some(path) = s.strip_prefix("unix:") {
+        if path.is_empty() {
+            return Err("listen: 'unix:' needs a socket path".into());
+        }
+        return Ok(Address::Unix { path: path.into(), mode: None });
+    }
+    if s == "systemd" {
+        return Ok(Address::Systemd);
+    }
+    if let Some(fd) = s.strip_prefix("fd:") {
+        return fd.parse().map(Address::Fd).map_err(|_| format!("listen: bad descriptor '{}'", fd));
+    }
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(Address::Tcp(addr));
+    }
+    // A bare host or IPv6 address, or a name to resolve
+    let host = s.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<std::net::IpAddr>() {
+        Ok(ip) => Ok(Address::Tcp(SocketAddr::new(ip, DEFAULT_PORT))),
+        Err(_) => match s.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => {
+                let port = port.parse().map_err(|_| format!("listen: bad port in '{}'", s))?;
+                resolve(host, port).map(Address::Tcp)
+            }
+            _ => resolve(s, DEFAULT_PORT).map(Address::Tcp),
+        },
+    }
+}
+
+fn resolve(host: &str, port: u16) -> Result<SocketAddr, String> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("listen: cannot resolve '{}': {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("listen: '{}' has no addresses", host))
+}
+
+/// A file mode given as an octal string (`"660"`) or a number.
+fn parse_mode(mode: Value) -> mlua::Result<Option<u32>> {
+    match mode {
+        Value::Nil => Ok(None),
+        Value::String(s) => {
+            let s = s.to_str()?;
+            u32::from_str_radix(s.trim_start_matches("0o"), 8)
+                .map(Some)
+                .map_err(|_| mlua::Error::runtime(format!("listen: bad file mode '{}'", &*s)))
+        }
+        other => vm::to_integer(&other)
+            .and_then(|m| u32::try_from(m).ok())
+            .map(Some)
+            .ok_or_else(|| mlua::Error::runtime("listen: mode must be an octal string such as \"660\"")),
+    }
+}
+
+/// A bound, non-blocking listening socket.
+pub enum Listener {
+    Tcp(std::net::TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+impl Listener {
+    /// The port actually bound, for TCP sockets.
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Listener::Tcp(l) => l.local_addr().ok().map(|a| a.port()),
+            #[cfg(unix)]
+            Listener::Unix(_) => None,
+        }
+    }
+
+    /// The bound address for display: `127.0.0.1:3000`, `[::]:8080` or
+    /// `unix:/run/app.sock`.
+    pub fn describe(&self) -> String {
+        match self {
+            Listener::Tcp(l) => l.local_addr().map(|a| a.to_string()).unwrap_or_default(),
+            #[cfg(unix)]
+            Listener::Unix(l) => {
+                let path = l.local_addr().ok().and_then(|a| a.as_pathname().map(|p| p.display().to_string()));
+                format!("unix:{}", path.unwrap_or_default())
+            }
+        }
+    }
+
+    /// Register with the Tokio reactor; must run inside the runtime.
+    pub fn into_async(self) -> io::Result<AsyncListener> {
+        match self {
+            Listener::Tcp(l) => Ok(AsyncListener::Tcp(tokio::net::TcpListener::from_std(l)?)),
+            #[cfg(unix)]
+            Listener::Unix(l) => Ok(AsyncListener::Unix(tokio::net::UnixListener::from_std(l)?)),
+        }
+    }
+}
+
+/// A stream accepted from any kind of listener.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+pub enum AsyncListener {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl AsyncListener {
+    pub async fn accept(&self) -> io::Result<Box<dyn Connection>> {
+        match self {
+            AsyncListener::Tcp(l) => {
+                let (stream, _peer) = l.accept().await?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            AsyncListener::Unix(l) => {
+                let (stream, _peer) = l.accept().await?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+/// Bind (or adopt) the listening socket described by `options`.
+pub fn bind(options: &BindOptions) -> io::Result<Listener> {
+    match &options.address {
+        Address::Tcp(addr) => bind_tcp(*addr, options),
+        #[cfg(unix)]
+        Address::Unix { path, mode } => bind_unix(path, *mode),
+        #[cfg(unix)]
+        Address::Systemd => adopt(systemd_fd()?),
+        #[cfg(unix)]
+        Address::Fd(fd) => adopt(*fd),
+        #[cfg(not(unix))]
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix sockets and inherited descriptors are only supported on Unix",
+        )),
+    }
+}
+
+fn bind_tcp(addr: SocketAddr, options: &BindOptions) -> io::Result<Listener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        // Set explicitly: the OS default differs (Linux dual-stack, Windows not)
+        socket.set_only_v6(options.ipv6_only)?;
+    }
+    // Lets a restarted server bind while old connections sit in TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(options.backlog)?;
+    socket.set_nonblocking(true)?;
+    Ok(Listener::Tcp(socket.into()))
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path, mode: Option<u32>) -> io::Result<Listener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    // A socket file left by a previous run blocks bind; remove it unless a
+    // server is still answering on it
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() && std::os::unix::net::UnixStream::connect(path).is_err() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    listener.set_nonblocking(true)?;
+    Ok(Listener::Unix(listener))
+}
+
+/// The first descriptor systemd passed to this process (sd_listen_fds).
+#[cfg(unix)]
+fn systemd_fd() -> io::Result<i32> {
+    const SD_LISTEN_FDS_START: i32 = 3;
+    let not_activated = || io::Error::new(io::ErrorKind::NotFound, "listen: no sockets passed by systemd (LISTEN_FDS)");
+    let pid: u32 = std::env::var("LISTEN_PID").ok().and_then(|p| p.parse().ok()).ok_or_else(not_activated)?;
+    let count: i32 = std::env::var("LISTEN_FDS").ok().and_then(|n| n.parse().ok()).ok_or_else(not_activated)?;
+    // The variables stay set: changing the environment while runtime threads
+    // exist is unsound, and LISTEN_PID already keeps children from adopting
+    if pid != std::process::id() || count < 1 {
+        return Err(not_activated());
+    }
+    Ok(SD_LISTEN_FDS_START)
+}
+
+/// Take over an inherited listening socket, TCP or Unix.
+#[cfg(unix)]
+fn adopt(fd: i32) -> io::Result<Listener> {
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+    let not_listener = || io::Error::new(io::ErrorKind::InvalidInput, format!("listen: fd {} is not a listening socket", fd));
+    if fd < 0 {
+        return Err(not_listener());
+    }
+    // Safety: only borrowed until it is known to be a listening socket;
+    // ManuallyDrop keeps a wrong `fd` (stdout, a runtime descriptor) open on
+    // every error path
+    let socket = ManuallyDrop::new(unsafe { socket2::Socket::from_raw_fd(fd) });
+    if !socket.is_listener().unwrap_or(false) {
+        return Err(not_listener());
+    }
+    let tcp = socket.local_addr()?.as_socket().is_some();
+    socket.set_nonblocking(true)?;
+    // The parent handed us a listening socket: from here on we own it
+    let socket = ManuallyDrop::into_inner(socket);
+    if tcp {
+        Ok(Listener::Tcp(socket.into()))
+    } else {
+        Ok(Listener::Unix(socket.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_adopting_a_non_socket_keeps_it_open() {
+        use std::os::fd::AsRawFd;
+        let file = std::fs::File::open(env!("CARGO_MANIFEST_DIR")).unwrap();
+        assert!(adopt(file.as_raw_fd()).is_err());
+        assert!(file.metadata().is_ok());
+        assert!(adopt(-1).is_err());
+    }
+
+    #[test]
+    fn test_parse_address_forms() {
+        assert_eq!(parse_address("0.0.0.0:8080"), Ok(Address::Tcp("0.0.0.0:8080".parse().unwrap())));
+        assert_eq!(parse_address("[::]:0"), Ok(Address::Tcp("[::]:0".parse().unwrap())));
+        assert_eq!(parse_address("::"), Ok(Address::Tcp("[::]:3000".parse().unwrap())));
+        assert_eq!(parse_address("0.0.0.0"), Ok(Address::Tcp("0.0.0.0:3000".parse().unwrap())));
+        assert_eq!(
+            parse_address("unix:/run/app.sock"),
+            Ok(Address::Unix { path: "/run/app.sock".into(), mode: None })
+        );
+        assert_eq!(parse_address("systemd"), Ok(Address::Systemd));
+        assert_eq!(parse_address("fd:3"), Ok(Address::Fd(3)));
+        assert!(parse_address("unix:").is_err());
+    }
+
+    #[test]
+    fn test_port_zero_binds_a_free_port() {
+        let options = BindOptions { address: parse_address("127.0.0.1:0").unwrap(), ipv6_only: false, backlog: 16 };
+        let listener = bind(&options).unwrap();
+        assert_ne!(listener.port(), Some(0));
+        assert!(listener.describe().starts_with("127.0.0.1:"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_mode_and_stale_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("coppermoon-bind-{}.sock", std::process::id()));
+        let options = BindOptions {
+            address: Address::Unix { path: path.clone(), mode: Some(0o660) },
+            ipv6_only: false,
+            backlog: 16,
+        };
+        drop(bind(&options).unwrap());
+        // The file of the closed listener is stale and gets replaced
+        let listener = bind(&options).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+        assert!(listener.describe().ends_with(".sock"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
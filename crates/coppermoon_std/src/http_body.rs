@@ -0,0 +1,487 @@
+//! Request body parsers for `http.server`
+//!
+//! `application/x-www-form-urlencoded` and `multipart/form-data` (RFC 7578).
+//! Most bodies arrive whole (within the server's `max_body_size`) and are
+//! parsed in memory. [`MultipartParser`] also takes a body in pieces, which
+//! the server uses to write file uploads to disk as they arrive, so large
+//! uploads are neither buffered nor turned into Lua strings.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Limits and storage for [`parse_multipart`] and [`MultipartParser`].
+#[derive(Debug, Clone)]
+pub struct MultipartOptions {
+    /// Largest file part, in bytes
+    pub max_file_size: usize,
+    /// Largest non-file field, in bytes
+    pub max_field_size: usize,
+    pub max_parts: usize,
+    /// Largest body as a whole, in bytes
+    pub max_size: usize,
+    /// Write file parts to this directory instead of keeping them in memory
+    pub spool: Option<PathBuf>,
+}
+
+impl Default for MultipartOptions {
+    fn default() -> Self {
+        MultipartOptions {
+            max_file_size: usize::MAX,
+            max_field_size: 1024 * 1024,
+            max_parts: 1000,
+            max_size: usize::MAX,
+            spool: None,
+        }
+    }
+}
+
+/// One part of a multipart body.
+#[derive(Debug, PartialEq)]
+pub struct Part {
+    pub name: String,
+    /// Set for file uploads; only the last path component is kept
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub size: usize,
+    pub content: PartContent,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PartContent {
+    Data(Vec<u8>),
+    /// Spooled to this file
+    File(PathBuf),
+}
+
+/// Decode a form or query component: `+` is a space, `%XX` a byte.
+pub fn form_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+                if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    out.push(byte);
+                    i += 2;
+                } else {
+                    out.push(b'%');
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// `application/x-www-form-urlencoded` pairs, in order.
+pub fn parse_form(body: &[u8]) -> Vec<(String, String)> {
+    String::from_utf8_lossy(body)
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (form_decode(key), form_decode(value))
+        })
+        .collect()
+}
+
+/// The media type of a `Content-Type` value, lowercased, and its parameters.
+pub fn media_type(content_type: &str) -> (String, Vec<(String, String)>) {
+    let (media, params) = content_type.split_once(';').unwrap_or((content_type, ""));
+    (media.trim().to_ascii_lowercase(), header_params(params))
+}
+
+/// `key=value; key="quoted \"value\""` parameters; keys are lowercased.
+fn header_params(s: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ';' || c.is_whitespace()).is_some() {}
+        let key: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=' && *c != ';')).collect();
+        if key.is_empty() && chars.peek().is_none() {
+            return params;
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => value.extend(chars.next()),
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                value = std::iter::from_fn(|| chars.next_if(|c| *c != ';')).collect();
+            }
+        }
+        params.push((key.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Split a `multipart/form-data` body on `boundary`. On error, files
+/// already spooled for earlier parts are removed.
+pub fn parse_multipart(body: &[u8], boundary: &str, options: &MultipartOptions) -> Result<Vec<Part>, String> {
+    let mut parser = MultipartParser::new(boundary, options.clone());
+    parser.feed(body)?;
+    parser.finish()
+}
+
+/// Longest boundary line or block of part headers
+const MAX_PART_HEAD: usize = 16 * 1024;
+
+/// Incremental `multipart/form-data` parser: the body can be fed in pieces
+/// of any size, and spooled file parts are written out as they arrive.
+/// Files of a parser that fails or is dropped before [`finish`](Self::finish)
+/// are removed.
+pub struct MultipartParser {
+    options: MultipartOptions,
+    /// `\r\n--boundary`
+    delimiter: Vec<u8>,
+    /// Input not consumed yet
+    buf: Vec<u8>,
+    state: State,
+    /// Bytes fed so far
+    size: usize,
+    parts: Vec<Part>,
+    current: Option<OpenPart>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Preamble,
+    /// After a delimiter: padding and a line break, or `--` for the last one
+    Delimiter,
+    Headers,
+    Body,
+    /// Past the closing delimiter; the epilogue is ignored
+    Done,
+}
+
+/// The part being read, with its spool file if it has one.
+struct OpenPart {
+    part: Part,
+    file: Option<std::io::BufWriter<std::fs::File>>,
+}
+
+impl MultipartParser {
+    pub fn new(boundary: &str, options: MultipartOptions) -> Self {
+        MultipartParser {
+            options,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // The first delimiter may open the body, with no line break before it
+            buf: b"\r\n".to_vec(),
+            state: State::Preamble,
+            size: 0,
+            parts: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// Parse the next piece of the body.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), String> {
+        self.size += data.len();
+        if self.size > self.options.max_size {
+            return Err(format!("multipart: body is larger than {} bytes", self.options.max_size));
+        }
+        if self.state == State::Done {
+            return Ok(());
+        }
+        self.buf.extend_from_slice(data);
+
+        loop {
+            match self.state {
+                State::Preamble => {
+                    let Some(at) = find(&self.buf, &self.delimiter) else {
+                        // Keep what could be the start of a delimiter
+                        let keep = self.buf.len().min(self.delimiter.len() - 1);
+                        self.buf.drain(..self.buf.len() - keep);
+                        return Ok(());
+                    };
+                    self.buf.drain(..at + self.delimiter.len());
+                    self.state = State::Delimiter;
+                }
+                State::Delimiter => {
+                    if self.buf.starts_with(b"--") {
+                        self.buf.clear();
+                        self.state = State::Done;
+                        return Ok(());
+                    }
+                    // Transport padding, then the line break ending the delimiter line
+                    let Some(end) = find(&self.buf, b"\r\n") else {
+                        return self.wait_for_head("malformed boundary line");
+                    };
+                    if !self.buf[..end].iter().all(|b| *b == b' ' || *b == b'\t') {
+                        return Err("multipart: malformed boundary line".into());
+                    }
+                    self.buf.drain(..end + 2);
+                    self.state = State::Headers;
+                }
+                State::Headers => {
+                    let (head, len) = if self.buf.starts_with(b"\r\n") {
+                        (String::new(), 2)
+                    } else {
+                        let Some(end) = find(&self.buf, b"\r\n\r\n") else {
+                            return self.wait_for_head("part headers too long");
+                        };
+                        (String::from_utf8_lossy(&self.buf[..end]).into_owned(), end + 4)
+                    };
+                    self.buf.drain(..len);
+                    if self.parts.len() == self.options.max_parts {
+                        return Err(format!("multipart: more than {} parts", self.options.max_parts));
+                    }
+                    self.current = Some(open_part(&head, &self.options)?);
+                    self.state = State::Body;
+                }
+                State::Body => {
+                    let found = find(&self.buf, &self.delimiter);
+                    // Without a delimiter, hold back what could be the start of one
+                    let end = found.unwrap_or(self.buf.len().saturating_sub(self.delimiter.len() - 1));
+                    let current = self.current.as_mut().expect("a part is open in the body state");
+                    current.append(&self.buf[..end], &self.options)?;
+                    if found.is_none() {
+                        self.buf.drain(..end);
+                        return Ok(());
+                    }
+                    self.buf.drain(..end + self.delimiter.len());
+                    let current = self.current.take().expect("a part is open in the body state");
+                    self.parts.push(current.close()?);
+                    self.state = State::Delimiter;
+                }
+                State::Done => return Ok(()),
+            }
+        }
+    }
+
+    /// The parts, once the closing delimiter has been read.
+    pub fn finish(mut self) -> Result<Vec<Part>, String> {
+        match self.state {
+            State::Done => Ok(std::mem::take(&mut self.parts)),
+            State::Preamble => Err("multipart: no boundary in body".into()),
+            State::Delimiter => Err("multipart: truncated body".into()),
+            State::Headers => Err("multipart: truncated part headers".into()),
+            State::Body => Err("multipart: missing closing boundary".into()),
+        }
+    }
+
+    /// Wait for more input, unless a line or header block is already too long.
+    fn wait_for_head(&self, error: &str) -> Result<(), String> {
+        if self.buf.len() > MAX_PART_HEAD {
+            return Err(format!("multipart: {}", error));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MultipartParser {
+    fn drop(&mut self) {
+        let current = self.current.take().map(|open| open.part);
+        for part in self.parts.drain(..).chain(current) {
+            if let PartContent::File(path) = part.content {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+fn open_part(head: &str, options: &MultipartOptions) -> Result<OpenPart, String> {
+    let mut disposition = Vec::new();
+    let mut content_type = None;
+    for line in head.split("\r\n") {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-disposition" => disposition = media_type(value).1,
+            "content-type" => content_type = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+    let param = |key: &str| disposition.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+    let name = param("name").ok_or("multipart: part without a name")?;
+    // filename* (RFC 5987) wins over filename
+    let filename = param("filename*")
+        .and_then(|v| v.split_once("''").map(|(_, encoded)| crate::http_router::percent_decode(encoded)))
+        .or_else(|| param("filename"))
+        .map(|f| f.rsplit(['/', '\\']).next().unwrap_or_default().to_string());
+
+    let (content, file) = match (&filename, &options.spool) {
+        (Some(_), Some(dir)) => {
+            let (path, file) = spool_file(dir)?;
+            (PartContent::File(path), Some(std::io::BufWriter::new(file)))
+        }
+        _ => (PartContent::Data(Vec::new()), None),
+    };
+    Ok(OpenPart { part: Part { name, filename, content_type, size: 0, content }, file })
+}
+
+impl OpenPart {
+    fn append(&mut self, data: &[u8], options: &MultipartOptions) -> Result<(), String> {
+        let part = &mut self.part;
+        let limit = if part.filename.is_some() { options.max_file_size } else { options.max_field_size };
+        part.size += data.len();
+        if part.size > limit {
+            return Err(format!("multipart: part '{}' is larger than {} bytes", part.name, limit));
+        }
+        match (&mut part.content, &mut self.file) {
+            (PartContent::File(path), Some(file)) => std::io::Write::write_all(file, data)
+                .map_err(|e| format!("multipart: cannot write '{}': {}", path.display(), e)),
+            (PartContent::Data(bytes), _) => {
+                bytes.extend_from_slice(data);
+                Ok(())
+            }
+            (PartContent::File(_), None) => unreachable!("spooled parts are opened with a file"),
+        }
+    }
+
+    fn close(mut self) -> Result<Part, String> {
+        if let (PartContent::File(path), Some(file)) = (&self.part.content, &mut self.file) {
+            if let Err(e) = std::io::Write::flush(file) {
+                let _ = std::fs::remove_file(path);
+                return Err(format!("multipart: cannot write '{}': {}", path.display(), e));
+            }
+        }
+        Ok(self.part)
+    }
+}
+
+static NEXT_SPOOL_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Create a new file for an uploaded file in `dir`.
+fn spool_file(dir: &Path) -> Result<(PathBuf, std::fs::File), String> {
+    let id = NEXT_SPOOL_ID.fetch_add(1, Ordering::Relaxed);
+    let path = dir.join(format!("coppermoon-upload-{}-{}", std::process::id(), id));
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| format!("multipart: cannot write '{}': {}", path.display(), e))?;
+    Ok((path, file))
+}
+
+/// Parts whose spooled files are removed when this is dropped, unless they
+/// were moved elsewhere by then.
+#[derive(Debug)]
+pub struct SpooledParts(pub Vec<Part>);
+
+impl Drop for SpooledParts {
+    fn drop(&mut self) {
+        for part in &self.0 {
+            if let PartContent::File(path) = &part.content {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        Hello, world\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"C:\\\\tmp\\\\a \\\"b\\\".bin\"\r\n\
+        Content-Type: application/octet-stream\r\n\r\n\
+        \x00\xff\r\n--Xy\r\n--XyZ--\r\n";
+
+    #[test]
+    fn test_form_decoding() {
+        assert_eq!(
+            parse_form(b"a=1&b=x+y&c=%C3%A9%26&d&=e"),
+            vec![
+                ("a".into(), "1".into()),
+                ("b".into(), "x y".into()),
+                ("c".into(), "é&".into()),
+                ("d".into(), "".into()),
+                ("".into(), "e".into()),
+            ]
+        );
+        assert_eq!(form_decode("100%"), "100%");
+        let (media, params) = media_type("Multipart/Form-Data; boundary=\"a;b\"; charset=utf-8");
+        assert_eq!(media, "multipart/form-data");
+        assert_eq!(params, vec![("boundary".into(), "a;b".into()), ("charset".into(), "utf-8".into())]);
+    }
+
+    #[test]
+    fn test_multipart_parts() {
+        let parts = parse_multipart(BODY, "XyZ", &MultipartOptions::default()).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "title");
+        assert_eq!(parts[0].content, PartContent::Data(b"Hello, world".to_vec()));
+        assert_eq!(parts[1].filename.as_deref(), Some("a \"b\".bin"));
+        assert_eq!(parts[1].content_type.as_deref(), Some("application/octet-stream"));
+        assert_eq!(parts[1].content, PartContent::Data(b"\x00\xff\r\n--Xy".to_vec()));
+    }
+
+    #[test]
+    fn test_multipart_limits_and_spool() {
+        let small = MultipartOptions { max_file_size: 4, ..Default::default() };
+        assert!(parse_multipart(BODY, "XyZ", &small).unwrap_err().contains("'file'"));
+        let one = MultipartOptions { max_parts: 1, ..Default::default() };
+        assert!(parse_multipart(BODY, "XyZ", &one).is_err());
+        assert!(parse_multipart(b"--XyZ\r\n\r\nno end", "XyZ", &MultipartOptions::default()).is_err());
+
+        let spooled = MultipartOptions { spool: Some(std::env::temp_dir()), ..Default::default() };
+        let parts = parse_multipart(BODY, "XyZ", &spooled).unwrap();
+        let PartContent::File(path) = &parts[1].content else {
+            panic!("file part was not spooled");
+        };
+        assert_eq!(std::fs::read(path).unwrap(), b"\x00\xff\r\n--Xy");
+        assert_eq!(parts[1].size, 6);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_multipart_fed_in_pieces() {
+        let dir = std::env::temp_dir().join(format!("coppermoon-pieces-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = MultipartOptions { spool: Some(dir.clone()), ..Default::default() };
+        // One byte at a time: every delimiter and header block is split
+        let mut parser = MultipartParser::new("XyZ", options.clone());
+        for byte in BODY {
+            parser.feed(std::slice::from_ref(byte)).unwrap();
+        }
+        let parts = SpooledParts(parser.finish().unwrap());
+        assert_eq!(parts.0[0].content, PartContent::Data(b"Hello, world".to_vec()));
+        let PartContent::File(path) = &parts.0[1].content else {
+            panic!("file part was not spooled");
+        };
+        assert_eq!(std::fs::read(path).unwrap(), b"\x00\xff\r\n--Xy");
+        drop(parts);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        // A body cut off midway leaves nothing behind
+        let mut parser = MultipartParser::new("XyZ", options);
+        parser.feed(&BODY[..BODY.len() - 12]).unwrap();
+        assert!(parser.finish().unwrap_err().contains("closing boundary"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+
+        let small = MultipartOptions { max_size: BODY.len() - 1, ..Default::default() };
+        assert!(parse_multipart(BODY, "XyZ", &small).unwrap_err().contains("body is larger"));
+    }
+
+    #[test]
+    fn test_failed_parse_removes_spooled_files() {
+        let dir = std::env::temp_dir().join(format!("coppermoon-spool-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let body = b"--XyZ\r\n\
+            Content-Disposition: form-data; name=\"a\"; filename=\"a.txt\"\r\n\r\n\
+            aaa\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"b\"; filename=\"b.txt\"\r\n\r\n\
+            bbb\r\n--XyZ--\r\n";
+        let options = MultipartOptions { max_parts: 1, spool: Some(dir.clone()), ..Default::default() };
+        assert!(parse_multipart(body, "XyZ", &options).is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+//! Response compression for `http.server`
+//!
+//! Enabled per server with `listen(..., { compress = true })` or a table of
+//! options. Complete responses are compressed with the encoding the client
+//! prefers (by `Accept-Encoding` quality, ties going to the server's order);
+//! streamed responses are sent as they are.
+
+use crate::http_static::encoding_quality;
+use mlua::{Table, Value};
+use std::io::Write;
+
+/// Bodies smaller than this are sent uncompressed by default
+const MIN_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// The `Content-Encoding` token
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip, Encoding::Deflate]
+            .into_iter()
+            .find(|e| e.name() == name)
+    }
+
+    /// Compress `data`; `level` is clamped to what the encoding supports.
+    pub fn compress(self, data: &[u8], level: Option<u32>) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let quality = level.unwrap_or(4).min(11);
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, quality, 22);
+                writer.write_all(data)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Zstd => zstd::bulk::compress(data, level.unwrap_or(3).clamp(1, 22) as i32),
+            Encoding::Gzip => {
+                let level = flate2::Compression::new(level.unwrap_or(6).min(9));
+                let mut writer = flate2::write::GzEncoder::new(Vec::new(), level);
+                writer.write_all(data)?;
+                writer.finish()
+            }
+            // HTTP's "deflate" is the zlib format (RFC 9110 §8.4.1.2)
+            Encoding::Deflate => {
+                let level = flate2::Compression::new(level.unwrap_or(6).min(9));
+                let mut writer = flate2::write::ZlibEncoder::new(Vec::new(), level);
+                writer.write_all(data)?;
+                writer.finish()
+            }
+        }
+    }
+}
+
+/// Compression settings of one server.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    /// Offered encodings, preferred first
+    encodings: Vec<Encoding>,
+    /// Encoding-specific default when `None`
+    level: Option<u32>,
+    min_size: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            encodings: vec![Encoding::Brotli, Encoding::Zstd, Encoding::Gzip, Encoding::Deflate],
+            level: None,
+            min_size: MIN_SIZE,
+        }
+    }
+}
+
+impl Compression {
+    /// Read `compress` from the listen options: `true`, or a table with
+    /// `level`, `min_size` and `encodings` (e.g. `{ "gzip", "br" }`).
+    pub fn from_options(options: &Table) -> mlua::Result<Option<Self>> {
+        let options = match options.get::<Value>("compress")? {
+            Value::Nil | Value::Boolean(false) => return Ok(None),
+            Value::Boolean(true) => return Ok(Some(Compression::default())),
+            Value::Table(options) => options,
+            _ => return Err(mlua::Error::runtime("listen: 'compress' must be a boolean or a table")),
+        };
+        let mut compression = Compression::default();
+        compression.level = options.get("level")?;
+        if let Some(min_size) = options.get::<Option<usize>>("min_size")? {
+            compression.min_size = min_size;
+        }
+        if let Some(names) = options.get::<Option<Vec<String>>>("encodings")? {
+            compression.encodings = names
+                .iter()
+                .map(|name| {
+                    Encoding::from_name(name).ok_or_else(|| {
+                        mlua::Error::runtime(format!(
+                            "listen: unknown encoding '{}' (expected br, zstd, gzip or deflate)",
+                            name
+                        ))
+                    })
+                })
+                .collect::<mlua::Result<_>>()?;
+        }
+        Ok(Some(compression))
+    }
+
+    /// The offered encoding the client rates highest, if it accepts any.
+    pub fn negotiate(&self, accept: &str) -> Option<Encoding> {
+        let mut best = None;
+        let mut best_q = 0.0;
+        for encoding in &self.encodings {
+            let q = encoding_quality(accept, encoding.name());
+            if q > best_q {
+                (best, best_q) = (Some(*encoding), q);
+            }
+        }
+        best
+    }
+
+    /// Compress a complete response body if it is worth it and the client
+    /// accepts it, updating the headers to match.
+    pub fn apply(
+        &self,
+        accept: Option<&str>,
+        status: u16,
+        content_type: &str,
+        headers: &mut Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> Vec<u8> {
+        if status < 200
+            || status == 204
+            || status == 304
+            || body.len() < self.min_size
+            || !compressible(content_type)
+            || header(headers, "content-encoding").is_some()
+        {
+            return body;
+        }
+        // Caches must not hand this response to clients with other encodings
+        add_vary(headers);
+
+        let Some(encoding) = self.negotiate(accept.unwrap_or("")) else {
+            return body;
+        };
+        let compressed = match encoding.compress(&body, self.level) {
+            Ok(compressed) if compressed.len() < body.len() => compressed,
+            _ => return body,
+        };
+        headers.push(("Content-Encoding".into(), encoding.name().into()));
+        // A strong ETag names the uncompressed bytes
+        if let Some((_, etag)) = headers.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case("etag")) {
+            if let Some(tag) = etag.strip_suffix('"').filter(|t| t.starts_with('"')) {
+                *etag = format!("{}-{}\"", tag, encoding.name());
+            }
+        }
+        compressed
+    }
+}
+
+fn header<'h>(headers: &'h [(String, String)], name: &str) -> Option<&'h str> {
+    headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+}
+
+fn add_vary(headers: &mut Vec<(String, String)>) {
+    match headers.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case("vary")) {
+        Some((_, vary)) => {
+            let listed = vary.split(',').any(|v| v.trim() == "*" || v.trim().eq_ignore_ascii_case("accept-encoding"));
+            if !listed {
+                vary.push_str(", Accept-Encoding");
+            }
+        }
+        None => headers.push(("Vary".into(), "Accept-Encoding".into())),
+    }
+}
+
+/// False for media types that are compressed already.
+fn compressible(content_type: &str) -> bool {
+    let media = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    if media == "image/svg+xml" {
+        return true;
+    }
+    let compressed_prefixes = ["image/", "video/", "audio/", "font/woff"];
+    let compressed = [
+        "application/zip",
+        "application/gzip",
+        "application/x-gzip",
+        "application/zstd",
+        "application/x-bzip2",
+        "application/x-xz",
+        "application/x-7z-compressed",
+        "application/x-rar-compressed",
+        "application/pdf",
+        "text/event-stream",
+    ];
+    !compressed_prefixes.iter().any(|p| media.starts_with(p)) && !compressed.contains(&media.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_negotiation() {
+        let compression = Compression::default();
+        assert_eq!(compression.negotiate("gzip, deflate, br, zstd"), Some(Encoding::Brotli));
+        assert_eq!(compression.negotiate("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(compression.negotiate("br;q=0, *;q=0.1"), Some(Encoding::Zstd));
+        assert_eq!(compression.negotiate("identity"), None);
+        assert_eq!(compression.negotiate(""), None);
+    }
+
+    #[test]
+    fn test_apply_compresses_and_updates_headers() {
+        let compression = Compression { encodings: vec![Encoding::Gzip], ..Default::default() };
+        let body = br#"{"items":[1,2,3,4,5,6,7,8,9,10]}"#.repeat(100);
+
+        let mut headers = vec![("ETag".to_string(), "\"v1\"".to_string())];
+        let compressed = compression.apply(Some("gzip"), 200, "application/json", &mut headers, body.clone());
+        assert!(compressed.len() < body.len());
+        assert!(headers.contains(&("Content-Encoding".into(), "gzip".into())));
+        assert!(headers.contains(&("Vary".into(), "Accept-Encoding".into())));
+        assert!(headers.contains(&("ETag".into(), "\"v1-gzip\"".into())));
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(compressed.as_slice()).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, body);
+
+        let mut headers = Vec::new();
+        assert_eq!(compression.apply(Some("br"), 200, "text/html", &mut headers, body.clone()), body);
+        assert_eq!(headers, vec![("Vary".to_string(), "Accept-Encoding".to_string())]);
+
+        let mut headers = Vec::new();
+        assert_eq!(compression.apply(Some("gzip"), 200, "image/png", &mut headers, body.clone()), body);
+        assert_eq!(compression.apply(Some("gzip"), 200, "text/plain", &mut headers, b"tiny".to_vec()), b"tiny");
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn test_every_encoding_round_trips() {
+        let body = b"hello hello hello hello hello hello".repeat(50);
+        for encoding in [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip, Encoding::Deflate] {
+            let compressed = encoding.compress(&body, Some(9)).unwrap();
+            let mut decoded = Vec::new();
+            match encoding {
+                Encoding::Brotli => {
+                    brotli::Decompressor::new(compressed.as_slice(), 4096).read_to_end(&mut decoded).unwrap();
+                }
+                Encoding::Zstd => decoded = zstd::decode_all(compressed.as_slice()).unwrap(),
+                Encoding::Gzip => {
+                    flate2::read::GzDecoder::new(compressed.as_slice()).read_to_end(&mut decoded).unwrap();
+                }
+                Encoding::Deflate => {
+                    flate2::read::ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut decoded).unwrap();
+                }
+            }
+            assert_eq!(decoded, body, "{}", encoding.name());
+        }
+    }
+}
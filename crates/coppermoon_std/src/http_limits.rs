@@ -0,0 +1,52 @@
+//! Request limits for `http.server`
+//!
+//! Kept apart from the server itself so embedders can read and configure
+//! limits (e.g. from `coppermoon.toml`) even in builds without the
+//! `http-server` feature.
+
+use mlua::Lua;
+
+const MAX_REQUEST_LINE: usize = 8 * 1024;       // 8 KB
+const MAX_HEADER_LINE: usize = 8 * 1024;        // 8 KB per header
+const MAX_HEADER_COUNT: usize = 100;             // max number of headers
+const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;  // 10 MB
+const CONNECTION_TIMEOUT_SECS: u64 = 30;         // 30s to receive a request
+const KEEP_ALIVE_SECS: u64 = 5;                  // idle time between requests
+const MAX_REQUESTS_PER_CONNECTION: usize = 1000;
+
+/// Request size and time limits. Defaults to the constants above; an
+/// embedder (e.g. the CLI from `coppermoon.toml`) can change them per Lua
+/// state with [`set_limits`] before `server:listen` is called.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_request_line: usize,
+    pub max_header_line: usize,
+    pub max_header_count: usize,
+    pub max_body_size: usize,
+    /// Time allowed to receive one request (line, headers and body)
+    pub timeout_secs: u64,
+    /// How long an idle keep-alive connection waits for its next request
+    pub keep_alive_secs: u64,
+    /// Requests served on one connection before it is closed (1 disables
+    /// keep-alive)
+    pub max_requests_per_connection: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_request_line: MAX_REQUEST_LINE,
+            max_header_line: MAX_HEADER_LINE,
+            max_header_count: MAX_HEADER_COUNT,
+            max_body_size: MAX_BODY_SIZE,
+            timeout_secs: CONNECTION_TIMEOUT_SECS,
+            keep_alive_secs: KEEP_ALIVE_SECS,
+            max_requests_per_connection: MAX_REQUESTS_PER_CONNECTION,
+        }
+    }
+}
+
+/// Set the limits used by servers started from this Lua state.
+pub fn set_limits(lua: &Lua, limits: Limits) {
+    lua.set_app_data(limits);
+}
@@ -0,0 +1,506 @@
+//! Request router for `http.server`
+//!
+//! A radix tree over route patterns:
+//!
+//! - `/users/:id` captures one path segment as `id`
+//! - `/users/:id(\d+)` only matches segments that fit the regex
+//! - `/posts/:page?` makes the segment optional
+//! - `/files/*path` captures the rest of the path (`*` alone: `"*"`)
+//! - `api.example.com/users`, `*.example.com/`, `:tenant.example.com/` only
+//!   match requests for those hosts
+//!
+//! Static text beats a parameter, which beats a splat; host routes are tried
+//! before routes without a host. Each leaf holds one value per method, where
+//! `ALL` matches any method except `WS` (WebSocket upgrades).
+
+use regex::Regex;
+
+/// Captured parameters, in pattern order
+pub type Params = Vec<(String, String)>;
+
+/// The outcome of [`Router::find`].
+#[derive(Debug, PartialEq)]
+pub enum Match<'a, T> {
+    Found { value: &'a T, params: Params },
+    /// The path matched, but only for these methods
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+pub struct Router<T> {
+    hosts: Vec<HostRoutes<T>>,
+    root: Node<T>,
+}
+
+struct HostRoutes<T> {
+    pattern: String,
+    root: Node<T>,
+}
+
+struct Node<T> {
+    /// Static text consumed by this node
+    label: String,
+    /// Static children; no two start with the same character
+    children: Vec<Node<T>>,
+    /// `:name` segments, tried in insertion order
+    params: Vec<Param<T>>,
+    splat: Option<Splat<T>>,
+    methods: Vec<(String, T)>,
+}
+
+struct Param<T> {
+    name: String,
+    /// Source of `constraint`, to share nodes between routes
+    pattern: Option<String>,
+    constraint: Option<Regex>,
+    node: Node<T>,
+}
+
+struct Splat<T> {
+    name: String,
+    methods: Vec<(String, T)>,
+}
+
+enum Token {
+    Static(String),
+    Param { name: String, constraint: Option<String> },
+    Splat(String),
+}
+
+impl<T> Default for Router<T> {
+    fn default() -> Self {
+        Router { hosts: Vec::new(), root: Node::new(String::new()) }
+    }
+}
+
+impl<T> Router<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a route. A later route with the same method and pattern replaces
+    /// an earlier one.
+    pub fn insert(&mut self, method: &str, pattern: &str, value: T) -> Result<(), String>
+    where
+        T: Clone,
+    {
+        let (host, path) = split_host(pattern)?;
+        let root = match host {
+            None => &mut self.root,
+            Some(host) => {
+                let host = host.to_ascii_lowercase();
+                let index = match self.hosts.iter().position(|h| h.pattern == host) {
+                    Some(index) => index,
+                    None => {
+                        self.hosts.push(HostRoutes { pattern: host, root: Node::new(String::new()) });
+                        self.hosts.len() - 1
+                    }
+                };
+                &mut self.hosts[index].root
+            }
+        };
+        for path in expand_optional(path) {
+            root.insert(&parse_pattern(&path)?, method, value.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Route a request. `HEAD` falls back to `GET` (RFC 9110 §9.3.2).
+    pub fn find(&self, method: &str, host: Option<&str>, path: &str) -> Match<'_, T> {
+        let wanted = match method {
+            "WS" => vec!["WS"],
+            "HEAD" => vec!["HEAD", "ALL", "GET"],
+            _ => vec![method, "ALL"],
+        };
+        let mut search = Search { wanted: &wanted, allowed: Vec::new() };
+
+        let host = host.map(normalize_host);
+        if let Some(host) = &host {
+            // Exact host names first, then wildcards and parameters
+            let exact = self.hosts.iter().filter(|h| h.pattern == *host);
+            let patterns = self.hosts.iter().filter(|h| h.pattern != *host);
+            for routes in exact.chain(patterns) {
+                let Some(mut params) = match_host(&routes.pattern, host) else {
+                    continue;
+                };
+                if let Some(value) = search.node(&routes.root, path, &mut params) {
+                    return Match::Found { value, params };
+                }
+            }
+        }
+
+        let mut params = Params::new();
+        if let Some(value) = search.node(&self.root, path, &mut params) {
+            return Match::Found { value, params };
+        }
+        if search.allowed.is_empty() {
+            Match::NotFound
+        } else {
+            Match::MethodNotAllowed(search.allowed.into_iter().map(str::to_string).collect())
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn new(label: String) -> Self {
+        Node { label, children: Vec::new(), params: Vec::new(), splat: None, methods: Vec::new() }
+    }
+
+    fn insert(&mut self, tokens: &[Token], method: &str, value: T) -> Result<(), String> {
+        let Some((token, rest)) = tokens.split_first() else {
+            set_method(&mut self.methods, method, value);
+            return Ok(());
+        };
+        match token {
+            Token::Static(text) => self.static_child(text).insert(rest, method, value),
+            Token::Param { name, constraint } => {
+                let index = match self.params.iter().position(|p| p.name == *name && p.pattern == *constraint) {
+                    Some(index) => index,
+                    None => {
+                        let regex = match constraint {
+                            Some(c) => Some(Regex::new(&format!("^(?:{})$", c)).map_err(|e| e.to_string())?),
+                            None => None,
+                        };
+                        self.params.push(Param {
+                            name: name.clone(),
+                            pattern: constraint.clone(),
+                            constraint: regex,
+                            node: Node::new(String::new()),
+                        });
+                        self.params.len() - 1
+                    }
+                };
+                self.params[index].node.insert(rest, method, value)
+            }
+            Token::Splat(name) => {
+                let splat = self.splat.get_or_insert_with(|| Splat { name: name.clone(), methods: Vec::new() });
+                splat.name = name.clone();
+                set_method(&mut splat.methods, method, value);
+                Ok(())
+            }
+        }
+    }
+
+    /// The node reached by consuming `text`, splitting edges as needed.
+    fn static_child(&mut self, text: &str) -> &mut Node<T> {
+        if text.is_empty() {
+            return self;
+        }
+        let first = text.chars().next();
+        let Some(index) = self.children.iter().position(|c| c.label.chars().next() == first) else {
+            self.children.push(Node::new(text.to_string()));
+            return self.children.last_mut().unwrap();
+        };
+        let child = &mut self.children[index];
+        let common = common_prefix(&child.label, text);
+        if common < child.label.len() {
+            child.split(common);
+        }
+        child.static_child(&text[common..])
+    }
+
+    /// Keep the first `at` bytes of the label here and move the rest, with
+    /// everything below, into a new child.
+    fn split(&mut self, at: usize) {
+        let tail = Node {
+            label: self.label.split_off(at),
+            children: std::mem::take(&mut self.children),
+            params: std::mem::take(&mut self.params),
+            splat: self.splat.take(),
+            methods: std::mem::take(&mut self.methods),
+        };
+        self.children = vec![tail];
+    }
+}
+
+fn set_method<T>(methods: &mut Vec<(String, T)>, method: &str, value: T) {
+    match methods.iter_mut().find(|(m, _)| m == method) {
+        Some(entry) => entry.1 = value,
+        None => methods.push((method.to_string(), value)),
+    }
+}
+
+/// Walks the tree for one request, remembering which methods the path
+/// matched when none of them was wanted.
+struct Search<'w> {
+    wanted: &'w [&'w str],
+    allowed: Vec<&'w str>,
+}
+
+impl<'w> Search<'w> {
+    /// Match `path`, the part of the request path after `node.label`.
+    fn node<'a, T>(&mut self, node: &'a Node<T>, path: &str, params: &mut Params) -> Option<&'a T>
+    where
+        'a: 'w,
+    {
+        if path.is_empty() {
+            if let Some(value) = self.methods(&node.methods) {
+                return Some(value);
+            }
+        }
+        for child in &node.children {
+            if let Some(rest) = path.strip_prefix(child.label.as_str()) {
+                if let Some(value) = self.node(child, rest, params) {
+                    return Some(value);
+                }
+            }
+        }
+        let end = path.find('/').unwrap_or(path.len());
+        if end > 0 {
+            let value = percent_decode(&path[..end]);
+            for param in &node.params {
+                if param.constraint.as_ref().is_some_and(|re| !re.is_match(&value)) {
+                    continue;
+                }
+                params.push((param.name.clone(), value.clone()));
+                if let Some(found) = self.node(&param.node, &path[end..], params) {
+                    return Some(found);
+                }
+                params.pop();
+            }
+        }
+        if let Some(splat) = &node.splat {
+            if let Some(value) = self.methods(&splat.methods) {
+                params.push((splat.name.clone(), percent_decode(path)));
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn methods<'a, T>(&mut self, methods: &'a [(String, T)]) -> Option<&'a T>
+    where
+        'a: 'w,
+    {
+        for wanted in self.wanted {
+            if let Some((_, value)) = methods.iter().find(|(m, _)| m == wanted) {
+                return Some(value);
+            }
+        }
+        for (method, _) in methods {
+            if !self.allowed.contains(&method.as_str()) {
+                self.allowed.push(method.as_str());
+            }
+        }
+        None
+    }
+}
+
+/// Split `api.example.com/users` into host and path; `*` is the whole path space.
+fn split_host(pattern: &str) -> Result<(Option<&str>, &str), String> {
+    if pattern == "*" {
+        return Ok((None, "/*"));
+    }
+    if pattern.starts_with('/') {
+        return Ok((None, pattern));
+    }
+    match pattern.find('/') {
+        Some(slash) if slash > 0 => Ok((Some(&pattern[..slash]), &pattern[slash..])),
+        _ => Err(format!("route '{}' must start with '/' or a host name", pattern)),
+    }
+}
+
+/// Every combination of present and absent `:name?` segments.
+fn expand_optional(path: &str) -> Vec<String> {
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    let mut paths = vec![String::new()];
+    for segment in segments {
+        let optional = segment.starts_with(':') && segment.ends_with('?');
+        let required = if optional { &segment[..segment.len() - 1] } else { segment };
+        let mut next = Vec::new();
+        for path in &paths {
+            next.push(format!("{}/{}", path, required));
+            if optional {
+                next.push(path.clone());
+            }
+        }
+        paths = next;
+    }
+    for path in &mut paths {
+        if path.is_empty() {
+            path.push('/');
+        }
+    }
+    paths
+}
+
+fn parse_pattern(path: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    for (i, segment) in segments.iter().enumerate() {
+        text.push('/');
+        if let Some(param) = segment.strip_prefix(':') {
+            let (name, constraint) = match param.split_once('(') {
+                Some((name, rest)) => match rest.strip_suffix(')') {
+                    Some(constraint) => (name, Some(constraint.to_string())),
+                    None => return Err(format!("unclosed constraint in '{}'", segment)),
+                },
+                None => (param, None),
+            };
+            if name.is_empty() {
+                return Err(format!("parameter without a name in '{}'", path));
+            }
+            tokens.push(Token::Static(std::mem::take(&mut text)));
+            tokens.push(Token::Param { name: name.to_string(), constraint });
+        } else if let Some(name) = segment.strip_prefix('*') {
+            if i + 1 != segments.len() {
+                return Err(format!("'*{}' must be the last segment of '{}'", name, path));
+            }
+            tokens.push(Token::Static(std::mem::take(&mut text)));
+            tokens.push(Token::Splat(if name.is_empty() { "*".to_string() } else { name.to_string() }));
+        } else {
+            text.push_str(segment);
+        }
+    }
+    if !text.is_empty() {
+        tokens.push(Token::Static(text));
+    }
+    Ok(tokens)
+}
+
+/// Length in bytes of the common prefix, on a character boundary.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i)
+}
+
+/// Lowercase, without the port.
+fn normalize_host(host: &str) -> String {
+    let host = match host.rfind(':') {
+        // Not the inside of an IPv6 literal such as [::1]
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    };
+    host.to_ascii_lowercase()
+}
+
+/// Match a host against `example.com`, `*.example.com` (any subdomain) or
+/// `:name.example.com` (one label, captured).
+fn match_host(pattern: &str, host: &str) -> Option<Params> {
+    if let Some(suffix) = pattern.strip_prefix('*') {
+        return (host.len() > suffix.len() && host.ends_with(suffix)).then(Params::new);
+    }
+    if let Some(rest) = pattern.strip_prefix(':') {
+        let (name, suffix) = rest.split_once('.').map_or((rest, ""), |(n, _)| (n, &rest[n.len()..]));
+        let label = host.strip_suffix(suffix)?;
+        return (!label.is_empty() && !label.contains('.')).then(|| vec![(name.to_string(), label.to_string())]);
+    }
+    (pattern == host).then(Params::new)
+}
+
+/// Decode `%XX` escapes; invalid UTF-8 is replaced.
+pub(crate) fn percent_decode(s: &str) -> String {
+    if !s.contains('%') {
+        return s.to_string();
+    }
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                out.push(byte);
+                i += 3;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(routes: &[(&str, &str)]) -> Router<String> {
+        let mut router = Router::new();
+        for (method, pattern) in routes {
+            router.insert(method, pattern, format!("{} {}", method, pattern)).unwrap();
+        }
+        router
+    }
+
+    fn found(router: &Router<String>, method: &str, host: Option<&str>, path: &str) -> Option<(String, Params)> {
+        match router.find(method, host, path) {
+            Match::Found { value, params } => Some((value.clone(), params)),
+            _ => None,
+        }
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_static_beats_param_beats_splat() {
+        let r = router(&[
+            ("GET", "/users/me"),
+            ("GET", "/users/:id"),
+            ("GET", "/users/:id/posts/:post"),
+            ("GET", "/users/*rest"),
+            ("GET", "/usage"),
+        ]);
+        assert_eq!(found(&r, "GET", None, "/users/me").unwrap().0, "GET /users/me");
+        assert_eq!(found(&r, "GET", None, "/users/42").unwrap().1, params(&[("id", "42")]));
+        assert_eq!(
+            found(&r, "GET", None, "/users/42/posts/a%20b").unwrap().1,
+            params(&[("id", "42"), ("post", "a b")])
+        );
+        assert_eq!(found(&r, "GET", None, "/users/42/likes").unwrap().1, params(&[("rest", "42/likes")]));
+        assert_eq!(found(&r, "GET", None, "/usage").unwrap().0, "GET /usage");
+        assert!(found(&r, "GET", None, "/use").is_none());
+    }
+
+    #[test]
+    fn test_constraints_and_optional_segments() {
+        let r = router(&[("GET", "/items/:id(\\d+)"), ("GET", "/items/:slug"), ("GET", "/:lang?/about")]);
+        assert_eq!(found(&r, "GET", None, "/items/7").unwrap().0, "GET /items/:id(\\d+)");
+        assert_eq!(found(&r, "GET", None, "/items/seven").unwrap().1, params(&[("slug", "seven")]));
+        assert_eq!(found(&r, "GET", None, "/about").unwrap().1, params(&[]));
+        assert_eq!(found(&r, "GET", None, "/fr/about").unwrap().1, params(&[("lang", "fr")]));
+        assert!(Router::<()>::new().insert("GET", "/x/:id([)", ()).is_err());
+    }
+
+    #[test]
+    fn test_method_fallbacks_and_405() {
+        let r = router(&[("GET", "/a"), ("POST", "/a"), ("ALL", "/b"), ("WS", "/chat"), ("GET", "*")]);
+        assert_eq!(found(&r, "HEAD", None, "/a").unwrap().0, "GET /a");
+        assert_eq!(found(&r, "DELETE", None, "/b").unwrap().0, "ALL /b");
+        assert_eq!(found(&r, "GET", None, "/anything/else").unwrap().1, params(&[("*", "anything/else")]));
+        assert_eq!(r.find("DELETE", None, "/a"), Match::MethodNotAllowed(vec!["GET".into(), "POST".into()]));
+        assert_eq!(r.find("POST", None, "/chat"), Match::MethodNotAllowed(vec!["WS".into(), "GET".into()]));
+        assert_eq!(found(&r, "WS", None, "/chat").unwrap().0, "WS /chat");
+        assert_eq!(r.find("WS", None, "/a"), Match::MethodNotAllowed(vec!["GET".into(), "POST".into()]));
+    }
+
+    #[test]
+    fn test_host_routes() {
+        let r = router(&[
+            ("GET", "/"),
+            ("GET", "api.example.com/"),
+            ("GET", ":tenant.example.com/"),
+            ("GET", "*.example.org/"),
+        ]);
+        assert_eq!(found(&r, "GET", Some("API.example.com:8080"), "/").unwrap().0, "GET api.example.com/");
+        assert_eq!(found(&r, "GET", Some("acme.example.com"), "/").unwrap().1, params(&[("tenant", "acme")]));
+        assert_eq!(found(&r, "GET", Some("a.b.example.org"), "/").unwrap().0, "GET *.example.org/");
+        assert_eq!(found(&r, "GET", Some("a.b.example.com"), "/").unwrap().0, "GET /");
+        assert_eq!(found(&r, "GET", None, "/").unwrap().0, "GET /");
+    }
+
+    #[test]
+    fn test_radix_split_keeps_routes() {
+        let r = router(&[("GET", "/search"), ("GET", "/sea"), ("GET", "/séance"), ("GET", "/sé")]);
+        for path in ["/search", "/sea", "/séance", "/sé"] {
+            assert_eq!(found(&r, "GET", None, path).unwrap().0, format!("GET {}", path));
+        }
+        assert!(found(&r, "GET", None, "/se").is_none());
+    }
+}
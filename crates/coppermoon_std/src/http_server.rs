@@ -3,24 +3,28 @@
 //! Provides an HTTP server with concurrent connection handling.
 //! Connections are accepted and I/O is performed asynchronously on Tokio
 //! worker threads, while Lua handler execution is serialised on the main
-//! thread (Node.js-style event loop).
+//! thread (Node.js-style event loop). Connections are persistent (HTTP/1.1
+//! keep-alive) within the idle timeout and per-connection request limit.
+//! Streamed responses and Server-Sent Events are fed from the main thread
+//! through a channel, so a long-lived stream never blocks other requests.
+//! WebSocket routes upgrade the connection in place; frames are read and
+//! written on the connection task and their events run on the main thread.
 
 use coppermoon_core::Result;
-use coppermoon_core::event_loop;
-use mlua::{Lua, Table, Function, Value, RegistryKey};
+use coppermoon_core::{event_loop, hot, permissions, vm};
+use crate::http_bind::{self, BindOptions, Connection};
+use crate::http_body::{self, MultipartOptions, Part, PartContent, SpooledParts};
+use crate::http_router::{Match, Params, Router};
+use crate::http_static::{self, StaticMount, StaticMounts, StaticResponse};
+use crate::http_limits::Limits;
+use mlua::{AnyUserData, Lua, Table, Function, Value, RegistryKey, UserData, UserDataFields, UserDataMethods};
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
-
-// ---------------------------------------------------------------------------
-// Security limits
-// ---------------------------------------------------------------------------
-
-const MAX_REQUEST_LINE: usize = 8 * 1024;       // 8 KB
-const MAX_HEADER_LINE: usize = 8 * 1024;        // 8 KB per header
-const MAX_HEADER_COUNT: usize = 100;             // max number of headers
-const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;  // 10 MB
-const CONNECTION_TIMEOUT_SECS: u64 = 30;         // 30s idle timeout
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 
 // ---------------------------------------------------------------------------
 // Plain-data types that cross the channel boundary (no Lua objects)
@@ -29,9 +33,29 @@ const CONNECTION_TIMEOUT_SECS: u64 = 30;         // 30s idle timeout
 struct ParsedRequest {
     method: String,
     path: String,
+    /// e.g. `HTTP/1.1`
+    version: String,
     query_string: Option<String>,
     headers: HashMap<String, String>,
-    body: String,
+    /// Raw bytes, exactly as received (after chunked decoding)
+    body: Vec<u8>,
+    /// A multipart body the connection task spooled to disk (listen's
+    /// `uploads`) instead of reading it into `body`
+    parts: Option<SpooledParts>,
+    /// Set on HTTPS connections
+    tls: Option<TlsInfo>,
+}
+
+/// What the TLS handshake of a connection settled on.
+#[derive(Debug, Clone)]
+struct TlsInfo {
+    /// e.g. `TLSv1.3`
+    version: String,
+    alpn: Option<String>,
+    /// SNI host name the client asked for
+    server_name: Option<String>,
+    /// Verified client certificate (mTLS), PEM-encoded
+    client_cert: Option<String>,
 }
 
 struct HttpResponse {
@@ -39,11 +63,208 @@ struct HttpResponse {
     content_type: String,
     body: Vec<u8>,
     headers: Vec<(String, String)>,
+    /// Streamed body (`ctx:write`), sent with chunked encoding instead of `body`
+    stream: Option<tokio::sync::mpsc::Receiver<Chunk>>,
+    /// Accepted WebSocket upgrade; the connection switches protocols
+    upgrade: Option<WsSession>,
+}
+
+impl HttpResponse {
+    fn plain(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        HttpResponse {
+            status,
+            content_type: "text/plain".into(),
+            body: body.into(),
+            headers: Vec::new(),
+            stream: None,
+            upgrade: None,
+        }
+    }
+}
+
+/// A piece of a streamed response body.
+enum Chunk {
+    Data(Vec<u8>),
+    /// Send everything written so far to the client now
+    Flush,
+    /// Write an SSE keep-alive comment whenever the stream is idle this long
+    KeepAlive(Duration),
+    /// Run when the stream ends, whether the server or the client closed it
+    OnClose(Box<dyn FnOnce() + Send>),
+    /// The response failed midway: close the connection without ending the
+    /// body, so the client sees it is incomplete
+    Abort,
+}
+
+/// Chunks a streamed response may queue ahead of what the client has taken
+const STREAM_BUFFER: usize = 64;
+
+/// Per-server settings shared by its connection tasks.
+struct ServeConfig {
+    statics: Arc<[StaticMount]>,
+    #[cfg(feature = "http-compress")]
+    compression: Option<crate::http_compress::Compression>,
+    /// Limits and directory for multipart requests spooled while they are read
+    uploads: Option<MultipartOptions>,
 }
 
 /// Message sent from a connection task to the main Lua thread.
 type RequestMessage = (ParsedRequest, tokio::sync::oneshot::Sender<HttpResponse>);
 
+/// The answer channel of one request, shared by the dispatcher and the ctx
+/// methods that stream a response before the handler returns.
+#[derive(Clone)]
+struct Reply(Arc<Mutex<ReplyState>>);
+
+struct ReplyState {
+    /// Taken once the status line and headers have been handed over
+    head: Option<tokio::sync::oneshot::Sender<HttpResponse>>,
+    /// Open while a streamed body is in progress
+    chunks: Option<tokio::sync::mpsc::Sender<Chunk>>,
+    /// Keep the stream open after the handler returns
+    detached: bool,
+}
+
+impl Reply {
+    fn new(head: tokio::sync::oneshot::Sender<HttpResponse>) -> Self {
+        Reply(Arc::new(Mutex::new(ReplyState { head: Some(head), chunks: None, detached: false })))
+    }
+
+    /// Send a complete response. Returns false if one was already sent.
+    fn send(&self, response: HttpResponse) -> bool {
+        match self.0.lock().unwrap().head.take() {
+            // Ignore send error — the connection task may have dropped.
+            Some(head) => {
+                let _ = head.send(response);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start a streamed response with the head built by `head`, unless one
+    /// is already streaming. Returns false once the response is over.
+    fn start_stream(&self, head: impl FnOnce() -> mlua::Result<HttpResponse>) -> mlua::Result<bool> {
+        let mut state = self.0.lock().unwrap();
+        if state.chunks.is_some() {
+            return Ok(true);
+        }
+        let Some(sender) = state.head.take() else {
+            return Ok(false);
+        };
+        let mut response = head()?;
+        let (tx, rx) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        response.stream = Some(rx);
+        let _ = sender.send(response);
+        state.chunks = Some(tx);
+        Ok(true)
+    }
+
+    /// Queue a chunk of body data. Fails with `"closed"` if the stream ended
+    /// or the client is gone, and with `"full"` (dropping the chunk) while
+    /// the client is [`STREAM_BUFFER`] chunks behind.
+    fn write(&self, chunk: Chunk) -> std::result::Result<(), &'static str> {
+        let mut state = self.0.lock().unwrap();
+        let Some(tx) = &state.chunks else {
+            return Err("closed");
+        };
+        match tx.try_send(chunk) {
+            Ok(()) => Ok(()),
+            Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => Err("full"),
+            Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => {
+                state.chunks = None;
+                Err("closed")
+            }
+        }
+    }
+
+    /// Queue a control chunk (`Flush`, `KeepAlive`, `OnClose`), which must
+    /// not be dropped when the buffer is full.
+    fn push(&self, chunk: Chunk) {
+        let tx = self.0.lock().unwrap().chunks.clone();
+        match tx {
+            Some(tx) => send_eventually(tx, chunk),
+            None => run_on_close(chunk),
+        }
+    }
+
+    /// The sending side of the stream, while it is open.
+    fn sender(&self) -> Option<tokio::sync::mpsc::Sender<Chunk>> {
+        self.0.lock().unwrap().chunks.clone().filter(|tx| !tx.is_closed())
+    }
+
+    /// Queue an SSE event or comment and flush it. Returns false, dropping
+    /// it, if the client is gone or too far behind.
+    fn send_event(&self, text: String) -> bool {
+        let sent = self.write(Chunk::Data(text.into_bytes())).is_ok();
+        if sent {
+            self.push(Chunk::Flush);
+        }
+        sent
+    }
+
+    /// Whether a streamed response is in progress and the client is connected.
+    fn is_open(&self) -> bool {
+        self.0.lock().unwrap().chunks.as_ref().is_some_and(|tx| !tx.is_closed())
+    }
+
+    /// Keep the stream open after the handler returns.
+    fn detach(&self) {
+        self.0.lock().unwrap().detached = true;
+    }
+
+    /// Run `callback` when the stream ends, or now if it already has.
+    fn on_close(&self, callback: Box<dyn FnOnce() + Send>) {
+        self.push(Chunk::OnClose(callback));
+    }
+
+    /// End a streamed response, after `last` if given.
+    fn finish(&self, last: Option<Chunk>) {
+        let tx = self.0.lock().unwrap().chunks.take();
+        if let (Some(tx), Some(last)) = (tx, last) {
+            send_eventually(tx, last);
+        }
+    }
+
+    /// Cut a streamed response off without ending the body.
+    fn abort(&self) {
+        if let Some(tx) = self.0.lock().unwrap().chunks.take() {
+            send_eventually(tx, Chunk::Abort);
+        }
+    }
+
+    /// Called when the handler returns: end the stream unless it was detached.
+    fn end(&self) {
+        let mut state = self.0.lock().unwrap();
+        if !state.detached {
+            state.chunks = None;
+        }
+    }
+}
+
+/// Queue `chunk`, waiting on the runtime for room if the buffer is full.
+/// An `OnClose` that can't be delivered because the stream is over runs
+/// right away.
+fn send_eventually(tx: tokio::sync::mpsc::Sender<Chunk>, chunk: Chunk) {
+    match tx.try_send(chunk) {
+        Ok(()) => {}
+        Err(tokio::sync::mpsc::error::TrySendError::Full(chunk)) => {
+            coppermoon_core::spawn(async move {
+                if let Err(e) = tx.send(chunk).await {
+                    run_on_close(e.0);
+                }
+            });
+        }
+        Err(tokio::sync::mpsc::error::TrySendError::Closed(chunk)) => run_on_close(chunk),
+    }
+}
+
+fn run_on_close(chunk: Chunk) {
+    if let Chunk::OnClose(callback) = chunk {
+        callback();
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Module registration (unchanged API surface)
 // ---------------------------------------------------------------------------
@@ -111,51 +332,178 @@ fn server_new(lua: &Lua, _: ()) -> mlua::Result<Table> {
         Ok(server)
     })?)?;
 
+    // server:ws(path, handler): handler(ws, req) runs for each upgraded connection
+    server.set("ws", lua.create_function(|_, (server, path, handler): (Table, String, Function)| {
+        let routes: Table = server.get("_routes")?;
+        routes.set(format!("WS:{}", path), handler)?;
+        Ok(server)
+    })?)?;
+
+    // Open WebSockets per route, for the broadcast helpers
+    let sockets = WsSockets::default();
+    server.set("_ws", sockets.clone())?;
+
+    // server:broadcast(route, data, { binary?, except?, path? }) -> number of sockets
+    let broadcast_sockets = sockets.clone();
+    server.set("broadcast", lua.create_function(move |_, (_, route, data, options): (Table, String, Value, Option<Table>)| {
+        let (binary, except, path) = match &options {
+            Some(options) => {
+                let except = match options.get::<Option<AnyUserData>>("except")? {
+                    Some(ud) => Some(ud.borrow::<WebSocket>()?.0.id),
+                    None => None,
+                };
+                let binary = options.get::<Option<bool>>("binary")?.unwrap_or(false);
+                (binary, except, options.get::<Option<String>>("path")?)
+            }
+            None => (false, None, None),
+        };
+        let message = ws_message(data, binary)?;
+        let sent = broadcast_sockets
+            .on_route(&route, path.as_deref())
+            .iter()
+            .filter(|socket| Some(socket.id) != except)
+            .filter(|socket| socket.outgoing.send(message.clone()).is_ok())
+            .count();
+        Ok(sent)
+    })?)?;
+
+    // server:clients(route, { path? }) -> { ws, ... }
+    server.set("clients", lua.create_function(move |lua, (_, route, options): (Table, String, Option<Table>)| {
+        let path = match &options {
+            Some(options) => options.get::<Option<String>>("path")?,
+            None => None,
+        };
+        lua.create_sequence_from(sockets.on_route(&route, path.as_deref()).into_iter().map(WebSocket))
+    })?)?;
+
+    // server:static(prefix, dir, options?): files served without calling Lua
+    let statics = StaticMounts::default();
+    server.set("_static", statics.clone())?;
+    server.set("static", lua.create_function(move |_, (server, prefix, dir, options): (Table, String, String, Option<Table>)| {
+        statics.add(StaticMount::new(&prefix, &dir, options)?);
+        Ok(server)
+    })?)?;
+
     server.set("listen", lua.create_function(server_listen)?)?;
 
     Ok(server)
 }
 
 // ---------------------------------------------------------------------------
-// server:listen(port, callback?)
+// server:listen(target, options?, callback?)
 // ---------------------------------------------------------------------------
 
-fn server_listen(lua: &Lua, (server, port, callback): (Table, u16, Option<Function>)) -> mlua::Result<()> {
-    server.set("_port", port)?;
+/// `target` is a port, an address string or an options table (see
+/// [`BindOptions::from_lua`]); listen(port, callback) still works.
+fn server_listen(lua: &Lua, (server, target, options, callback): (Table, Value, Value, Option<Function>)) -> mlua::Result<()> {
+    let (target, options, callback) = match (target, options) {
+        (Value::Table(options), Value::Function(callback)) => (Value::Nil, Some(options), Some(callback)),
+        (Value::Table(options), Value::Nil) => (Value::Nil, Some(options), callback),
+        (target, Value::Table(options)) => (target, Some(options), callback),
+        (target, Value::Function(callback)) => (target, None, Some(callback)),
+        (target, Value::Nil) => (target, None, callback),
+        (_, other) => {
+            return Err(mlua::Error::runtime(format!(
+                "listen: expected an options table or callback, got {}",
+                other.type_name()
+            )))
+        }
+    };
+    let bind_options = BindOptions::from_lua(&target, options.as_ref())?;
+
+    #[cfg(feature = "http-tls")]
+    let tls = match &options {
+        Some(options) => crate::http_tls::TlsOptions::from_table(options)?,
+        None => None,
+    }
+    .map(|options| crate::http_tls::Acceptor::new(options).map_err(mlua::Error::runtime))
+    .transpose()?;
+    #[cfg(feature = "http-tls")]
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    #[cfg(not(feature = "http-tls"))]
+    if let Some(options) = &options {
+        if options.contains_key("cert")? {
+            return Err(mlua::Error::runtime("listen: TLS needs coppermoon_std's http-tls feature"));
+        }
+    }
+    #[cfg(not(feature = "http-tls"))]
+    let scheme = "http";
+
+    #[cfg(feature = "http-compress")]
+    let compression = match &options {
+        Some(options) => crate::http_compress::Compression::from_options(options)?,
+        None => None,
+    };
+    #[cfg(not(feature = "http-compress"))]
+    if let Some(options) = &options {
+        if options.contains_key("compress")? {
+            return Err(mlua::Error::runtime("listen: compression needs coppermoon_std's http-compress feature"));
+        }
+    }
+
+    let uploads = match &options {
+        Some(options) => upload_options(lua, options)?,
+        None => None,
+    };
 
     let routes: Table = server.get("_routes")?;
 
     // Store route handlers in the Lua registry so they stay alive.
-    let mut route_handlers: HashMap<String, RegistryKey> = HashMap::new();
-    for pair in routes.pairs::<String, Function>() {
-        let (key, handler) = pair?;
-        let reg_key = lua.create_registry_value(handler)?;
-        route_handlers.insert(key, reg_key);
+    let mut router = collect_route_handlers(lua, &routes)?;
+    let mut hot_generation = hot::generation(lua);
+    let sockets = server.get::<AnyUserData>("_ws")?.borrow::<WsSockets>()?.clone();
+
+    // A Unix socket is a file: binding creates it and may replace a stale one
+    if matches!(bind_options.address, http_bind::Address::Unix { .. }) {
+        permissions::check_fs_write(lua, "listening on a Unix socket")?;
     }
 
-    let addr = format!("127.0.0.1:{}", port);
+    // Bind here so errors reach the script and port 0 resolves before the callback
+    let listener = http_bind::bind(&bind_options)
+        .map_err(|e| mlua::Error::runtime(format!("Failed to listen on {}: {}", bind_options.address, e)))?;
+    let port = listener.port();
+    let addr = listener.describe();
+    server.set("_port", port)?;
+    server.set("_address", addr.as_str())?;
+    let limits = lua.app_data_ref::<Limits>().map(|l| *l).unwrap_or_default();
+    let config = Arc::new(ServeConfig {
+        statics: server.get::<AnyUserData>("_static")?.borrow::<StaticMounts>()?.snapshot(),
+        #[cfg(feature = "http-compress")]
+        compression,
+        uploads,
+    });
 
     // Create a std::sync::mpsc channel for request dispatch.
     // The main Lua thread receives on this channel (blocking, NOT inside
     // a Tokio context) so that Lua handlers can freely call block_on().
     let (tx, rx) = std::sync::mpsc::channel::<RequestMessage>();
 
+    #[cfg(feature = "http-tls")]
+    if let Some(tls) = &tls {
+        tls.watch();
+    }
+
     // Spawn the async accept loop on the Tokio runtime.
-    let addr_clone = addr.clone();
     coppermoon_core::spawn(async move {
-        let listener = match tokio::net::TcpListener::bind(&addr_clone).await {
+        let listener = match listener.into_async() {
             Ok(l) => l,
             Err(e) => {
-                eprintln!("Failed to bind to {}: {}", addr_clone, e);
+                eprintln!("Failed to register listener: {}", e);
                 return;
             }
         };
 
         loop {
             match listener.accept().await {
-                Ok((stream, _peer)) => {
+                Ok(stream) => {
                     let tx = tx.clone();
-                    tokio::spawn(handle_connection(stream, tx));
+                    let config = Arc::clone(&config);
+                    #[cfg(feature = "http-tls")]
+                    if let Some(tls) = &tls {
+                        tokio::spawn(handle_tls_connection(tls.clone(), stream, tx, config, limits));
+                        continue;
+                    }
+                    tokio::spawn(handle_connection(stream, None, tx, config, limits));
                 }
                 Err(e) => {
                     eprintln!("Accept error: {}", e);
@@ -164,24 +512,34 @@ fn server_listen(lua: &Lua, (server, port, callback): (Table, u16, Option<Functi
         }
     });
 
-    // Notify callback if provided
+    // Notify callback if provided: (port, address); port is nil on a Unix socket
     if let Some(cb) = callback {
-        cb.call::<()>(port)?;
+        cb.call::<()>((port, addr.as_str()))?;
     }
 
-    println!("CopperMoon server listening on http://{}", addr);
+    println!("CopperMoon server listening on {}://{}", scheme, addr);
 
     // ---------- Main Lua event loop ----------
     // We use recv_timeout so we can also drain pending timers.
     loop {
-        // Process any ready timer callbacks between requests.
+        // Process any ready timer callbacks and queued main-thread tasks
+        // (e.g. remote REPL evaluations) between requests.
         drain_timers(lua);
+        event_loop::run_main_thread_tasks(lua);
+
+        // After a hot module reload, route to the new handler functions
+        hot::poll(lua);
+        if hot::generation(lua) != hot_generation {
+            hot_generation = hot::generation(lua);
+            match collect_route_handlers(lua, &routes) {
+                Ok(handlers) => router = handlers,
+                Err(e) => eprintln!("Failed to refresh routes after reload: {}", e),
+            }
+        }
 
         match rx.recv_timeout(Duration::from_millis(10)) {
             Ok((request, resp_tx)) => {
-                let response = dispatch_to_lua(lua, &request, &route_handlers);
-                // Ignore send error — the connection task may have dropped.
-                let _ = resp_tx.send(response);
+                dispatch_to_lua(lua, &request, &router, &sockets, &Reply::new(resp_tx));
             }
             Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
             Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
@@ -191,22 +549,98 @@ fn server_listen(lua: &Lua, (server, port, callback): (Table, u16, Option<Functi
     Ok(())
 }
 
+/// A route's handler, with the pattern it was registered under.
+struct Route {
+    pattern: String,
+    handler: RegistryKey,
+}
+
+/// Build the router from the `METHOD:pattern` keys of `_routes`, holding
+/// each handler in the registry. Handlers replaced by a hot module reload
+/// are updated to their newest version, in the route table as well.
+fn collect_route_handlers(lua: &Lua, routes: &Table) -> mlua::Result<Router<Rc<Route>>> {
+    let mut router = Router::new();
+    for pair in routes.pairs::<String, Function>() {
+        let (key, mut handler) = pair?;
+        if let Some(current) = hot::replacement(lua, &handler) {
+            routes.set(key.as_str(), current.clone())?;
+            handler = current;
+        }
+        let (method, pattern) = key.split_once(':').unwrap_or(("ALL", key.as_str()));
+        let route = Route { pattern: pattern.to_string(), handler: lua.create_registry_value(handler)? };
+        router
+            .insert(method, pattern, Rc::new(route))
+            .map_err(|e| mlua::Error::runtime(format!("Invalid route '{}': {}", pattern, e)))?;
+    }
+    Ok(router)
+}
+
 // ---------------------------------------------------------------------------
 // Async connection handler (runs on a Tokio worker thread)
 // ---------------------------------------------------------------------------
 
-async fn handle_connection(
-    stream: tokio::net::TcpStream,
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    tls: Option<TlsInfo>,
     tx: std::sync::mpsc::Sender<RequestMessage>,
+    config: Arc<ServeConfig>,
+    limits: Limits,
 ) {
-    if let Err(e) = handle_connection_inner(stream, tx).await {
+    if let Err(e) = handle_connection_inner(stream, tls, tx, config, limits).await {
         eprintln!("Connection error: {}", e);
     }
 }
 
+/// Complete the TLS handshake (within the request timeout), then serve the
+/// connection as usual.
+#[cfg(feature = "http-tls")]
+async fn handle_tls_connection(
+    tls: crate::http_tls::Acceptor,
+    stream: Box<dyn Connection>,
+    tx: std::sync::mpsc::Sender<RequestMessage>,
+    config: Arc<ServeConfig>,
+    limits: Limits,
+) {
+    match tokio::time::timeout(Duration::from_secs(limits.timeout_secs), tls.accept(stream)).await {
+        Ok(Ok(stream)) => {
+            let info = tls_info(stream.get_ref().1);
+            handle_connection(stream, Some(info), tx, config, limits).await;
+        }
+        Ok(Err(e)) => eprintln!("TLS handshake failed: {}", e),
+        Err(_timeout) => {}
+    }
+}
+
+#[cfg(feature = "http-tls")]
+fn tls_info(conn: &tokio_rustls::rustls::ServerConnection) -> TlsInfo {
+    TlsInfo {
+        version: conn
+            .protocol_version()
+            .map(|v| format!("{:?}", v).replace('_', "."))
+            .unwrap_or_default(),
+        alpn: conn.alpn_protocol().map(|p| String::from_utf8_lossy(p).into_owned()),
+        server_name: conn.server_name().map(str::to_string),
+        client_cert: conn.peer_certificates().and_then(|certs| certs.first()).map(|cert| der_to_pem(cert)),
+    }
+}
+
+/// PEM-encode a DER certificate.
+#[cfg(feature = "http-tls")]
+fn der_to_pem(der: &[u8]) -> String {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    let encoded = STANDARD.encode(der);
+    let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str("-----END CERTIFICATE-----\n");
+    pem
+}
+
 /// Read a line with a size limit. Returns `None` if the limit is exceeded.
-async fn read_limited_line(
-    reader: &mut tokio::io::BufReader<tokio::net::tcp::ReadHalf<'_>>,
+async fn read_limited_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
     limit: usize,
 ) -> std::result::Result<Option<String>, std::io::Error> {
     let mut line = String::new();
@@ -225,79 +659,202 @@ async fn read_limited_line(
     Ok(Some(line))
 }
 
-async fn handle_connection_inner(
-    mut stream: tokio::net::TcpStream,
+/// Serve requests on one connection until either side closes it.
+///
+/// Requests are handled one at a time, so pipelined requests (sent before
+/// the previous response arrived) wait in the read buffer and their
+/// responses go out in request order.
+async fn handle_connection_inner<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    tls: Option<TlsInfo>,
     tx: std::sync::mpsc::Sender<RequestMessage>,
+    config: Arc<ServeConfig>,
+    limits: Limits,
 ) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (reader, mut writer) = stream.split();
+    let (reader, mut writer) = tokio::io::split(stream);
     let mut reader = tokio::io::BufReader::new(reader);
+    let mut served = 0usize;
 
-    // Apply connection timeout to the entire request parsing phase.
-    let result = tokio::time::timeout(
-        Duration::from_secs(CONNECTION_TIMEOUT_SECS),
-        parse_request(&mut reader),
-    )
-    .await;
+    loop {
+        // Between requests, wait for the next one only as long as keep-alive allows
+        if served > 0 {
+            let next = tokio::time::timeout(Duration::from_secs(limits.keep_alive_secs), reader.fill_buf()).await;
+            match next {
+                Ok(Ok(buf)) if !buf.is_empty() => {}
+                _ => return Ok(()),
+            }
+        }
 
-    let request = match result {
-        Ok(Ok(req)) => req,
-        Ok(Err(e)) => {
-            // Parse error — determine appropriate status code
-            let err_msg = e.to_string();
-            let (status, msg) = if err_msg.contains("line too long") {
-                (414u16, "URI Too Long")
-            } else if err_msg.contains("Header too long") {
-                (431u16, "Request Header Fields Too Large")
-            } else if err_msg.contains("Too many headers") {
-                (431u16, "Request Header Fields Too Large")
-            } else if err_msg.contains("Body too large") {
-                (413u16, "Payload Too Large")
-            } else {
-                (400u16, "Bad Request")
-            };
-            let resp = build_response_bytes(status as u16, "text/plain", msg, &[]);
-            writer.write_all(&resp).await.ok();
-            return Ok(());
+        let mut request = match parse_request(&mut reader, &limits, config.uploads.as_ref()).await {
+            Ok(req) => req,
+            Err(e) => {
+                // Parse error — determine appropriate status code
+                let err_msg = e.to_string();
+                let (status, msg) = if err_msg.contains("Request timeout") {
+                    (408u16, "Request Timeout")
+                } else if err_msg.contains("line too long") {
+                    (414u16, "URI Too Long")
+                } else if err_msg.contains("Header too long") {
+                    (431u16, "Request Header Fields Too Large")
+                } else if err_msg.contains("Too many headers") {
+                    (431u16, "Request Header Fields Too Large")
+                } else if err_msg.contains("Body too large") || err_msg.contains("larger than") {
+                    (413u16, "Payload Too Large")
+                } else {
+                    (400u16, "Bad Request")
+                };
+                let resp = build_response_bytes(status as u16, "text/plain", msg, &[]);
+                writer.write_all(&resp).await.ok();
+                return Ok(());
+            }
+        };
+
+        served += 1;
+        request.tls = tls.clone();
+        let mut keep_alive = wants_keep_alive(&request) && served < limits.max_requests_per_connection;
+
+        // server:static mounts are answered here; missing files go on to Lua
+        if !config.statics.is_empty() {
+            let file = http_static::serve(
+                &config.statics,
+                &request.method,
+                &request.path,
+                request.query_string.as_deref(),
+                &request.headers,
+            )
+            .await;
+            if let Some(file) = file {
+                let head_only = request.method == "HEAD";
+                if write_static(&mut writer, file, head_only, keep_alive.then_some(limits.keep_alive_secs)).await.is_err()
+                    || !keep_alive
+                {
+                    return Ok(());
+                }
+                continue;
+            }
+        }
+
+        // Send to main Lua thread and wait for response.
+        let ws_key = websocket_key(&request).map(str::to_string);
+        #[cfg(feature = "http-compress")]
+        let accept_encoding = request.headers.get("accept-encoding").cloned();
+        let is_head = request.method == "HEAD";
+        let chunked_ok = request.version != "HTTP/1.0";
+        let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+        tx.send((request, resp_tx))?;
+
+        match resp_rx.await {
+            Ok(response) => {
+                #[cfg(feature = "http-compress")]
+                let response = compress_response(&config, accept_encoding, response).await;
+                if let (Some(session), Some(key)) = (response.upgrade, &ws_key) {
+                    serve_websocket(&mut reader, &mut writer, key, session, limits.max_body_size).await;
+                    return Ok(());
+                }
+                // A handler can end the connection with `Connection: close`
+                keep_alive &= !response.headers.iter().any(|(k, v)| {
+                    k.eq_ignore_ascii_case("connection") && has_token(v, "close")
+                });
+                if let Some(chunks) = response.stream {
+                    // HTTP/1.0 has no chunked encoding: the body ends when the connection does
+                    keep_alive &= chunked_ok;
+                    let length = if chunked_ok { BodyLength::Chunked } else { BodyLength::UntilClose };
+                    let head = build_head(
+                        response.status,
+                        &response.content_type,
+                        length,
+                        &response.headers,
+                        keep_alive.then_some(limits.keep_alive_secs),
+                    );
+                    if write_stream(&mut writer, head, chunks, chunked_ok, is_head).await.is_err() {
+                        return Ok(());
+                    }
+                    if !keep_alive {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                let bytes = build_response_bytes_ex(
+                    response.status,
+                    &response.content_type,
+                    &response.body,
+                    &response.headers,
+                    is_head,
+                    keep_alive.then_some(limits.keep_alive_secs),
+                );
+                if writer.write_all(&bytes).await.is_err() || writer.flush().await.is_err() {
+                    return Ok(());
+                }
+            }
+            Err(_) => {
+                let bytes = build_response_bytes(500, "text/plain", "Internal Server Error", &[]);
+                writer.write_all(&bytes).await.ok();
+                return Ok(());
+            }
         }
-        Err(_timeout) => {
-            let resp = build_response_bytes(408, "text/plain", "Request Timeout", &[]);
-            writer.write_all(&resp).await.ok();
+
+        if !keep_alive {
             return Ok(());
         }
-    };
+    }
+}
+
+/// Whether the client asked to keep the connection open: the default for
+/// HTTP/1.1 unless it sent `Connection: close`, opt-in with
+/// `Connection: keep-alive` for HTTP/1.0.
+fn wants_keep_alive(request: &ParsedRequest) -> bool {
+    let connection = request.headers.get("connection").map(String::as_str).unwrap_or("");
+    if request.version == "HTTP/1.0" {
+        has_token(connection, "keep-alive")
+    } else {
+        !has_token(connection, "close")
+    }
+}
 
-    // Send to main Lua thread and wait for response.
-    let is_head = request.method == "HEAD";
-    let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
-    tx.send((request, resp_tx))?;
+/// Whether a comma-separated header value contains `token` (case-insensitive).
+fn has_token(value: &str, token: &str) -> bool {
+    value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+/// How a request body is delimited.
+#[derive(Debug, Clone, Copy)]
+enum Framing {
+    Length(usize),
+    Chunked,
+}
 
-    match resp_rx.await {
-        Ok(response) => {
-            let bytes = build_response_bytes_ex(
-                response.status,
-                &response.content_type,
-                &response.body,
-                &response.headers,
-                is_head,
-            );
-            writer.write_all(&bytes).await.ok();
-            writer.flush().await.ok();
+/// Parse an HTTP request with enforced size limits. It must arrive within
+/// the request timeout, except for a multipart body spooled to disk
+/// (`uploads`), which only must not stall for that long.
+async fn parse_request<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limits: &Limits,
+    uploads: Option<&MultipartOptions>,
+) -> std::result::Result<ParsedRequest, Box<dyn std::error::Error + Send + Sync>> {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(limits.timeout_secs);
+    let (mut request, framing) = tokio::time::timeout_at(deadline, read_head(reader, limits))
+        .await
+        .map_err(|_| "Request timeout")??;
+    match uploads.zip(multipart_boundary(&request.headers)) {
+        Some((options, boundary)) => {
+            request.parts = Some(read_multipart(reader, framing, &boundary, options, limits).await?);
         }
-        Err(_) => {
-            let bytes = build_response_bytes(500, "text/plain", "Internal Server Error", &[]);
-            writer.write_all(&bytes).await.ok();
+        None => {
+            request.body = tokio::time::timeout_at(deadline, read_body(reader, framing, limits))
+                .await
+                .map_err(|_| "Request timeout")??;
         }
     }
-
-    Ok(())
+    Ok(request)
 }
 
-/// Parse an HTTP request with enforced size limits.
-async fn parse_request(
-    reader: &mut tokio::io::BufReader<tokio::net::tcp::ReadHalf<'_>>,
-) -> std::result::Result<ParsedRequest, Box<dyn std::error::Error + Send + Sync>> {
+/// Read the request line and headers, and how the body is framed.
+async fn read_head<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limits: &Limits,
+) -> std::result::Result<(ParsedRequest, Framing), Box<dyn std::error::Error + Send + Sync>> {
     // --- Parse request line (bounded) ---
-    let request_line = read_limited_line(reader, MAX_REQUEST_LINE)
+    let request_line = read_limited_line(reader, limits.max_request_line)
         .await?
         .ok_or("Request line too long")?;
 
@@ -308,6 +865,8 @@ async fn parse_request(
 
     let method = parts[0].to_uppercase();
     let full_path = parts[1].to_string();
+    // HTTP/0.9-style request lines have no version; treat them as 1.0
+    let version = parts.get(2).map_or("HTTP/1.0", |v| v).to_uppercase();
 
     let (path, query_string) = if let Some(pos) = full_path.find('?') {
         (full_path[..pos].to_string(), Some(full_path[pos + 1..].to_string()))
@@ -317,10 +876,10 @@ async fn parse_request(
 
     // --- Parse headers (bounded count and size) ---
     let mut headers: HashMap<String, String> = HashMap::new();
-    let mut content_length: usize = 0;
+    let mut content_length: Option<usize> = None;
 
-    for _ in 0..MAX_HEADER_COUNT + 1 {
-        let line = read_limited_line(reader, MAX_HEADER_LINE)
+    for _ in 0..limits.max_header_count + 1 {
+        let line = read_limited_line(reader, limits.max_header_line)
             .await?
             .ok_or("Header too long")?;
 
@@ -328,33 +887,355 @@ async fn parse_request(
             break;
         }
 
-        if headers.len() >= MAX_HEADER_COUNT {
+        if headers.len() >= limits.max_header_count {
             return Err("Too many headers".into());
         }
 
         if let Some((key, value)) = line.trim().split_once(':') {
             let key = key.trim().to_lowercase();
             let value = value.trim().to_string();
+            // Framing the server and a proxy could read differently is
+            // rejected (RFC 9112 §6.3): the rest of the connection would be
+            // parsed out of step
             if key == "content-length" {
-                content_length = value.parse().unwrap_or(0);
+                for item in value.split(',') {
+                    let item = item.trim();
+                    if item.is_empty() || !item.bytes().all(|b| b.is_ascii_digit()) {
+                        return Err("Bad Content-Length".into());
+                    }
+                    let length: usize = item.parse().map_err(|_| "Body too large")?;
+                    if content_length.is_some_and(|seen| seen != length) {
+                        return Err("Bad Content-Length: conflicting values".into());
+                    }
+                    content_length = Some(length);
+                }
+            }
+            if key == "transfer-encoding" {
+                if let Some(previous) = headers.get_mut(&key) {
+                    previous.push_str(", ");
+                    previous.push_str(&value);
+                    continue;
+                }
             }
             headers.insert(key, value);
         }
     }
 
-    // --- Read body (bounded) ---
-    let body = if content_length > 0 {
-        if content_length > MAX_BODY_SIZE {
+    // --- Body framing ---
+    let chunked = match headers.get("transfer-encoding") {
+        None => false,
+        Some(_) if content_length.is_some() => {
+            return Err("Bad request: both Transfer-Encoding and Content-Length".into());
+        }
+        // chunked must be the final coding, or the body has no end
+        Some(te) if te.rsplit(',').next().is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked")) => true,
+        Some(_) => return Err("Bad Transfer-Encoding".into()),
+    };
+    let framing = if chunked { Framing::Chunked } else { Framing::Length(content_length.unwrap_or(0)) };
+
+    let request = ParsedRequest {
+        method,
+        path,
+        version,
+        query_string,
+        headers,
+        body: Vec::new(),
+        parts: None,
+        tls: None,
+    };
+    Ok((request, framing))
+}
+
+/// Read a whole body into memory, within `max_body_size`.
+async fn read_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    framing: Framing,
+    limits: &Limits,
+) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    match framing {
+        Framing::Chunked => read_chunked_body(reader, limits).await,
+        Framing::Length(0) => Ok(Vec::new()),
+        Framing::Length(len) => {
+            if len > limits.max_body_size {
+                return Err("Body too large".into());
+            }
+            let mut buf = vec![0u8; len];
+            reader.read_exact(&mut buf).await?;
+            Ok(buf)
+        }
+    }
+}
+
+/// Decode a `Transfer-Encoding: chunked` body. Chunk extensions and
+/// trailers are ignored.
+async fn read_chunked_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limits: &Limits,
+) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut body = Vec::new();
+    loop {
+        let size = read_chunk_size(reader, limits).await?;
+        if size == 0 {
+            break;
+        }
+        if size > limits.max_body_size - body.len() {
             return Err("Body too large".into());
         }
-        let mut buf = vec![0u8; content_length];
-        reader.read_exact(&mut buf).await?;
-        String::from_utf8_lossy(&buf).to_string()
-    } else {
-        String::new()
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await?;
+        read_chunk_end(reader).await?;
+    }
+    read_trailers(reader, limits).await?;
+    Ok(body)
+}
+
+/// The size line of the next chunk.
+async fn read_chunk_size<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limits: &Limits,
+) -> std::result::Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let line = read_limited_line(reader, limits.max_header_line)
+        .await?
+        .ok_or("Chunk size line too long")?;
+    let size = line.trim().split(';').next().unwrap_or("").trim();
+    usize::from_str_radix(size, 16).map_err(|_| "Bad chunk size".into())
+}
+
+/// The line break after the data of a chunk.
+async fn read_chunk_end<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut crlf = [0u8; 2];
+    reader.read_exact(&mut crlf).await?;
+    if &crlf != b"\r\n" {
+        return Err("Bad chunk terminator".into());
+    }
+    Ok(())
+}
+
+/// Trailer fields, up to the blank line.
+async fn read_trailers<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limits: &Limits,
+) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    loop {
+        let line = read_limited_line(reader, limits.max_header_line)
+            .await?
+            .ok_or("Header too long")?;
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// The boundary of a `multipart/form-data` request.
+fn multipart_boundary(headers: &HashMap<String, String>) -> Option<String> {
+    let (media, params) = http_body::media_type(headers.get("content-type")?);
+    if media != "multipart/form-data" {
+        return None;
+    }
+    params.into_iter().find(|(key, _)| key == "boundary").map(|(_, value)| value)
+}
+
+/// Largest piece of a spooled body read at once
+const UPLOAD_PIECE: usize = 64 * 1024;
+
+/// Read a multipart body a piece at a time, writing its file parts to disk
+/// as they arrive; only text fields are held in memory. The body as a whole
+/// has no deadline and no `max_body_size`, but `options.max_size` applies
+/// and no read may stall for longer than the request timeout.
+async fn read_multipart<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    framing: Framing,
+    boundary: &str,
+    options: &MultipartOptions,
+    limits: &Limits,
+) -> std::result::Result<SpooledParts, Box<dyn std::error::Error + Send + Sync>> {
+    let mut parser = http_body::MultipartParser::new(boundary, options.clone());
+    let mut piece = vec![0u8; UPLOAD_PIECE];
+    // Bytes left in the body, or in the current chunk
+    let mut left = match framing {
+        Framing::Length(len) => len,
+        Framing::Chunked => 0,
+    };
+    loop {
+        if left == 0 {
+            if let Framing::Length(_) = framing {
+                break;
+            }
+            left = within(limits, read_chunk_size(reader, limits)).await?;
+            if left == 0 {
+                within(limits, read_trailers(reader, limits)).await?;
+                break;
+            }
+        }
+        let len = left.min(UPLOAD_PIECE);
+        within(limits, reader.read_exact(&mut piece[..len])).await?;
+        left -= len;
+        parser.feed(&piece[..len])?;
+        if left == 0 && matches!(framing, Framing::Chunked) {
+            within(limits, read_chunk_end(reader)).await?;
+        }
+    }
+    Ok(SpooledParts(parser.finish()?))
+}
+
+/// Run one read of a request, failing if it stalls for the request timeout.
+async fn within<T, E>(
+    limits: &Limits,
+    read: impl std::future::Future<Output = std::result::Result<T, E>>,
+) -> std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    match tokio::time::timeout(Duration::from_secs(limits.timeout_secs), read).await {
+        Ok(result) => result.map_err(Into::into),
+        Err(_) => Err("Request timeout".into()),
+    }
+}
+
+/// Bodies at least this large are compressed on the blocking pool so they
+/// don't stall the other connections on this worker
+#[cfg(feature = "http-compress")]
+const BLOCKING_COMPRESS_SIZE: usize = 64 * 1024;
+
+/// Compress a complete response if the server is configured to.
+#[cfg(feature = "http-compress")]
+async fn compress_response(
+    config: &ServeConfig,
+    accept_encoding: Option<String>,
+    mut response: HttpResponse,
+) -> HttpResponse {
+    let Some(compression) = config.compression.as_ref().filter(|_| response.stream.is_none()) else {
+        return response;
     };
+    let body = std::mem::take(&mut response.body);
+    if body.len() < BLOCKING_COMPRESS_SIZE {
+        response.body = compression.apply(
+            accept_encoding.as_deref(),
+            response.status,
+            &response.content_type,
+            &mut response.headers,
+            body,
+        );
+        return response;
+    }
+
+    let compression = compression.clone();
+    let (status, content_type) = (response.status, response.content_type.clone());
+    let mut headers = std::mem::take(&mut response.headers);
+    let compressed = tokio::task::spawn_blocking(move || {
+        let body = compression.apply(accept_encoding.as_deref(), status, &content_type, &mut headers, body);
+        (headers, body)
+    })
+    .await;
+    (response.headers, response.body) = compressed.expect("response compression panicked");
+    response
+}
+
+/// Write a `server:static` response, copying the file in pieces.
+async fn write_static<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    response: StaticResponse,
+    head_only: bool,
+    keep_alive: Option<u64>,
+) -> std::io::Result<()> {
+    let length = BodyLength::Fixed(response.length as usize);
+    let head = build_head(response.status, &response.content_type, length, &response.headers, keep_alive);
+    writer.write_all(head.as_bytes()).await?;
+    if let (Some((mut file, offset)), false) = (response.body, head_only) {
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        tokio::io::copy(&mut file.take(response.length), writer).await?;
+    }
+    writer.flush().await
+}
+
+/// Write a streamed response: the head, then each chunk as it arrives
+/// until the main thread ends the stream. Data is buffered until a
+/// `Chunk::Flush`, a full buffer or the end of the stream. `OnClose`
+/// callbacks run however the stream ends, including a failed write. A
+/// `Chunk::Abort` fails the stream without the terminating chunk, so the
+/// caller closes the connection.
+async fn write_stream<W: AsyncWrite + Unpin>(
+    writer: W,
+    head: String,
+    mut chunks: tokio::sync::mpsc::Receiver<Chunk>,
+    chunked: bool,
+    head_only: bool,
+) -> std::io::Result<()> {
+    let mut on_close = Vec::new();
+    let result = write_chunks(writer, head, &mut chunks, chunked, head_only, &mut on_close).await;
+    // Later writes from Lua now fail instead of queueing
+    drop(chunks);
+    for callback in on_close {
+        callback();
+    }
+    result
+}
+
+async fn write_chunks<W: AsyncWrite + Unpin>(
+    writer: W,
+    head: String,
+    chunks: &mut tokio::sync::mpsc::Receiver<Chunk>,
+    chunked: bool,
+    head_only: bool,
+    on_close: &mut Vec<Box<dyn FnOnce() + Send>>,
+) -> std::io::Result<()> {
+    let mut out = tokio::io::BufWriter::new(writer);
+    out.write_all(head.as_bytes()).await?;
+    out.flush().await?;
+    let mut ping: Option<tokio::time::Interval> = None;
+    loop {
+        let chunk = match &mut ping {
+            Some(interval) => tokio::select! {
+                chunk = chunks.recv() => chunk,
+                _ = interval.tick() => {
+                    // Also how a vanished client is noticed on a quiet stream
+                    write_data(&mut out, b": keep-alive\n\n", chunked).await?;
+                    out.flush().await?;
+                    continue;
+                }
+            },
+            None => chunks.recv().await,
+        };
+        let Some(chunk) = chunk else { break };
+        match chunk {
+            // An empty chunk would end a chunked body
+            Chunk::Data(data) if head_only || data.is_empty() => {}
+            Chunk::Data(data) => write_data(&mut out, &data, chunked).await?,
+            Chunk::Flush => out.flush().await?,
+            Chunk::KeepAlive(period) if head_only || period.is_zero() => {}
+            Chunk::KeepAlive(period) => {
+                let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                ping = Some(interval);
+            }
+            Chunk::OnClose(callback) => on_close.push(callback),
+            Chunk::Abort => {
+                out.flush().await?;
+                return Err(std::io::Error::other("streamed response aborted"));
+            }
+        }
+        // Data sent since the last tick keeps the stream alive
+        if let Some(interval) = &mut ping {
+            interval.reset();
+        }
+    }
+    if chunked && !head_only {
+        out.write_all(b"0\r\n\r\n").await?;
+    }
+    out.flush().await
+}
 
-    Ok(ParsedRequest { method, path, query_string, headers, body })
+async fn write_data<W: AsyncWrite + Unpin>(out: &mut W, data: &[u8], chunked: bool) -> std::io::Result<()> {
+    if chunked {
+        out.write_all(format!("{:x}\r\n", data.len()).as_bytes()).await?;
+        out.write_all(data).await?;
+        out.write_all(b"\r\n").await
+    } else {
+        out.write_all(data).await
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -364,62 +1245,172 @@ async fn parse_request(
 fn dispatch_to_lua(
     lua: &Lua,
     request: &ParsedRequest,
-    route_handlers: &HashMap<String, RegistryKey>,
-) -> HttpResponse {
-    match dispatch_to_lua_inner(lua, request, route_handlers) {
-        Ok(resp) => resp,
-        Err(e) => {
-            eprintln!("Handler error: {}", e);
-            HttpResponse {
-                status: 500,
-                content_type: "text/plain".into(),
-                body: format!("Internal Server Error: {}", e).into_bytes(),
-                headers: Vec::new(),
-            }
+    router: &Router<Rc<Route>>,
+    sockets: &WsSockets,
+    reply: &Reply,
+) {
+    if let Err(e) = dispatch_to_lua_inner(lua, request, router, sockets, reply) {
+        eprintln!("Handler error: {}", e);
+        // Too late for a 500 once a streamed response has started: cut it off
+        if !reply.send(HttpResponse::plain(500, format!("Internal Server Error: {}", e))) {
+            reply.abort();
         }
     }
+    reply.end();
 }
 
 fn dispatch_to_lua_inner(
     lua: &Lua,
     request: &ParsedRequest,
-    route_handlers: &HashMap<String, RegistryKey>,
-) -> mlua::Result<HttpResponse> {
-    // Find handler — exact match, then wildcard, then ALL method
-    let route_key = format!("{}:{}", request.method, request.path);
-    let wildcard_key = format!("{}:*", request.method);
-    let all_key = format!("ALL:{}", request.path);
-    let all_wildcard = "ALL:*".to_string();
-
-    let mut handler_key = route_handlers.get(&route_key)
-        .or_else(|| route_handlers.get(&wildcard_key))
-        .or_else(|| route_handlers.get(&all_key))
-        .or_else(|| route_handlers.get(&all_wildcard));
-
-    // HEAD falls back to GET per HTTP spec (RFC 7231 §4.3.2)
-    if handler_key.is_none() && request.method == "HEAD" {
-        let get_key = format!("GET:{}", request.path);
-        let get_wildcard = "GET:*".to_string();
-        handler_key = route_handlers.get(&get_key)
-            .or_else(|| route_handlers.get(&get_wildcard));
-    }
-
-    let Some(reg_key) = handler_key else {
-        return Ok(HttpResponse {
-            status: 404,
-            content_type: "text/plain".into(),
-            body: b"Not Found".to_vec(),
-            headers: Vec::new(),
-        });
-    };
-
-    // Build the request context table (same API as before)
-    let ctx = lua.create_table()?;
-    ctx.set("method", request.method.as_str())?;
-    ctx.set("path", request.path.as_str())?;
-    ctx.set("body", request.body.as_str())?;
+    router: &Router<Rc<Route>>,
+    sockets: &WsSockets,
+    reply: &Reply,
+) -> mlua::Result<()> {
+    let host = request.headers.get("host").map(String::as_str);
+    if websocket_key(request).is_some() {
+        if let Match::Found { value, params } = router.find("WS", host, &request.path) {
+            return accept_websocket(lua, request, value, &params, sockets, reply);
+        }
+    }
 
-    // Headers table
+    let (route, params) = match router.find(&request.method, host, &request.path) {
+        Match::Found { value, params } => (value, params),
+        Match::MethodNotAllowed(methods) if methods == ["WS"] => {
+            let mut response = HttpResponse::plain(426, "Upgrade Required");
+            response.headers.push(("Upgrade".into(), "websocket".into()));
+            reply.send(response);
+            return Ok(());
+        }
+        Match::MethodNotAllowed(mut methods) => {
+            methods.retain(|m| m != "WS");
+            if methods.iter().any(|m| m == "GET") && !methods.iter().any(|m| m == "HEAD") {
+                methods.push("HEAD".into());
+            }
+            let mut response = if request.method == "OPTIONS" {
+                methods.push("OPTIONS".into());
+                HttpResponse::plain(204, "")
+            } else {
+                HttpResponse::plain(405, "Method Not Allowed")
+            };
+            response.headers.push(("Allow".into(), methods.join(", ")));
+            reply.send(response);
+            return Ok(());
+        }
+        Match::NotFound => {
+            reply.send(HttpResponse::plain(404, "Not Found"));
+            return Ok(());
+        }
+    };
+
+    let ctx = request_table(lua, request, &params)?;
+    add_response_methods(lua, &ctx, reply)?;
+
+    // Call the handler
+    let handler: Function = lua.registry_value(&route.handler)?;
+    let result = handler.call::<Value>(ctx.clone())?;
+
+    // A returned iterator function streams its results until it returns nil
+    if let Value::Function(next) = &result {
+        if ctx.get::<Option<mlua::String>>("_body")?.is_none_or(|body| body.as_bytes().is_empty())
+            && reply.start_stream(|| response_head(&ctx))?
+        {
+            reply.detach();
+            pull_chunk(lua, reply.clone(), lua.create_registry_value(next.clone())?);
+            return Ok(());
+        }
+    }
+
+    let mut response = response_head(&ctx)?;
+    response.body = match ctx.get::<mlua::String>("_body") {
+        Ok(s) => s.as_bytes().to_vec(),
+        Err(_) => match result {
+            Value::String(s) => s.as_bytes().to_vec(),
+            Value::Nil => Vec::new(),
+            _ => value_to_json(&result).unwrap_or_default().into_bytes(),
+        },
+    };
+    reply.send(response);
+    Ok(())
+}
+
+/// Wait until the connection task has room for another chunk of a returned
+/// iterator's output, then fill the room from the main thread. A slow client
+/// holds back the iterator instead of the server buffering its output, and
+/// other requests are served in between.
+fn pull_chunk(lua: &Lua, reply: Reply, next: RegistryKey) {
+    let Some(tx) = reply.sender() else {
+        let _ = lua.remove_registry_value(next);
+        return;
+    };
+    coppermoon_core::spawn(async move {
+        let permit = tx.reserve_owned().await;
+        event_loop::post_main_thread_task(Box::new(move |lua: &Lua| match permit {
+            Ok(permit) => fill_chunks(lua, reply, next, permit),
+            // The client is gone
+            Err(_) => {
+                let _ = lua.remove_registry_value(next);
+                reply.finish(None);
+            }
+        }));
+    });
+}
+
+/// Call the iterator while the stream has room, at most [`STREAM_BUFFER`]
+/// times per turn, then go back to waiting.
+fn fill_chunks(
+    lua: &Lua,
+    reply: Reply,
+    next: RegistryKey,
+    mut permit: tokio::sync::mpsc::OwnedPermit<Chunk>,
+) {
+    for _ in 0..STREAM_BUFFER {
+        let chunk = lua
+            .registry_value::<Function>(&next)
+            .and_then(|next| next.call::<Option<mlua::String>>(()));
+        match chunk {
+            Ok(Some(chunk)) => {
+                let tx = permit.send(Chunk::Data(chunk.as_bytes().to_vec()));
+                match tx.try_reserve_owned() {
+                    Ok(more) => permit = more,
+                    Err(_) => break,
+                }
+            }
+            Ok(None) => {
+                let _ = lua.remove_registry_value(next);
+                reply.finish(None);
+                return;
+            }
+            Err(e) => {
+                event_loop::report_error("Response iterator", &e);
+                let _ = lua.remove_registry_value(next);
+                drop(permit);
+                reply.abort();
+                return;
+            }
+        }
+    }
+    pull_chunk(lua, reply, next);
+}
+
+/// The request side of a handler's ctx: method, path, params, body, headers
+/// and query.
+fn request_table(lua: &Lua, request: &ParsedRequest, params: &Params) -> mlua::Result<Table> {
+    let ctx = lua.create_table()?;
+    ctx.set("method", request.method.as_str())?;
+    ctx.set("path", request.path.as_str())?;
+    ctx.set("body", lua.create_string(&request.body)?)?;
+    if let Some(parts) = &request.parts {
+        ctx.set("_multipart", multipart_table(lua, &parts.0)?)?;
+    }
+
+    // Route parameters (`:id`, `*path`, host labels)
+    let params_table = lua.create_table()?;
+    for (name, value) in params {
+        params_table.set(name.as_str(), value.as_str())?;
+    }
+    ctx.set("params", params_table)?;
+
+    // Headers table
     let headers_table = lua.create_table()?;
     for (k, v) in &request.headers {
         headers_table.set(k.as_str(), v.as_str())?;
@@ -432,15 +1423,161 @@ fn dispatch_to_lua_inner(
         for pair in qs.split('&') {
             if let Some((key, value)) = pair.split_once('=') {
                 query_table.set(
-                    urlencoding_decode(key),
-                    urlencoding_decode(value),
+                    http_body::form_decode(key),
+                    http_body::form_decode(value),
                 )?;
             }
         }
     }
     ctx.set("query", query_table)?;
 
-    // Response state
+    // ctx:form() -> { name = value }: urlencoded or multipart text fields
+    ctx.set("form", lua.create_function(form_fields)?)?;
+
+    // ctx:multipart(options?) -> { fields, files, parts }
+    ctx.set("multipart", lua.create_function(multipart_body)?)?;
+
+    // Where a reconnecting EventSource left off
+    if let Some(id) = request.headers.get("last-event-id") {
+        ctx.set("last_event_id", id.as_str())?;
+    }
+
+    // HTTPS only: { version, alpn, server_name, client_cert }
+    if let Some(tls) = &request.tls {
+        let tls_table = lua.create_table()?;
+        tls_table.set("version", tls.version.as_str())?;
+        tls_table.set("alpn", tls.alpn.as_deref())?;
+        tls_table.set("server_name", tls.server_name.as_deref())?;
+        tls_table.set("client_cert", tls.client_cert.as_deref())?;
+        ctx.set("tls", tls_table)?;
+    }
+    Ok(ctx)
+}
+
+/// The request's media type (lowercased) and its parameters.
+fn request_media_type(ctx: &Table) -> mlua::Result<(String, Vec<(String, String)>)> {
+    let headers: Table = ctx.get("headers")?;
+    let content_type: Option<String> = headers.get("content-type")?;
+    Ok(http_body::media_type(content_type.as_deref().unwrap_or("")))
+}
+
+fn form_fields(lua: &Lua, ctx: Table) -> mlua::Result<Table> {
+    if request_media_type(&ctx)?.0 == "multipart/form-data" {
+        return multipart_body(lua, (ctx, None))?.get("fields");
+    }
+    let body: mlua::String = ctx.get("body")?;
+    let fields = lua.create_table()?;
+    for (name, value) in http_body::parse_form(&body.as_bytes()) {
+        fields.set(name, value)?;
+    }
+    Ok(fields)
+}
+
+/// Options: `max_file_size`, `max_field_size` and `max_parts`. Parsed once
+/// per request; a body spooled with listen's `uploads` is parsed already.
+fn multipart_body(lua: &Lua, (ctx, options): (Table, Option<Table>)) -> mlua::Result<Table> {
+    if let Some(options) = &options {
+        if options.contains_key("spool")? {
+            return Err(mlua::Error::runtime(
+                "ctx:multipart: file parts are spooled with server:listen's 'uploads' option",
+            ));
+        }
+    }
+    if let Some(parsed) = ctx.get::<Option<Table>>("_multipart")? {
+        return Ok(parsed);
+    }
+    let (media, params) = request_media_type(&ctx)?;
+    let boundary = params
+        .into_iter()
+        .find(|(key, _)| key == "boundary" && media == "multipart/form-data")
+        .map(|(_, value)| value)
+        .ok_or_else(|| mlua::Error::runtime("ctx:multipart: not a multipart/form-data request"))?;
+
+    let mut limits = MultipartOptions::default();
+    if let Some(options) = &options {
+        multipart_limits(options, &mut limits)?;
+    }
+    let body: mlua::String = ctx.get("body")?;
+    let parts = http_body::parse_multipart(&body.as_bytes(), &boundary, &limits).map_err(mlua::Error::runtime)?;
+    let parsed = multipart_table(lua, &parts)?;
+    ctx.set("_multipart", parsed.clone())?;
+    Ok(parsed)
+}
+
+/// `max_file_size`, `max_field_size` and `max_parts` from `options`.
+fn multipart_limits(options: &Table, limits: &mut MultipartOptions) -> mlua::Result<()> {
+    if let Some(size) = options.get::<Option<usize>>("max_file_size")? {
+        limits.max_file_size = size;
+    }
+    if let Some(size) = options.get::<Option<usize>>("max_field_size")? {
+        limits.max_field_size = size;
+    }
+    if let Some(count) = options.get::<Option<usize>>("max_parts")? {
+        limits.max_parts = count;
+    }
+    Ok(())
+}
+
+/// Largest spooled request unless listen's `uploads.max_size` says otherwise
+const MAX_UPLOAD_SIZE: usize = 1024 * 1024 * 1024; // 1 GB
+
+/// listen's `uploads = { dir?, max_size?, max_file_size?, max_field_size?,
+/// max_parts? }`: multipart requests skip `max_body_size` and have their
+/// file parts written to `dir` (the temp directory by default) while they
+/// are read.
+fn upload_options(lua: &Lua, options: &Table) -> mlua::Result<Option<MultipartOptions>> {
+    let Some(uploads) = options.get::<Option<Table>>("uploads")? else {
+        return Ok(None);
+    };
+    permissions::check_fs_write(lua, "listen with 'uploads'")?;
+    let dir = match uploads.get::<Option<String>>("dir")? {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir(),
+    };
+    if !dir.is_dir() {
+        return Err(mlua::Error::runtime(format!("listen: uploads dir '{}' is not a directory", dir.display())));
+    }
+    let mut limits = MultipartOptions { max_size: MAX_UPLOAD_SIZE, spool: Some(dir), ..Default::default() };
+    multipart_limits(&uploads, &mut limits)?;
+    if let Some(size) = uploads.get::<Option<usize>>("max_size")? {
+        limits.max_size = size;
+    }
+    Ok(Some(limits))
+}
+
+/// `{ fields, files, parts }` for ctx:multipart.
+fn multipart_table(lua: &Lua, parts: &[Part]) -> mlua::Result<Table> {
+    let (fields, files, list) = (lua.create_table()?, lua.create_table()?, lua.create_table()?);
+    for part in parts {
+        let entry = lua.create_table()?;
+        entry.set("name", part.name.as_str())?;
+        entry.set("filename", part.filename.as_deref())?;
+        entry.set("content_type", part.content_type.as_deref())?;
+        entry.set("size", part.size)?;
+        match &part.content {
+            PartContent::Data(data) => {
+                entry.set("data", lua.create_string(data)?)?;
+                if part.filename.is_none() {
+                    fields.set(part.name.as_str(), lua.create_string(data)?)?;
+                }
+            }
+            PartContent::File(path) => entry.set("path", path.to_string_lossy().as_ref())?,
+        }
+        if part.filename.is_some() {
+            files.set(part.name.as_str(), entry.clone())?;
+        }
+        list.push(entry)?;
+    }
+
+    let parsed = lua.create_table()?;
+    parsed.set("fields", fields)?;
+    parsed.set("files", files)?;
+    parsed.set("parts", list)?;
+    Ok(parsed)
+}
+
+/// Response state and the methods that set or stream it.
+fn add_response_methods(lua: &Lua, ctx: &Table, reply: &Reply) -> mlua::Result<()> {
     ctx.set("_status", 200u16)?;
     ctx.set("_content_type", "text/plain")?;
     ctx.set("_body", "")?;
@@ -452,11 +1589,21 @@ fn dispatch_to_lua_inner(
     })?)?;
 
     // ctx:json(data)
-    ctx.set("json", lua.create_function(|_lua, (ctx, data): (Table, Value)| {
-        let json_str = value_to_json(&data)?;
+    // ctx:json() -> value: decode the request body (nil if empty)
+    ctx.set("json", lua.create_function(|lua, (ctx, args): (Table, mlua::Variadic<Value>)| {
+        let Some(data) = args.first() else {
+            let body: mlua::String = ctx.get("body")?;
+            if body.as_bytes().iter().all(u8::is_ascii_whitespace) {
+                return Ok(Value::Nil);
+            }
+            let json: serde_json::Value = serde_json::from_slice(&body.as_bytes())
+                .map_err(|e| mlua::Error::runtime(format!("Invalid JSON body: {}", e)))?;
+            return crate::json::json_to_lua(lua, &json);
+        };
+        let json_str = value_to_json(data)?;
         ctx.set("_content_type", "application/json")?;
         ctx.set("_body", json_str)?;
-        Ok(ctx)
+        Ok(Value::Table(ctx))
     })?)?;
 
     // ctx:text(str)
@@ -473,20 +1620,687 @@ fn dispatch_to_lua_inner(
         Ok(ctx)
     })?)?;
 
-    // Call the handler
-    let handler: Function = lua.registry_value(reg_key)?;
-    let result = handler.call::<Value>(ctx.clone())?;
+    // ctx:write(chunk) -> true | false, "closed" | "full": stream the body,
+    // sending status and headers first
+    let stream_reply = reply.clone();
+    ctx.set("write", lua.create_function(move |_, (ctx, chunk): (Table, mlua::String)| {
+        if !stream_reply.start_stream(|| response_head(&ctx))? {
+            return Ok((false, Some("closed")));
+        }
+        match stream_reply.write(Chunk::Data(chunk.as_bytes().to_vec())) {
+            Ok(()) => Ok((true, None)),
+            Err(reason) => Ok((false, Some(reason))),
+        }
+    })?)?;
+
+    // ctx:flush() -> boolean: send buffered chunks to the client now
+    let flush_reply = reply.clone();
+    ctx.set("flush", lua.create_function(move |_, ctx: Table| {
+        if !flush_reply.start_stream(|| response_head(&ctx))? {
+            return Ok(false);
+        }
+        flush_reply.push(Chunk::Flush);
+        Ok(flush_reply.is_open())
+    })?)?;
+
+    // ctx:finish(chunk?): end a streamed response
+    let finish_reply = reply.clone();
+    ctx.set("finish", lua.create_function(move |_, (ctx, chunk): (Table, Option<mlua::String>)| {
+        let last = match chunk {
+            Some(chunk) if finish_reply.start_stream(|| response_head(&ctx))? => {
+                Some(Chunk::Data(chunk.as_bytes().to_vec()))
+            }
+            _ => None,
+        };
+        finish_reply.finish(last);
+        Ok(())
+    })?)?;
+
+    // ctx:sse(options?) -> event stream that outlives the handler
+    let sse_reply = reply.clone();
+    ctx.set("sse", lua.create_function(move |lua, (ctx, options): (Table, Option<Table>)| {
+        start_event_stream(lua, &ctx, options, &sse_reply)
+    })?)?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Server-Sent Events
+// ---------------------------------------------------------------------------
+
+/// Seconds between keep-alive comments on an idle event stream
+const SSE_KEEP_ALIVE_SECS: f64 = 15.0;
+
+/// `ctx:sse{ keepalive = secs, retry = ms }`: send the `text/event-stream`
+/// head and detach the response from the handler.
+fn start_event_stream(lua: &Lua, ctx: &Table, options: Option<Table>, reply: &Reply) -> mlua::Result<EventStream> {
+    let (keepalive, retry) = match &options {
+        Some(options) => (
+            options.get::<Option<f64>>("keepalive")?.unwrap_or(SSE_KEEP_ALIVE_SECS),
+            options.get::<Option<u64>>("retry")?,
+        ),
+        None => (SSE_KEEP_ALIVE_SECS, None),
+    };
+    if !keepalive.is_finite() || keepalive < 0.0 {
+        return Err(mlua::Error::runtime("sse keepalive must be a non-negative number of seconds"));
+    }
+
+    ctx.set("_status", 200u16)?;
+    ctx.set("_content_type", "text/event-stream")?;
+    let headers = match ctx.get::<Option<Table>>("_headers")? {
+        Some(headers) => headers,
+        None => {
+            let headers = lua.create_table()?;
+            ctx.set("_headers", headers.clone())?;
+            headers
+        }
+    };
+    headers.set("Cache-Control", "no-cache")?;
+    // Stop nginx from buffering the stream
+    headers.set("X-Accel-Buffering", "no")?;
+
+    if !reply.start_stream(|| response_head(ctx))? {
+        return Err(mlua::Error::runtime("ctx:sse() called after the response was sent"));
+    }
+    reply.detach();
+    reply.push(Chunk::KeepAlive(Duration::from_secs_f64(keepalive)));
+    if let Some(retry) = retry {
+        let _ = reply.write(Chunk::Data(format_event(None, None, None, Some(retry)).into_bytes()));
+    }
+    reply.push(Chunk::Flush);
+    Ok(EventStream { reply: reply.clone() })
+}
+
+/// The object returned by `ctx:sse()`. The stream ends on `close()`, when
+/// the client disconnects, or when the object is garbage collected.
+struct EventStream {
+    reply: Reply,
+}
+
+impl UserData for EventStream {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        // stream:send{ event=, data=, id=, retry= } or stream:send(data) -> boolean
+        methods.add_method("send", |_, this, event: Value| {
+            let text = match event {
+                Value::Table(event) => {
+                    let data = match event.get::<Value>("data")? {
+                        Value::Nil => None,
+                        Value::Table(t) => Some(value_to_json(&Value::Table(t))?),
+                        other => Some(field_string(other, "data")?),
+                    };
+                    let name = event.get::<Option<String>>("event")?;
+                    let id = match event.get::<Value>("id")? {
+                        Value::Nil => None,
+                        other => Some(field_string(other, "id")?),
+                    };
+                    let retry = event.get::<Option<u64>>("retry")?;
+                    format_event(name.as_deref(), data.as_deref(), id.as_deref(), retry)
+                }
+                other => format_event(None, Some(&field_string(other, "data")?), None, None),
+            };
+            Ok(this.reply.send_event(text))
+        });
+
+        // stream:comment(text?) -> boolean
+        methods.add_method("comment", |_, this, text: Option<String>| {
+            let comment = format_comment(text.as_deref().unwrap_or(""));
+            Ok(this.reply.send_event(comment))
+        });
+
+        // stream:is_open() -> boolean
+        methods.add_method("is_open", |_, this, ()| Ok(this.reply.is_open()));
+
+        // stream:on_close(fn): called on the main thread once the stream ends
+        methods.add_method("on_close", |lua, this, callback: Function| {
+            let key = lua.create_registry_value(callback)?;
+            this.reply.on_close(Box::new(move || {
+                event_loop::post_main_thread_task(Box::new(move |lua: &Lua| {
+                    if let Ok(callback) = lua.registry_value::<Function>(&key) {
+                        if let Err(e) = callback.call::<()>(()) {
+                            event_loop::report_error("SSE close callback", &e);
+                        }
+                    }
+                    let _ = lua.remove_registry_value(key);
+                }));
+            }));
+            Ok(())
+        });
+
+        // stream:close()
+        methods.add_method("close", |_, this, ()| {
+            this.reply.finish(None);
+            Ok(())
+        });
+    }
+}
+
+/// A string, number or boolean event field as text.
+fn field_string(value: Value, field: &str) -> mlua::Result<String> {
+    match value {
+        Value::String(s) => Ok(s.to_str()?.to_string()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        other => Err(mlua::Error::runtime(format!(
+            "sse {} must be a string, number or table, got {}",
+            field,
+            other.type_name()
+        ))),
+    }
+}
+
+/// One event in `text/event-stream` format. Multi-line data becomes several
+/// `data:` lines; line breaks in `event` and `id` would split the field, so
+/// they are dropped.
+fn format_event(event: Option<&str>, data: Option<&str>, id: Option<&str>, retry: Option<u64>) -> String {
+    let single_line = |s: &str| s.chars().filter(|c| *c != '\r' && *c != '\n').collect::<String>();
+    let mut out = String::new();
+    if let Some(id) = id {
+        out.push_str(&format!("id: {}\n", single_line(id)));
+    }
+    if let Some(event) = event {
+        out.push_str(&format!("event: {}\n", single_line(event)));
+    }
+    if let Some(retry) = retry {
+        out.push_str(&format!("retry: {}\n", retry));
+    }
+    if let Some(data) = data {
+        for line in data.split('\n') {
+            out.push_str(&format!("data: {}\n", line.strip_suffix('\r').unwrap_or(line)));
+        }
+    }
+    out.push('\n');
+    out
+}
+
+/// A comment line per line of `text`; clients ignore them.
+fn format_comment(text: &str) -> String {
+    let mut out = String::new();
+    for line in text.split('\n') {
+        out.push_str(&format!(": {}\n", line.strip_suffix('\r').unwrap_or(line)));
+    }
+    out.push('\n');
+    out
+}
+
+// ---------------------------------------------------------------------------
+// WebSocket routes (RFC 6455)
+// ---------------------------------------------------------------------------
+
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// How long to wait for the client's Close frame after sending ours
+const WS_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+// Close codes
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_NO_STATUS: u16 = 1005;
+const CLOSE_ABNORMAL: u16 = 1006;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+static NEXT_SOCKET_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The `Sec-WebSocket-Key` of a valid upgrade request.
+fn websocket_key(request: &ParsedRequest) -> Option<&str> {
+    let header = |name: &str| request.headers.get(name).map(String::as_str).unwrap_or("");
+    let upgrade = request.method == "GET"
+        && has_token(header("upgrade"), "websocket")
+        && has_token(header("connection"), "upgrade")
+        && header("sec-websocket-version") == "13";
+    upgrade.then(|| header("sec-websocket-key")).filter(|key| !key.is_empty())
+}
+
+/// The `Sec-WebSocket-Accept` answer to a client key.
+fn websocket_accept(key: &str) -> String {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use sha1::{Digest, Sha1};
+    STANDARD.encode(Sha1::digest(format!("{}{}", key, WS_GUID).as_bytes()))
+}
+
+/// A frame queued for the client by the main thread.
+#[derive(Clone)]
+enum WsOutgoing {
+    Message { binary: bool, data: Vec<u8> },
+    Close(u16, String),
+}
+
+/// An open WebSocket, shared by its Lua objects, its route's socket list and
+/// the connection task.
+struct WsSocket {
+    id: u64,
+    /// Pattern of the `server:ws` route, e.g. `/rooms/:id`
+    route: String,
+    /// Path the client asked for, e.g. `/rooms/42`
+    path: String,
+    outgoing: tokio::sync::mpsc::UnboundedSender<WsOutgoing>,
+    /// `ws:on` handlers by event name
+    handlers: Mutex<HashMap<String, RegistryKey>>,
+}
+
+impl WsSocket {
+    fn handler(&self, lua: &Lua, event: &str) -> mlua::Result<Option<Function>> {
+        match self.handlers.lock().unwrap().get(event) {
+            Some(key) => lua.registry_value(key).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Open sockets by route pattern, shared by a server's broadcast helpers
+/// and its connections.
+#[derive(Clone, Default)]
+struct WsSockets(Arc<Mutex<HashMap<String, Vec<Arc<WsSocket>>>>>);
+
+impl UserData for WsSockets {}
+
+impl WsSockets {
+    /// The sockets of `route`, only those connected to `path` if given.
+    fn on_route(&self, route: &str, path: Option<&str>) -> Vec<Arc<WsSocket>> {
+        let routes = self.0.lock().unwrap();
+        let sockets = routes.get(route).map(Vec::as_slice).unwrap_or_default();
+        sockets.iter().filter(|s| path.is_none_or(|path| s.path == path)).cloned().collect()
+    }
 
+    fn add(&self, socket: &Arc<WsSocket>) {
+        self.0.lock().unwrap().entry(socket.route.clone()).or_default().push(Arc::clone(socket));
+    }
+
+    fn remove(&self, socket: &WsSocket) {
+        let mut routes = self.0.lock().unwrap();
+        if let Some(sockets) = routes.get_mut(&socket.route) {
+            sockets.retain(|s| s.id != socket.id);
+            if sockets.is_empty() {
+                routes.remove(&socket.route);
+            }
+        }
+    }
+}
+
+/// Handed to the connection task with the 101 response. Dropping it, once
+/// the connection ends or if it never starts, retires the socket and runs
+/// its close handler.
+struct WsSession {
+    socket: Arc<WsSocket>,
+    outgoing: tokio::sync::mpsc::UnboundedReceiver<WsOutgoing>,
+    sockets: WsSockets,
+    /// Close code and reason reported to Lua
+    close: (u16, String),
+}
+
+impl Drop for WsSession {
+    fn drop(&mut self) {
+        self.sockets.remove(&self.socket);
+        let socket = Arc::clone(&self.socket);
+        let (code, reason) = std::mem::take(&mut self.close);
+        event_loop::post_main_thread_task(Box::new(move |lua: &Lua| {
+            // Handlers often capture the socket; free them so both can be collected
+            let handlers: Vec<_> = socket.handlers.lock().unwrap().drain().collect();
+            for (event, key) in handlers {
+                if event == "close" {
+                    let result = lua
+                        .registry_value::<Function>(&key)
+                        .and_then(|handler| handler.call::<()>((code, reason.as_str())));
+                    if let Err(e) = result {
+                        event_loop::report_error("WebSocket close handler", &e);
+                    }
+                }
+                let _ = lua.remove_registry_value(key);
+            }
+        }));
+    }
+}
+
+/// Run the route's handler with a new socket and answer 101 if it succeeds.
+fn accept_websocket(
+    lua: &Lua,
+    request: &ParsedRequest,
+    route: &Route,
+    params: &Params,
+    sockets: &WsSockets,
+    reply: &Reply,
+) -> mlua::Result<()> {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let socket = Arc::new(WsSocket {
+        id: NEXT_SOCKET_ID.fetch_add(1, Ordering::Relaxed),
+        route: route.pattern.clone(),
+        path: request.path.clone(),
+        outgoing: tx,
+        handlers: Mutex::new(HashMap::new()),
+    });
+    sockets.add(&socket);
+    let session = WsSession {
+        socket: Arc::clone(&socket),
+        outgoing: rx,
+        sockets: sockets.clone(),
+        close: (CLOSE_ABNORMAL, String::new()),
+    };
+
+    let handler: Function = lua.registry_value(&route.handler)?;
+    handler.call::<()>((WebSocket(socket), request_table(lua, request, params)?))?;
+
+    let mut response = HttpResponse::plain(101, "");
+    response.upgrade = Some(session);
+    reply.send(response);
+    Ok(())
+}
+
+/// The socket object handed to `server:ws` handlers.
+struct WebSocket(Arc<WsSocket>);
+
+impl UserData for WebSocket {
+    fn add_fields<F: UserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("id", |_, this| Ok(this.0.id));
+        fields.add_field_method_get("route", |_, this| Ok(this.0.route.clone()));
+        fields.add_field_method_get("path", |_, this| Ok(this.0.path.clone()));
+    }
+
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        // ws:send(data, binary?) -> boolean
+        methods.add_method("send", |_, this, (data, binary): (Value, Option<bool>)| {
+            let message = ws_message(data, binary.unwrap_or(false))?;
+            Ok(this.0.outgoing.send(message).is_ok())
+        });
+
+        // ws:on("message" | "close", handler) -> ws
+        methods.add_function("on", |lua, (ud, event, handler): (AnyUserData, String, Function)| {
+            if event != "message" && event != "close" {
+                return Err(mlua::Error::runtime(format!(
+                    "unknown WebSocket event '{}' (expected 'message' or 'close')",
+                    event
+                )));
+            }
+            let key = lua.create_registry_value(handler)?;
+            let old = ud.borrow::<WebSocket>()?.0.handlers.lock().unwrap().insert(event, key);
+            if let Some(old) = old {
+                lua.remove_registry_value(old)?;
+            }
+            Ok(ud)
+        });
+
+        // ws:close(code?, reason?)
+        methods.add_method("close", |_, this, (code, reason): (Option<u16>, Option<String>)| {
+            let mut reason = reason.unwrap_or_default();
+            // Control frames carry at most 125 bytes, two of them the code
+            while reason.len() > 123 {
+                reason.pop();
+            }
+            let _ = this.0.outgoing.send(WsOutgoing::Close(code.unwrap_or(CLOSE_NORMAL), reason));
+            Ok(())
+        });
+
+        // ws:is_open() -> boolean
+        methods.add_method("is_open", |_, this, ()| Ok(!this.0.outgoing.is_closed()));
+    }
+}
+
+/// A Lua value as an outgoing message: strings as they are, tables as JSON.
+fn ws_message(data: Value, binary: bool) -> mlua::Result<WsOutgoing> {
+    let data = match data {
+        Value::String(s) => s.as_bytes().to_vec(),
+        Value::Table(_) => value_to_json(&data)?.into_bytes(),
+        other => {
+            return Err(mlua::Error::runtime(format!(
+                "WebSocket data must be a string or table, got {}",
+                other.type_name()
+            )))
+        }
+    };
+    if !binary && std::str::from_utf8(&data).is_err() {
+        return Err(mlua::Error::runtime("WebSocket text must be valid UTF-8; send binary data with send(data, true)"));
+    }
+    Ok(WsOutgoing::Message { binary, data })
+}
+
+/// One frame read from the client.
+#[derive(Debug, PartialEq)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Decode the client frame at the start of `buf`, with the number of bytes
+/// it used. `Ok(None)` means more bytes are needed; `Err` is the close code
+/// to fail the connection with.
+fn parse_frame(buf: &[u8], max_payload: usize) -> std::result::Result<Option<(Frame, usize)>, u16> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buf[0] & 0x80 != 0;
+    let opcode = buf[0] & 0x0F;
+    // No extensions are negotiated, so the reserved bits must be clear
+    if buf[0] & 0x70 != 0 || buf[1] & 0x80 == 0 {
+        return Err(CLOSE_PROTOCOL_ERROR);
+    }
+    let (len, mut pos) = match buf[1] & 0x7F {
+        126 if buf.len() >= 4 => (u64::from(u16::from_be_bytes([buf[2], buf[3]])), 4),
+        127 if buf.len() >= 10 => (u64::from_be_bytes(buf[2..10].try_into().unwrap()), 10),
+        126 | 127 => return Ok(None),
+        len => (u64::from(len), 2),
+    };
+    let is_control = opcode & 0x8 != 0;
+    if is_control && (!fin || len > 125) {
+        return Err(CLOSE_PROTOCOL_ERROR);
+    }
+    if len > max_payload as u64 {
+        return Err(CLOSE_TOO_BIG);
+    }
+    let len = len as usize;
+    if buf.len() < pos + 4 + len {
+        return Ok(None);
+    }
+    let mask = [buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]];
+    pos += 4;
+    let payload = buf[pos..pos + len].iter().enumerate().map(|(i, b)| b ^ mask[i % 4]).collect();
+    Ok(Some((Frame { fin, opcode, payload }, pos + len)))
+}
+
+/// Encode an unmasked server frame.
+fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    if code == CLOSE_NO_STATUS {
+        return Vec::new();
+    }
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    payload
+}
+
+/// What the connection does with a frame from the client.
+#[derive(Debug, PartialEq)]
+enum FrameAction {
+    None,
+    Message { binary: bool, data: Vec<u8> },
+    Pong(Vec<u8>),
+    Close(u16, String),
+    Fail(u16),
+}
+
+/// Apply one frame, reassembling fragmented messages in `partial`.
+fn handle_frame(frame: Frame, partial: &mut Option<(u8, Vec<u8>)>, max_message: usize) -> FrameAction {
+    let (opcode, data) = match frame.opcode {
+        OP_TEXT | OP_BINARY if partial.is_some() => return FrameAction::Fail(CLOSE_PROTOCOL_ERROR),
+        OP_TEXT | OP_BINARY if !frame.fin => {
+            *partial = Some((frame.opcode, frame.payload));
+            return FrameAction::None;
+        }
+        OP_TEXT | OP_BINARY => (frame.opcode, frame.payload),
+        OP_CONTINUATION => {
+            let Some((_, data)) = partial.as_mut() else {
+                return FrameAction::Fail(CLOSE_PROTOCOL_ERROR);
+            };
+            if data.len() + frame.payload.len() > max_message {
+                return FrameAction::Fail(CLOSE_TOO_BIG);
+            }
+            data.extend_from_slice(&frame.payload);
+            if !frame.fin {
+                return FrameAction::None;
+            }
+            partial.take().unwrap()
+        }
+        OP_PING => return FrameAction::Pong(frame.payload),
+        OP_PONG => return FrameAction::None,
+        OP_CLOSE => {
+            return match frame.payload.len() {
+                0 => FrameAction::Close(CLOSE_NO_STATUS, String::new()),
+                1 => FrameAction::Fail(CLOSE_PROTOCOL_ERROR),
+                _ => match String::from_utf8(frame.payload[2..].to_vec()) {
+                    Ok(reason) => FrameAction::Close(u16::from_be_bytes([frame.payload[0], frame.payload[1]]), reason),
+                    Err(_) => FrameAction::Fail(CLOSE_INVALID_DATA),
+                },
+            }
+        }
+        _ => return FrameAction::Fail(CLOSE_PROTOCOL_ERROR),
+    };
+    if opcode == OP_TEXT && std::str::from_utf8(&data).is_err() {
+        return FrameAction::Fail(CLOSE_INVALID_DATA);
+    }
+    FrameAction::Message { binary: opcode == OP_BINARY, data }
+}
+
+/// Switch the connection to WebSocket and exchange frames until it closes.
+async fn serve_websocket<R, W>(reader: &mut R, writer: &mut W, key: &str, mut session: WsSession, max_message: usize)
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let head = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        websocket_accept(key)
+    );
+    if writer.write_all(head.as_bytes()).await.is_err() || writer.flush().await.is_err() {
+        return;
+    }
+    session.close = exchange_frames(reader, writer, &mut session, max_message).await;
+}
+
+/// The frame loop; returns the close code and reason for Lua.
+async fn exchange_frames<R, W>(reader: &mut R, writer: &mut W, session: &mut WsSession, max_message: usize) -> (u16, String)
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    async fn send<W: AsyncWrite + Unpin>(writer: &mut W, opcode: u8, payload: &[u8]) -> bool {
+        writer.write_all(&encode_frame(opcode, payload)).await.is_ok() && writer.flush().await.is_ok()
+    }
+    let abnormal = || (CLOSE_ABNORMAL, String::new());
+
+    let mut buf = Vec::new();
+    let mut partial = None;
+    // Set once we sent a Close frame: its code, reason and answer deadline
+    let mut closing: Option<(u16, String, tokio::time::Instant)> = None;
+    loop {
+        let deadline = closing.as_ref().map_or_else(tokio::time::Instant::now, |c| c.2);
+        tokio::select! {
+            // fill_buf is cancel-safe, so a queued message never loses input
+            read = reader.fill_buf() => {
+                let n = match read {
+                    Ok(data) if !data.is_empty() => {
+                        buf.extend_from_slice(data);
+                        data.len()
+                    }
+                    _ => return closing.map_or_else(abnormal, |(code, reason, _)| (code, reason)),
+                };
+                reader.consume(n);
+                loop {
+                    let frame = match parse_frame(&buf, max_message) {
+                        Ok(Some((frame, used))) => {
+                            buf.drain(..used);
+                            frame
+                        }
+                        Ok(None) => break,
+                        Err(code) => {
+                            send(writer, OP_CLOSE, &close_payload(code, "")).await;
+                            return (code, String::new());
+                        }
+                    };
+                    match handle_frame(frame, &mut partial, max_message) {
+                        FrameAction::None => {}
+                        FrameAction::Message { binary, data } => emit_message(&session.socket, binary, data),
+                        FrameAction::Pong(payload) => {
+                            if !send(writer, OP_PONG, &payload).await {
+                                return abnormal();
+                            }
+                        }
+                        FrameAction::Close(code, reason) => {
+                            // Answer the client's Close unless it answered ours
+                            if closing.is_none() {
+                                send(writer, OP_CLOSE, &close_payload(code, "")).await;
+                            }
+                            return (code, reason);
+                        }
+                        FrameAction::Fail(code) => {
+                            send(writer, OP_CLOSE, &close_payload(code, "")).await;
+                            return (code, String::new());
+                        }
+                    }
+                }
+            }
+            out = session.outgoing.recv(), if closing.is_none() => match out {
+                Some(WsOutgoing::Message { binary, data }) => {
+                    if !send(writer, if binary { OP_BINARY } else { OP_TEXT }, &data).await {
+                        return abnormal();
+                    }
+                }
+                Some(WsOutgoing::Close(code, reason)) => {
+                    if !send(writer, OP_CLOSE, &close_payload(code, &reason)).await {
+                        return abnormal();
+                    }
+                    closing = Some((code, reason, tokio::time::Instant::now() + WS_CLOSE_TIMEOUT));
+                }
+                None => return abnormal(),
+            },
+            _ = tokio::time::sleep_until(deadline), if closing.is_some() => {
+                return closing.map_or_else(abnormal, |(code, reason, _)| (code, reason));
+            }
+        }
+    }
+}
+
+/// Run the socket's message handler on the main thread.
+fn emit_message(socket: &Arc<WsSocket>, binary: bool, data: Vec<u8>) {
+    let socket = Arc::clone(socket);
+    event_loop::post_main_thread_task(Box::new(move |lua: &Lua| {
+        let result = socket.handler(lua, "message").and_then(|handler| match handler {
+            Some(handler) => handler.call::<()>((lua.create_string(&data)?, binary)),
+            None => Ok(()),
+        });
+        if let Err(e) = result {
+            event_loop::report_error("WebSocket message handler", &e);
+        }
+    }));
+}
+
+/// Status, content type and custom headers (`ctx._headers`) set on a ctx,
+/// with an empty body.
+fn response_head(ctx: &Table) -> mlua::Result<HttpResponse> {
     let status: u16 = ctx.get("_status").unwrap_or(200);
     let content_type: String = ctx.get("_content_type").unwrap_or_else(|_| "text/plain".to_string());
-    let body: Vec<u8> = match ctx.get::<mlua::String>("_body") {
-        Ok(s) => s.as_bytes().to_vec(),
-        Err(_) => match result {
-            Value::String(s) => s.as_bytes().to_vec(),
-            Value::Nil => Vec::new(),
-            _ => value_to_json(&result).unwrap_or_default().into_bytes(),
-        },
-    };
 
     // Read custom headers from ctx._headers
     let mut extra_headers = Vec::new();
@@ -505,7 +2319,7 @@ fn dispatch_to_lua_inner(
         }
     }
 
-    Ok(HttpResponse { status, content_type, body, headers: extra_headers })
+    Ok(HttpResponse { status, content_type, body: Vec::new(), headers: extra_headers, stream: None, upgrade: None })
 }
 
 // ---------------------------------------------------------------------------
@@ -523,7 +2337,7 @@ fn drain_timers(lua: &Lua) {
                     let func: mlua::Result<Function> = lua.registry_value(&cb.registry_key);
                     if let Ok(func) = func {
                         if let Err(e) = func.call::<()>(()) {
-                            eprintln!("Timer callback error: {}", e);
+                            event_loop::report_error("Timer callback", &e);
                         }
                     }
                     match cb.timer_type {
@@ -544,26 +2358,6 @@ fn drain_timers(lua: &Lua) {
 // Utility functions (kept from original)
 // ---------------------------------------------------------------------------
 
-fn urlencoding_decode(s: &str) -> String {
-    let mut result = String::new();
-    let mut chars = s.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        if c == '%' {
-            let hex: String = chars.by_ref().take(2).collect();
-            if let Ok(byte) = u8::from_str_radix(&hex, 16) {
-                result.push(byte as char);
-            }
-        } else if c == '+' {
-            result.push(' ');
-        } else {
-            result.push(c);
-        }
-    }
-
-    result
-}
-
 /// Escape a string for safe JSON embedding (RFC 8259).
 fn escape_json_string(s: &str) -> String {
     let mut out = String::with_capacity(s.len() + 2);
@@ -605,8 +2399,8 @@ fn value_to_json(value: &Value) -> mlua::Result<String> {
 
             for pair in t.clone().pairs::<Value, Value>() {
                 if let Ok((key, _)) = pair {
-                    match key {
-                        Value::Integer(i) if i > 0 => {
+                    match vm::to_integer(&key) {
+                        Some(i) if i > 0 => {
                             if i > max_index {
                                 max_index = i;
                             }
@@ -651,22 +2445,51 @@ fn build_response_bytes(
     body: &str,
     extra_headers: &[(String, String)],
 ) -> Vec<u8> {
-    build_response_bytes_ex(status, content_type, body.as_bytes(), extra_headers, false)
+    build_response_bytes_ex(status, content_type, body.as_bytes(), extra_headers, false, None)
 }
 
 /// Build HTTP response bytes. When `head_only` is true, Content-Length reflects
 /// the body size but the body itself is omitted (HTTP HEAD semantics).
+/// `keep_alive` is the idle timeout to advertise if the connection stays
+/// open; `None` sends `Connection: close`.
 fn build_response_bytes_ex(
     status: u16,
     content_type: &str,
     body: &[u8],
     extra_headers: &[(String, String)],
     head_only: bool,
+    keep_alive: Option<u64>,
 ) -> Vec<u8> {
+    let head = build_head(status, content_type, BodyLength::Fixed(body.len()), extra_headers, keep_alive);
+    let mut bytes = head.into_bytes();
+    if !head_only {
+        bytes.extend_from_slice(body);
+    }
+    bytes
+}
+
+/// How the end of a response body is signalled.
+#[derive(Debug, Clone, Copy)]
+enum BodyLength {
+    Fixed(usize),
+    Chunked,
+    /// HTTP/1.0 streaming: the body ends when the connection closes
+    UntilClose,
+}
+
+/// Status line and headers, up to and including the blank line.
+fn build_head(
+    status: u16,
+    content_type: &str,
+    length: BodyLength,
+    extra_headers: &[(String, String)],
+    keep_alive: Option<u64>,
+) -> String {
     let status_text = match status {
         200 => "OK",
         201 => "Created",
         204 => "No Content",
+        206 => "Partial Content",
         301 => "Moved Permanently",
         302 => "Found",
         303 => "See Other",
@@ -682,7 +2505,9 @@ fn build_response_bytes_ex(
         409 => "Conflict",
         413 => "Payload Too Large",
         414 => "URI Too Long",
+        416 => "Range Not Satisfiable",
         422 => "Unprocessable Entity",
+        426 => "Upgrade Required",
         429 => "Too Many Requests",
         431 => "Request Header Fields Too Large",
         500 => "Internal Server Error",
@@ -691,23 +2516,283 @@ fn build_response_bytes_ex(
         _ => "Unknown",
     };
 
-    let mut header = format!(
-        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
-        status,
-        status_text,
-        content_type,
-        body.len(),
-    );
+    let mut header = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\n", status, status_text, content_type);
+    match length {
+        BodyLength::Fixed(len) => header.push_str(&format!("Content-Length: {}\r\n", len)),
+        BodyLength::Chunked => header.push_str("Transfer-Encoding: chunked\r\n"),
+        BodyLength::UntilClose => {}
+    }
+    match keep_alive {
+        Some(idle) => header.push_str(&format!("Connection: keep-alive\r\nKeep-Alive: timeout={}\r\n", idle)),
+        None => header.push_str("Connection: close\r\n"),
+    }
 
     for (key, value) in extra_headers {
+        // The connection task decides on keep-alive
+        if key.eq_ignore_ascii_case("connection") {
+            continue;
+        }
         header.push_str(&format!("{}: {}\r\n", key, value));
     }
 
     header.push_str("\r\n");
+    header
+}
 
-    let mut bytes = header.into_bytes();
-    if !head_only {
-        bytes.extend_from_slice(body);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> ParsedRequest {
+        let mut reader = tokio::io::BufReader::new(raw.as_bytes());
+        coppermoon_core::block_on(parse_request(&mut reader, &Limits::default(), None)).unwrap()
+    }
+
+    #[test]
+    fn test_ambiguous_framing_is_rejected() {
+        let parse_err = |raw: &str| {
+            let mut reader = tokio::io::BufReader::new(raw.as_bytes());
+            coppermoon_core::block_on(parse_request(&mut reader, &Limits::default(), None)).is_err()
+        };
+        assert!(parse_err("POST / HTTP/1.1\r\nContent-Length: abc\r\n\r\n"));
+        assert!(parse_err("POST / HTTP/1.1\r\nContent-Length: +3\r\n\r\nabc"));
+        assert!(parse_err("POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\nabcd"));
+        assert!(parse_err("POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"));
+        assert!(parse_err("POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n"));
+        assert_eq!(parse("POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 3\r\n\r\nabc").body, b"abc");
+    }
+
+    #[test]
+    fn test_keep_alive_negotiation() {
+        assert!(wants_keep_alive(&parse("GET / HTTP/1.1\r\nHost: x\r\n\r\n")));
+        assert!(!wants_keep_alive(&parse("GET / HTTP/1.1\r\nConnection: close\r\n\r\n")));
+        assert!(!wants_keep_alive(&parse("GET / HTTP/1.0\r\n\r\n")));
+        assert!(wants_keep_alive(&parse("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n")));
+    }
+
+    #[test]
+    fn test_chunked_request_body() {
+        let req = parse("POST /up HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nX-Trailer: 1\r\n\r\n");
+        assert_eq!(req.body, b"Wikipedia");
+        assert!(wants_keep_alive(&req));
+    }
+
+    #[test]
+    fn test_stream_is_chunk_encoded() {
+        let (tx, rx) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        tx.try_send(Chunk::Data(b"hello ".to_vec())).unwrap();
+        tx.try_send(Chunk::Data(Vec::new())).unwrap();
+        tx.try_send(Chunk::Flush).unwrap();
+        tx.try_send(Chunk::Data(b"world".to_vec())).unwrap();
+        drop(tx);
+        let mut out = Vec::new();
+        coppermoon_core::block_on(write_stream(&mut out, "HEAD\r\n\r\n".into(), rx, true, false)).unwrap();
+        assert_eq!(out, b"HEAD\r\n\r\n6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_aborted_stream_has_no_final_chunk() {
+        let (head_tx, mut head_rx) = tokio::sync::oneshot::channel();
+        let reply = Reply::new(head_tx);
+        assert!(reply.start_stream(|| Ok(HttpResponse::plain(200, ""))).unwrap());
+        let stream = head_rx.try_recv().unwrap().stream.unwrap();
+        assert_eq!(reply.write(Chunk::Data(b"partial".to_vec())), Ok(()));
+        // What dispatch_to_lua does when the handler fails mid-stream
+        assert!(!reply.send(HttpResponse::plain(500, "")));
+        reply.abort();
+        reply.end();
+        assert_eq!(reply.write(Chunk::Data(b"more".to_vec())), Err("closed"));
+
+        let mut out = Vec::new();
+        assert!(coppermoon_core::block_on(write_stream(&mut out, String::new(), stream, true, false)).is_err());
+        assert_eq!(out, b"7\r\npartial\r\n");
+    }
+
+    #[test]
+    fn test_full_stream_refuses_data_but_keeps_control_chunks() {
+        let (head_tx, mut head_rx) = tokio::sync::oneshot::channel();
+        let reply = Reply::new(head_tx);
+        assert!(reply.start_stream(|| Ok(HttpResponse::plain(200, ""))).unwrap());
+        let mut stream = head_rx.try_recv().unwrap().stream.unwrap();
+        for _ in 0..STREAM_BUFFER {
+            assert_eq!(reply.write(Chunk::Data(b"x".to_vec())), Ok(()));
+        }
+        assert_eq!(reply.write(Chunk::Data(b"y".to_vec())), Err("full"));
+        coppermoon_core::block_on(async {
+            reply.finish(Some(Chunk::Data(b"last".to_vec())));
+            let mut received = Vec::new();
+            while let Some(chunk) = stream.recv().await {
+                if let Chunk::Data(data) = chunk {
+                    received.extend(data);
+                }
+            }
+            assert_eq!(received, [b"x".repeat(STREAM_BUFFER), b"last".to_vec()].concat());
+        });
+    }
+
+    #[test]
+    fn test_uploads_need_fs_write() {
+        let lua = Lua::new();
+        let options: Table = lua.load("{ uploads = { max_size = 100 } }").eval().unwrap();
+        let uploads = upload_options(&lua, &options).unwrap().unwrap();
+        assert_eq!((uploads.max_size, uploads.spool), (100, Some(std::env::temp_dir())));
+
+        permissions::deny_fs_write(&lua, "disabled");
+        let err = upload_options(&lua, &options).unwrap_err().to_string();
+        assert!(err.contains("permission denied: listen with 'uploads' is disabled"), "{}", err);
+    }
+
+    #[test]
+    fn test_multipart_upload_is_spooled_while_read() {
+        let dir = std::env::temp_dir().join(format!("coppermoon-upload-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let uploads = MultipartOptions { spool: Some(dir.clone()), ..Default::default() };
+        // Larger than max_body_size, in chunks that split the delimiters
+        let limits = Limits { max_body_size: 16, ..Default::default() };
+        let file = "x".repeat(UPLOAD_PIECE + 10);
+        let body = format!(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--XyZ\r\n\
+             Content-Disposition: form-data; name=\"f\"; filename=\"f.txt\"\r\n\r\n{}\r\n--XyZ--\r\n",
+            file
+        );
+        let chunked: String = body
+            .as_bytes()
+            .chunks(7)
+            .map(|c| format!("{:x}\r\n{}\r\n", c.len(), String::from_utf8_lossy(c)))
+            .collect();
+        let raw = format!(
+            "POST /up HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XyZ\r\n\
+             Transfer-Encoding: chunked\r\n\r\n{}0\r\n\r\nGET /next HTTP/1.1\r\n\r\n",
+            chunked
+        );
+        let mut reader = tokio::io::BufReader::new(raw.as_bytes());
+        coppermoon_core::block_on(async {
+            let request = parse_request(&mut reader, &limits, Some(&uploads)).await.unwrap();
+            assert!(request.body.is_empty());
+            let parts = request.parts.as_ref().unwrap();
+            assert_eq!(parts.0[0].content, PartContent::Data(b"1".to_vec()));
+            let PartContent::File(path) = &parts.0[1].content else {
+                panic!("file part was not spooled");
+            };
+            assert_eq!(std::fs::read(path).unwrap(), file.as_bytes());
+            // The connection is left at the next request
+            assert_eq!(parse_request(&mut reader, &limits, Some(&uploads)).await.unwrap().path, "/next");
+            drop(request);
+            assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+            // Without `uploads` the same body is over the limit
+            let mut reader = tokio::io::BufReader::new(raw.as_bytes());
+            assert!(parse_request(&mut reader, &limits, None).await.is_err());
+        });
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sse_event_format() {
+        assert_eq!(
+            format_event(Some("tick"), Some("a\r\nb"), Some("7\n"), Some(3000)),
+            "id: 7\nevent: tick\nretry: 3000\ndata: a\ndata: b\n\n"
+        );
+        assert_eq!(format_event(None, Some(""), None, None), "data: \n\n");
+        assert_eq!(format_comment("hi\nthere"), ": hi\n: there\n\n");
+    }
+
+    #[test]
+    fn test_stream_close_callbacks_run_when_stream_ends() {
+        let (tx, rx) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        let closed = Arc::new(Mutex::new(false));
+        let flag = Arc::clone(&closed);
+        tx.try_send(Chunk::KeepAlive(Duration::from_secs(60))).unwrap();
+        tx.try_send(Chunk::OnClose(Box::new(move || *flag.lock().unwrap() = true))).unwrap();
+        tx.try_send(Chunk::Data(b"data: x\n\n".to_vec())).unwrap();
+        drop(tx);
+        let mut out = Vec::new();
+        coppermoon_core::block_on(write_stream(&mut out, String::new(), rx, false, false)).unwrap();
+        assert_eq!(out, b"data: x\n\n");
+        assert!(*closed.lock().unwrap());
+    }
+
+    #[test]
+    fn test_websocket_handshake() {
+        let req = parse("GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n");
+        assert_eq!(websocket_key(&req), Some("dGhlIHNhbXBsZSBub25jZQ=="));
+        assert_eq!(websocket_accept("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(websocket_key(&parse("GET /chat HTTP/1.1\r\nUpgrade: websocket\r\n\r\n")), None);
+    }
+
+    #[test]
+    fn test_param_route_sockets_are_found_by_pattern() {
+        let lua = Lua::new();
+        let server = server_new(&lua, ()).unwrap();
+        lua.globals().set("server", server.clone()).unwrap();
+        lua.load(r#"server:ws("/rooms/:id", function(ws) assert(ws.route == "/rooms/:id") end)"#).exec().unwrap();
+        let router = collect_route_handlers(&lua, &server.get("_routes").unwrap()).unwrap();
+        let sockets = server.get::<AnyUserData>("_ws").unwrap().borrow::<WsSockets>().unwrap().clone();
+
+        let mut sessions = Vec::new();
+        for path in ["/rooms/1", "/rooms/1", "/rooms/2"] {
+            let request = parse(&format!(
+                "GET {} HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+                path
+            ));
+            let (head_tx, mut head_rx) = tokio::sync::oneshot::channel();
+            dispatch_to_lua(&lua, &request, &router, &sockets, &Reply::new(head_tx));
+            sessions.push(head_rx.try_recv().unwrap().upgrade.unwrap());
+        }
+
+        let count = |code: &str| lua.load(code).eval::<usize>().unwrap();
+        assert_eq!(count(r#"return server:broadcast("/rooms/:id", "hi")"#), 3);
+        assert_eq!(count(r#"return server:broadcast("/rooms/:id", "hi", { path = "/rooms/1" })"#), 2);
+        assert_eq!(count(r#"return #server:clients("/rooms/:id", { path = "/rooms/2" })"#), 1);
+        assert_eq!(count(r#"return #server:clients("/rooms/1")"#), 0);
+
+        sessions.truncate(1);
+        assert_eq!(count(r#"return #server:clients("/rooms/:id")"#), 1);
+    }
+
+    #[test]
+    fn test_websocket_frames() {
+        // Masked "Hello" from RFC 6455 section 5.7
+        let hello = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let (frame, used) = parse_frame(&hello, 1024).unwrap().unwrap();
+        assert_eq!((frame.fin, frame.opcode, frame.payload.as_slice(), used), (true, OP_TEXT, &b"Hello"[..], 11));
+        assert_eq!(parse_frame(&hello[..6], 1024), Ok(None));
+        assert_eq!(parse_frame(&[0x81, 0x05, b'H'], 1024), Err(CLOSE_PROTOCOL_ERROR));
+        assert_eq!(parse_frame(&hello, 4), Err(CLOSE_TOO_BIG));
+        assert_eq!(encode_frame(OP_TEXT, b"Hello"), b"\x81\x05Hello");
+        assert_eq!(&encode_frame(OP_BINARY, &[0; 256])[..4], &[0x82, 126, 1, 0]);
+    }
+
+    #[test]
+    fn test_websocket_fragments_reassemble() {
+        let frame = |fin, opcode, payload: &[u8]| Frame { fin, opcode, payload: payload.to_vec() };
+        let mut partial = None;
+        assert_eq!(handle_frame(frame(false, OP_TEXT, b"Hel"), &mut partial, 16), FrameAction::None);
+        assert_eq!(handle_frame(frame(true, OP_PING, b"p"), &mut partial, 16), FrameAction::Pong(b"p".to_vec()));
+        assert_eq!(
+            handle_frame(frame(true, OP_CONTINUATION, b"lo"), &mut partial, 16),
+            FrameAction::Message { binary: false, data: b"Hello".to_vec() }
+        );
+        assert_eq!(handle_frame(frame(true, OP_CONTINUATION, b"x"), &mut partial, 16), FrameAction::Fail(CLOSE_PROTOCOL_ERROR));
+        assert_eq!(handle_frame(frame(true, OP_TEXT, &[0xff]), &mut partial, 16), FrameAction::Fail(CLOSE_INVALID_DATA));
+        assert_eq!(
+            handle_frame(frame(true, OP_CLOSE, b"\x03\xe8bye"), &mut partial, 16),
+            FrameAction::Close(1000, "bye".into())
+        );
+    }
+
+    #[test]
+    fn test_pipelined_requests_parse_in_order() {
+        let raw = "POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET /b HTTP/1.1\r\n\r\n";
+        let mut reader = tokio::io::BufReader::new(raw.as_bytes());
+        let limits = Limits::default();
+        let (first, second) = coppermoon_core::block_on(async {
+            let first = parse_request(&mut reader, &limits, None).await.unwrap();
+            let second = parse_request(&mut reader, &limits, None).await.unwrap();
+            (first, second)
+        });
+        assert_eq!((first.path.as_str(), first.body.as_slice()), ("/a", &b"abc"[..]));
+        assert_eq!(second.path, "/b");
     }
-    bytes
 }
@@ -0,0 +1,505 @@
+//! Static files for `http.server`
+//!
+//! `server:static(prefix, dir, options)` mounts are served by the connection
+//! tasks without a round trip to Lua. Requests for files that do not exist
+//! fall through to the Lua routes.
+
+use crate::http_router::percent_decode;
+use mlua::{Table, UserData, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One `server:static` mount.
+#[derive(Debug, Clone)]
+pub struct StaticMount {
+    /// URL prefix without a trailing slash (`""` for the root)
+    prefix: String,
+    /// Canonical directory the files are served from
+    root: PathBuf,
+    /// Files tried, in order, for a directory
+    index: Vec<String>,
+    /// `Cache-Control: max-age`, in seconds
+    max_age: u64,
+    /// Add `immutable` to `Cache-Control` (for fingerprinted assets)
+    immutable: bool,
+    /// Serve `file.br` / `file.gz` to clients that accept them
+    precompressed: bool,
+    /// Serve files and directories whose name starts with `.`
+    dotfiles: bool,
+}
+
+impl StaticMount {
+    /// Read `index` (string, list or `false`), `max_age`, `immutable`,
+    /// `precompressed` and `dotfiles` from the options table.
+    pub fn new(prefix: &str, dir: &str, options: Option<Table>) -> mlua::Result<Self> {
+        let root = std::fs::canonicalize(dir)
+            .map_err(|e| mlua::Error::runtime(format!("static: cannot open '{}': {}", dir, e)))?;
+        if !root.is_dir() {
+            return Err(mlua::Error::runtime(format!("static: '{}' is not a directory", dir)));
+        }
+        let mut mount = StaticMount {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            root,
+            index: vec!["index.html".to_string()],
+            max_age: 0,
+            immutable: false,
+            precompressed: true,
+            dotfiles: false,
+        };
+        if let Some(options) = options {
+            mount.index = match options.get::<Value>("index")? {
+                Value::Nil => mount.index,
+                Value::Boolean(false) => Vec::new(),
+                Value::String(s) => vec![s.to_string_lossy()],
+                Value::Table(list) => list.sequence_values::<String>().collect::<mlua::Result<_>>()?,
+                _ => return Err(mlua::Error::runtime("static: 'index' must be a string, a list or false")),
+            };
+            mount.max_age = options.get::<Option<u64>>("max_age")?.unwrap_or(0);
+            mount.immutable = options.get::<Option<bool>>("immutable")?.unwrap_or(false);
+            mount.precompressed = options.get::<Option<bool>>("precompressed")?.unwrap_or(true);
+            mount.dotfiles = options.get::<Option<bool>>("dotfiles")?.unwrap_or(false);
+        }
+        Ok(mount)
+    }
+
+    /// The part of `path` below the mount, if it is inside it.
+    fn strip<'p>(&self, path: &'p str) -> Option<&'p str> {
+        let rest = path.strip_prefix(self.prefix.as_str())?;
+        (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+    }
+}
+
+/// The mounts of one server, shared with its connection tasks.
+#[derive(Clone, Default)]
+pub struct StaticMounts(Arc<Mutex<Vec<StaticMount>>>);
+
+impl StaticMounts {
+    pub fn add(&self, mount: StaticMount) {
+        self.0.lock().unwrap().push(mount);
+    }
+
+    /// The mounts as they are now, longest prefix first.
+    pub fn snapshot(&self) -> Arc<[StaticMount]> {
+        let mut mounts = self.0.lock().unwrap().clone();
+        mounts.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+        mounts.into()
+    }
+}
+
+impl UserData for StaticMounts {}
+
+/// A response for a static file, or a redirect or error about one.
+pub struct StaticResponse {
+    pub status: u16,
+    pub content_type: String,
+    pub headers: Vec<(String, String)>,
+    /// `Content-Length`: of the full file for a 304 or a `HEAD` request
+    pub length: u64,
+    /// The file and the offset to send `length` bytes from
+    pub body: Option<(tokio::fs::File, u64)>,
+}
+
+impl StaticResponse {
+    fn empty(status: u16, headers: Vec<(String, String)>) -> Self {
+        StaticResponse { status, content_type: "text/plain".into(), headers, length: 0, body: None }
+    }
+}
+
+/// Answer a `GET` or `HEAD` request from the mounts. `None` lets the request
+/// go on to the Lua routes.
+pub async fn serve(
+    mounts: &[StaticMount],
+    method: &str,
+    path: &str,
+    query: Option<&str>,
+    headers: &HashMap<String, String>,
+) -> Option<StaticResponse> {
+    if method != "GET" && method != "HEAD" {
+        return None;
+    }
+    for mount in mounts {
+        let Some(rest) = mount.strip(path) else {
+            continue;
+        };
+        match serve_from(mount, path, rest, query, headers).await {
+            Lookup::Found(response) => return Some(response),
+            Lookup::Forbidden => return Some(StaticResponse::empty(403, Vec::new())),
+            Lookup::Missing => {}
+        }
+    }
+    None
+}
+
+enum Lookup {
+    Found(StaticResponse),
+    Missing,
+    /// Outside the directory, or otherwise not to be served
+    Forbidden,
+}
+
+async fn serve_from(
+    mount: &StaticMount,
+    path: &str,
+    rest: &str,
+    query: Option<&str>,
+    headers: &HashMap<String, String>,
+) -> Lookup {
+    let Some(relative) = safe_relative_path(rest, mount.dotfiles) else {
+        return Lookup::Forbidden;
+    };
+    let Ok(mut file_path) = tokio::fs::canonicalize(mount.root.join(&relative)).await else {
+        return Lookup::Missing;
+    };
+    // Symlinks may point anywhere
+    if !file_path.starts_with(&mount.root) {
+        return Lookup::Forbidden;
+    }
+
+    let Ok(mut metadata) = tokio::fs::metadata(&file_path).await else {
+        return Lookup::Missing;
+    };
+    if metadata.is_dir() {
+        // Relative links in an index page need the trailing slash
+        if !path.ends_with('/') {
+            let location = match query {
+                Some(query) => format!("{}/?{}", path, query),
+                None => format!("{}/", path),
+            };
+            return Lookup::Found(StaticResponse::empty(301, vec![("Location".into(), location)]));
+        }
+        let mut index = None;
+        for name in &mount.index {
+            let candidate = file_path.join(name);
+            if let Ok(m) = tokio::fs::metadata(&candidate).await {
+                if m.is_file() {
+                    index = Some((candidate, m));
+                    break;
+                }
+            }
+        }
+        let Some((candidate, m)) = index else {
+            return Lookup::Missing;
+        };
+        (file_path, metadata) = (candidate, m);
+    }
+    if !metadata.is_file() {
+        return Lookup::Missing;
+    }
+
+    let content_type = mime_type(&file_path);
+    let mut response_headers = Vec::new();
+
+    // Precompressed variant next to the file
+    let mut encoding = None;
+    if mount.precompressed {
+        let accept = headers.get("accept-encoding").map(String::as_str).unwrap_or("");
+        for (coding, extension) in [("br", "br"), ("gzip", "gz")] {
+            if !accepts_encoding(accept, coding) {
+                continue;
+            }
+            let mut variant = file_path.clone().into_os_string();
+            variant.push(format!(".{}", extension));
+            let variant = PathBuf::from(variant);
+            if let Ok(m) = tokio::fs::metadata(&variant).await {
+                if m.is_file() {
+                    (file_path, metadata, encoding) = (variant, m, Some((coding, extension)));
+                    break;
+                }
+            }
+        }
+        response_headers.push(("Vary".into(), "Accept-Encoding".into()));
+    }
+
+    let len = metadata.len();
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let etag = entity_tag(len, modified, encoding.map(|(_, extension)| extension));
+    let last_modified = http_date(modified);
+    let mut cache_control = format!("public, max-age={}", mount.max_age);
+    if mount.immutable {
+        cache_control.push_str(", immutable");
+    }
+    response_headers.push(("ETag".into(), etag.clone()));
+    response_headers.push(("Last-Modified".into(), last_modified.clone()));
+    response_headers.push(("Cache-Control".into(), cache_control));
+    response_headers.push(("Accept-Ranges".into(), "bytes".into()));
+    if let Some((coding, _)) = encoding {
+        response_headers.push(("Content-Encoding".into(), coding.into()));
+    }
+
+    if not_modified(headers, &etag, modified) {
+        return Lookup::Found(StaticResponse {
+            status: 304,
+            content_type,
+            headers: response_headers,
+            length: len,
+            body: None,
+        });
+    }
+
+    // A Range applies only while the representation is the one If-Range names
+    let range_header = headers.get("range").filter(|_| {
+        headers.get("if-range").is_none_or(|validator| *validator == etag || *validator == last_modified)
+    });
+    let (status, start, length) = match range_header.map(|r| parse_range(r, len)) {
+        Some(Some(Ok((start, end)))) => {
+            response_headers.push(("Content-Range".into(), format!("bytes {}-{}/{}", start, end, len)));
+            (206, start, end - start + 1)
+        }
+        Some(Some(Err(()))) => {
+            response_headers.push(("Content-Range".into(), format!("bytes */{}", len)));
+            return Lookup::Found(StaticResponse::empty(416, response_headers));
+        }
+        _ => (200, 0, len),
+    };
+
+    let Ok(file) = tokio::fs::File::open(&file_path).await else {
+        return Lookup::Missing;
+    };
+    Lookup::Found(StaticResponse {
+        status,
+        content_type,
+        headers: response_headers,
+        length,
+        body: Some((file, start)),
+    })
+}
+
+/// The request path below a mount as a relative file path, or `None` if a
+/// segment could escape the directory (or is a hidden file and those are
+/// not served).
+fn safe_relative_path(rest: &str, dotfiles: bool) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for segment in rest.split('/') {
+        let segment = percent_decode(segment);
+        match segment.as_str() {
+            "" | "." => continue,
+            ".." => return None,
+            _ => {}
+        }
+        if segment.contains(['/', '\\', '\0']) || (!dotfiles && segment.starts_with('.')) {
+            return None;
+        }
+        // e.g. `C:` on Windows
+        if Path::new(&segment).components().count() != 1 || Path::new(&segment).has_root() {
+            return None;
+        }
+        relative.push(segment);
+    }
+    Some(relative)
+}
+
+/// Whether an `Accept-Encoding` value allows `coding` (RFC 9110 §12.5.3).
+pub(crate) fn accepts_encoding(accept: &str, coding: &str) -> bool {
+    encoding_quality(accept, coding) > 0.0
+}
+
+/// The quality value `Accept-Encoding` gives `coding`, 0 if not acceptable.
+pub(crate) fn encoding_quality(accept: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+    for item in accept.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let q = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            return q;
+        }
+        if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+fn entity_tag(len: u64, modified: SystemTime, extension: Option<&str>) -> String {
+    let stamp = modified.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    match extension {
+        Some(extension) => format!("\"{:x}-{:x}-{}\"", len, stamp, extension),
+        None => format!("\"{:x}-{:x}\"", len, stamp),
+    }
+}
+
+/// IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time).format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// `If-None-Match`, or failing that `If-Modified-Since` (RFC 9110 §13.2.2).
+fn not_modified(headers: &HashMap<String, String>, etag: &str, modified: SystemTime) -> bool {
+    if let Some(tags) = headers.get("if-none-match") {
+        // Weak comparison
+        return tags
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+    let Some(since) = headers.get("if-modified-since") else {
+        return false;
+    };
+    let Ok(since) = chrono::DateTime::parse_from_rfc2822(since) else {
+        return false;
+    };
+    let modified = modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    modified <= since.timestamp()
+}
+
+/// A single `bytes=` range as inclusive offsets. `None` when the header
+/// should be ignored (other units, several ranges, malformed); `Err` when it
+/// cannot be satisfied.
+fn parse_range(header: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        // Suffix: the last N bytes
+        let n: u64 = end.parse().ok()?;
+        if n == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        return Some(Ok((len.saturating_sub(n), len - 1)));
+    }
+    let start: u64 = start.parse().ok()?;
+    let end = match end {
+        "" => u64::MAX,
+        end => end.parse().ok()?,
+    };
+    if end < start {
+        return None;
+    }
+    if start >= len {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end.min(len - 1))))
+}
+
+/// Content type by file extension.
+fn mime_type(path: &Path) -> String {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    let mime = match extension.as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        _ => "application/octet-stream",
+    };
+    if mime.starts_with("text/") || matches!(mime, "application/json" | "application/xml" | "image/svg+xml") {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_parsing() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(Ok((0, 999))));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Some(Ok((990, 999))));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=5-1", 1000), None);
+    }
+
+    #[test]
+    fn test_traversal_is_rejected() {
+        assert_eq!(safe_relative_path("/css/site.css", false), Some(PathBuf::from("css/site.css")));
+        assert_eq!(safe_relative_path("/a/./b//c", false), Some(PathBuf::from("a/b/c")));
+        assert_eq!(safe_relative_path("/../etc/passwd", false), None);
+        assert_eq!(safe_relative_path("/%2e%2e/etc/passwd", false), None);
+        assert_eq!(safe_relative_path("/a%2f..%2f..%2fsecret", false), None);
+        assert_eq!(safe_relative_path("/a%5c..%5csecret", false), None);
+        assert_eq!(safe_relative_path("/.env", false), None);
+        assert_eq!(safe_relative_path("/.well-known/x", true), Some(PathBuf::from(".well-known/x")));
+    }
+
+    #[test]
+    fn test_accept_encoding() {
+        assert!(accepts_encoding("gzip, deflate, br", "br"));
+        assert!(!accepts_encoding("gzip, br;q=0", "br"));
+        assert!(accepts_encoding("*;q=0.5", "gzip"));
+        assert!(!accepts_encoding("identity", "gzip"));
+        assert_eq!(mime_type(Path::new("a/app.JS")), "text/javascript; charset=utf-8");
+        assert_eq!(mime_type(Path::new("logo.png")), "image/png");
+    }
+
+    #[test]
+    fn test_serve_file() {
+        let dir = std::env::temp_dir().join(format!("coppermoon-static-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        std::fs::write(dir.join("docs/index.html"), "<h1>docs</h1>").unwrap();
+        std::fs::write(dir.join("app.js"), "console.log(1)").unwrap();
+        std::fs::write(dir.join("app.js.gz"), "gzipped").unwrap();
+        let mount = StaticMount::new("/assets/", dir.to_str().unwrap(), None).unwrap();
+        let mounts = [mount];
+
+        let get = |path: &'static str, headers: &[(&str, &str)]| {
+            let headers: HashMap<String, String> =
+                headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            let mounts = mounts.clone();
+            coppermoon_core::block_on(async move { serve(&mounts, "GET", path, None, &headers).await })
+        };
+        let header = |response: &StaticResponse, name: &str| {
+            response.headers.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone())
+        };
+
+        let full = get("/assets/app.js", &[]).unwrap();
+        assert_eq!((full.status, full.length), (200, 14));
+        assert_eq!(full.content_type, "text/javascript; charset=utf-8");
+
+        let etag = header(&full, "ETag").unwrap();
+        assert_eq!(get("/assets/app.js", &[("if-none-match", etag.as_str())]).unwrap().status, 304);
+
+        let partial = get("/assets/app.js", &[("range", "bytes=8-")]).unwrap();
+        assert_eq!((partial.status, partial.length), (206, 6));
+        assert_eq!(header(&partial, "Content-Range").unwrap(), "bytes 8-13/14");
+
+        let gzipped = get("/assets/app.js", &[("accept-encoding", "gzip")]).unwrap();
+        assert_eq!(header(&gzipped, "Content-Encoding").as_deref(), Some("gzip"));
+        assert_eq!(gzipped.length, 7);
+
+        assert_eq!(get("/assets/docs", &[]).unwrap().status, 301);
+        assert_eq!(get("/assets/docs/", &[]).unwrap().length, 13);
+        assert_eq!(get("/assets/%2e%2e/x", &[]).unwrap().status, 403);
+        assert!(get("/assets/missing.js", &[]).is_none());
+        assert!(get("/assetsfoo", &[]).is_none());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,267 @@
+//! TLS termination for `http.server`
+//!
+//! Certificates and keys are read from PEM files and can be replaced while
+//! the server runs: they are reloaded on SIGHUP (Unix) and whenever one of
+//! the files changes. A reload that fails keeps the previous certificate.
+
+use mlua::Table;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+/// How often the certificate files are checked for changes
+const RELOAD_POLL: Duration = Duration::from_secs(2);
+
+/// TLS settings from the `server:listen` options.
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// CA bundle that client certificates must chain to (mTLS)
+    pub client_ca: Option<PathBuf>,
+    /// Reject clients without a certificate; otherwise one is only verified
+    /// if sent
+    pub client_auth_required: bool,
+    /// ALPN protocols to offer, most preferred first
+    pub alpn: Vec<String>,
+}
+
+impl TlsOptions {
+    /// Read `cert`, `key`, `client_ca`, `client_auth` (`"required"` or
+    /// `"optional"`) and `alpn` from a listen options table. `None` without
+    /// `cert` and `key`.
+    pub fn from_table(options: &Table) -> mlua::Result<Option<Self>> {
+        let cert: Option<String> = options.get("cert")?;
+        let key: Option<String> = options.get("key")?;
+        let (cert, key) = match (cert, key) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) => return Ok(None),
+            _ => return Err(mlua::Error::runtime("listen: 'cert' and 'key' must be given together")),
+        };
+        let client_ca: Option<String> = options.get("client_ca")?;
+        let client_auth_required = match options.get::<Option<String>>("client_auth")?.as_deref() {
+            None | Some("required") => true,
+            Some("optional") => false,
+            Some(other) => {
+                return Err(mlua::Error::runtime(format!(
+                    "listen: unknown client_auth '{}' (expected 'required' or 'optional')",
+                    other
+                )))
+            }
+        };
+        let alpn = options
+            .get::<Option<Vec<String>>>("alpn")?
+            .unwrap_or_else(|| vec!["http/1.1".to_string()]);
+        // The server only speaks HTTP/1; advertising h2 would break clients
+        if let Some(other) = alpn.iter().find(|p| *p != "http/1.1" && *p != "http/1.0") {
+            return Err(mlua::Error::runtime(format!(
+                "listen: unsupported alpn protocol '{}' (expected 'http/1.1' or 'http/1.0')",
+                other
+            )));
+        }
+        Ok(Some(TlsOptions {
+            cert: cert.into(),
+            key: key.into(),
+            client_ca: client_ca.map(PathBuf::from),
+            client_auth_required,
+            alpn,
+        }))
+    }
+
+    /// Modification times of the files, to notice when they are replaced.
+    fn stamps(&self) -> Vec<Option<SystemTime>> {
+        [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+/// Build a server configuration from the PEM files named in `options`.
+pub fn server_config(options: &TlsOptions) -> Result<rustls::ServerConfig, String> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+
+    let builder = match &options.client_ca {
+        Some(ca) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in read_certs(ca)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("Invalid CA certificate in '{}': {}", ca.display(), e))?;
+            }
+            let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if options.client_auth_required {
+                verifier
+            } else {
+                verifier.allow_unauthenticated()
+            };
+            builder.with_client_cert_verifier(verifier.build().map_err(|e| e.to_string())?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder
+        .with_single_cert(read_certs(&options.cert)?, read_key(&options.key)?)
+        .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+    config.alpn_protocols = options.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+    Ok(config)
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let pem = std::fs::read(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid PEM in '{}': {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in '{}'", path.display()));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    let pem = std::fs::read(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    rustls_pemfile::private_key(&mut pem.as_slice())
+        .map_err(|e| format!("Invalid PEM in '{}': {}", path.display(), e))?
+        .ok_or_else(|| format!("No private key found in '{}'", path.display()))
+}
+
+/// Accepts TLS connections with the current configuration, which
+/// [`Acceptor::watch`] swaps when the certificate changes.
+#[derive(Clone)]
+pub struct Acceptor {
+    options: Arc<TlsOptions>,
+    config: Arc<RwLock<Arc<rustls::ServerConfig>>>,
+}
+
+impl Acceptor {
+    pub fn new(options: TlsOptions) -> Result<Self, String> {
+        let config = server_config(&options)?;
+        Ok(Acceptor { options: Arc::new(options), config: Arc::new(RwLock::new(Arc::new(config))) })
+    }
+
+    /// Perform the server side of the handshake on `stream`.
+    pub async fn accept<IO>(&self, stream: IO) -> std::io::Result<tokio_rustls::server::TlsStream<IO>>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let config = Arc::clone(&self.config.read().unwrap());
+        tokio_rustls::TlsAcceptor::from(config).accept(stream).await
+    }
+
+    /// Re-read the certificate files; connections already open keep theirs.
+    pub fn reload(&self) -> Result<(), String> {
+        let config = server_config(&self.options)?;
+        *self.config.write().unwrap() = Arc::new(config);
+        Ok(())
+    }
+
+    /// Reload on SIGHUP and when the files change, for as long as the
+    /// process runs. Installing the SIGHUP handler replaces its default
+    /// action of terminating the process.
+    pub fn watch(&self) {
+        let acceptor = self.clone();
+        coppermoon_core::spawn(async move {
+            #[cfg(unix)]
+            let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).ok();
+            let mut stamps = acceptor.options.stamps();
+            loop {
+                #[cfg(unix)]
+                let signalled = match &mut hangup {
+                    Some(hangup) => tokio::select! {
+                        _ = hangup.recv() => true,
+                        _ = tokio::time::sleep(RELOAD_POLL) => false,
+                    },
+                    None => {
+                        tokio::time::sleep(RELOAD_POLL).await;
+                        false
+                    }
+                };
+                #[cfg(not(unix))]
+                let signalled = {
+                    tokio::time::sleep(RELOAD_POLL).await;
+                    false
+                };
+
+                let current = acceptor.options.stamps();
+                if !signalled && current == stamps {
+                    continue;
+                }
+                stamps = current;
+                match acceptor.reload() {
+                    Ok(()) => println!("Reloaded TLS certificate {}", acceptor.options.cert.display()),
+                    Err(e) => eprintln!("TLS reload failed, keeping the current certificate: {}", e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testdata(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata").join(name)
+    }
+
+    fn options() -> TlsOptions {
+        TlsOptions {
+            cert: testdata("cert.pem"),
+            key: testdata("key.pem"),
+            client_ca: None,
+            client_auth_required: true,
+            alpn: vec!["http/1.1".into()],
+        }
+    }
+
+    #[test]
+    fn test_handshake_negotiates_alpn() {
+        let acceptor = Acceptor::new(options()).unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(read_certs(&testdata("cert.pem")).unwrap().remove(0)).unwrap();
+        let mut client = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client));
+
+        let (client_io, server_io) = tokio::io::duplex(16 * 1024);
+        let name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+        let (server, client) = coppermoon_core::block_on(async {
+            tokio::join!(acceptor.accept(server_io), connector.connect(name, client_io))
+        });
+        let (server, _client) = (server.unwrap(), client.unwrap());
+        assert_eq!(server.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+    }
+
+    #[test]
+    fn test_bad_files_are_reported() {
+        let missing = TlsOptions { key: testdata("missing.pem"), ..options() };
+        assert!(server_config(&missing).unwrap_err().contains("missing.pem"));
+        let swapped = TlsOptions { key: testdata("cert.pem"), ..options() };
+        assert!(server_config(&swapped).unwrap_err().contains("No private key"));
+    }
+
+    #[test]
+    fn test_only_http1_alpn_is_accepted() {
+        let lua = mlua::Lua::new();
+        let listen = |alpn: &str| -> mlua::Result<Option<TlsOptions>> {
+            let source = format!("return {{ cert = 'c', key = 'k', alpn = {} }}", alpn);
+            let table: mlua::Table = lua.load(source).eval()?;
+            TlsOptions::from_table(&table)
+        };
+        assert!(listen("{ 'http/1.1', 'http/1.0' }").is_ok());
+        let err = listen("{ 'h2', 'http/1.1' }").unwrap_err().to_string();
+        assert!(err.contains("'h2'"), "{}", err);
+    }
+}
@@ -2,7 +2,7 @@
 //!
 //! Provides JSON encoding and decoding.
 
-use coppermoon_core::Result;
+use coppermoon_core::{vm, Result};
 use mlua::{Lua, Table, Value};
 use serde_json::{self, Value as JsonValue};
 
@@ -46,11 +46,13 @@ fn lua_to_json(value: &Value) -> mlua::Result<JsonValue> {
         Value::Nil => Ok(JsonValue::Null),
         Value::Boolean(b) => Ok(JsonValue::Bool(*b)),
         Value::Integer(i) => Ok(JsonValue::Number((*i).into())),
-        Value::Number(n) => {
-            serde_json::Number::from_f64(*n)
+        Value::Number(n) => match vm::to_integer(value).filter(|_| !vm::HAS_INTEGERS) {
+            // Integral numbers are integers on VMs without an integer subtype
+            Some(i) => Ok(JsonValue::Number(i.into())),
+            None => serde_json::Number::from_f64(*n)
                 .map(JsonValue::Number)
-                .ok_or_else(|| mlua::Error::runtime("Invalid number for JSON (NaN or Infinity)"))
-        }
+                .ok_or_else(|| mlua::Error::runtime("Invalid number for JSON (NaN or Infinity)")),
+        },
         Value::String(s) => {
             let str = s.to_str()
                 .map_err(|e| mlua::Error::runtime(format!("Invalid UTF-8: {}", e)))?;
@@ -63,8 +65,8 @@ fn lua_to_json(value: &Value) -> mlua::Result<JsonValue> {
 
             for pair in t.clone().pairs::<Value, Value>() {
                 if let Ok((key, _)) = pair {
-                    match key {
-                        Value::Integer(i) if i > 0 => {
+                    match vm::to_integer(&key) {
+                        Some(i) if i > 0 => {
                             if i > max_index {
                                 max_index = i;
                             }
@@ -112,13 +114,13 @@ fn lua_to_json(value: &Value) -> mlua::Result<JsonValue> {
 }
 
 /// Convert a JSON value to a Lua value
-fn json_to_lua(lua: &Lua, value: &JsonValue) -> mlua::Result<Value> {
+pub(crate) fn json_to_lua(lua: &Lua, value: &JsonValue) -> mlua::Result<Value> {
     match value {
         JsonValue::Null => Ok(Value::Nil),
         JsonValue::Bool(b) => Ok(Value::Boolean(*b)),
         JsonValue::Number(n) => {
             if let Some(i) = n.as_i64() {
-                Ok(Value::Integer(i))
+                Ok(vm::integer(i))
             } else if let Some(f) = n.as_f64() {
                 Ok(Value::Number(f))
             } else {
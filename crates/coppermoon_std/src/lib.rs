@@ -2,32 +2,97 @@
 //!
 //! This crate provides the standard library modules for CopperMoon,
 //! including fs, path, os, process, json, crypto, time, http, net and more.
+//!
+//! Most modules sit behind a cargo feature of the same name (all enabled by
+//! default), so embedders can leave out the ones they do not need along
+//! with their dependencies. [`FEATURES`] lists the ones compiled in.
 
 pub mod prelude;
+#[cfg(feature = "fs")]
 pub mod fs;
 pub mod path;
+#[cfg(feature = "os")]
 pub mod os;
+#[cfg(feature = "process")]
 pub mod process;
 pub mod json;
+#[cfg(feature = "crypto")]
 pub mod crypto;
 pub mod time;
+#[cfg(feature = "http")]
 pub mod http;
+#[cfg(feature = "http-server")]
 pub mod http_server;
+#[cfg(feature = "http-server")]
+pub mod http_bind;
+#[cfg(feature = "http-server")]
+pub mod http_body;
+#[cfg(feature = "http-server")]
+pub mod http_router;
+#[cfg(feature = "http-server")]
+pub mod http_static;
+#[cfg(feature = "http-tls")]
+pub mod http_tls;
+#[cfg(feature = "http-compress")]
+pub mod http_compress;
+pub mod http_limits;
+#[cfg(feature = "net")]
 pub mod net;
+#[cfg(feature = "websocket")]
 pub mod websocket;
 pub mod buffer;
+#[cfg(feature = "term")]
 pub mod term;
+#[cfg(feature = "console")]
 pub mod console;
 pub mod string_ext;
 pub mod table_ext;
+#[cfg(feature = "archive")]
 pub mod archive;
 pub mod datetime;
+#[cfg(feature = "regex")]
 pub mod regex;
+pub mod docs;
+pub mod dotenv;
+pub mod heap;
+pub mod runtime;
 
 use coppermoon_core::Result;
-use mlua::{Lua, Table};
-
-/// Register all standard library modules in the Lua state
+use mlua::Lua;
+
+/// Optional modules compiled into this build, by cargo feature name
+pub const FEATURES: &[&str] = &[
+    #[cfg(feature = "fs")]
+    "fs",
+    #[cfg(feature = "os")]
+    "os",
+    #[cfg(feature = "process")]
+    "process",
+    #[cfg(feature = "crypto")]
+    "crypto",
+    #[cfg(feature = "http")]
+    "http",
+    #[cfg(feature = "http-server")]
+    "http-server",
+    #[cfg(feature = "http-tls")]
+    "http-tls",
+    #[cfg(feature = "http-compress")]
+    "http-compress",
+    #[cfg(feature = "net")]
+    "net",
+    #[cfg(feature = "websocket")]
+    "websocket",
+    #[cfg(feature = "term")]
+    "term",
+    #[cfg(feature = "console")]
+    "console",
+    #[cfg(feature = "archive")]
+    "archive",
+    #[cfg(feature = "regex")]
+    "regex",
+];
+
+/// Register all compiled-in standard library modules in the Lua state
 pub fn register_all(lua: &Lua) -> Result<()> {
     // Register prelude (global functions)
     prelude::register(lua)?;
@@ -39,49 +104,74 @@ pub fn register_all(lua: &Lua) -> Result<()> {
     let globals = lua.globals();
 
     // fs module
+    #[cfg(feature = "fs")]
     globals.set("fs", fs::register(lua)?)?;
 
     // path module
     globals.set("path", path::register(lua)?)?;
 
     // os_ext module (extends built-in os)
+    #[cfg(feature = "os")]
     globals.set("os_ext", os::register(lua)?)?;
 
     // process module
+    #[cfg(feature = "process")]
     globals.set("process", process::register(lua)?)?;
 
     // json module
     globals.set("json", json::register(lua)?)?;
 
     // crypto module
+    #[cfg(feature = "crypto")]
     globals.set("crypto", crypto::register(lua)?)?;
 
     // time module
     globals.set("time", time::register(lua)?)?;
 
-    // http module (with server sub-module)
-    let http_module: Table = http::register(lua)?;
-    http_module.set("server", http_server::register(lua)?)?;
-    globals.set("http", http_module)?;
+    // http module (client, with server sub-module); either half can be left out
+    #[cfg(any(feature = "http", feature = "http-server"))]
+    {
+        #[cfg(feature = "http")]
+        let http_module: mlua::Table = http::register(lua)?;
+        #[cfg(not(feature = "http"))]
+        let http_module: mlua::Table = lua.create_table()?;
+        #[cfg(feature = "http-server")]
+        http_module.set("server", http_server::register(lua)?)?;
+        globals.set("http", http_module)?;
+    }
 
     // net module (TCP/UDP/WebSocket)
-    let net_module: Table = net::register(lua)?;
-    net_module.set("ws", websocket::register(lua)?)?;
-    globals.set("net", net_module)?;
+    #[cfg(any(feature = "net", feature = "websocket"))]
+    {
+        #[cfg(feature = "net")]
+        let net_module: mlua::Table = net::register(lua)?;
+        #[cfg(not(feature = "net"))]
+        let net_module: mlua::Table = lua.create_table()?;
+        #[cfg(feature = "websocket")]
+        net_module.set("ws", websocket::register(lua)?)?;
+        globals.set("net", net_module)?;
+    }
+
+    // runtime module (memory, GC and heap snapshots)
+    globals.set("runtime", runtime::register(lua)?)?;
 
     // buffer module (binary data manipulation)
     globals.set("buffer", buffer::register(lua)?)?;
 
     // term module (terminal styling and control)
+    #[cfg(feature = "term")]
     globals.set("term", term::register(lua)?)?;
 
     // console module (interactive input)
+    #[cfg(feature = "console")]
     globals.set("console", console::register(lua)?)?;
 
     // archive module (zip, tar, gzip)
+    #[cfg(feature = "archive")]
     globals.set("archive", archive::register(lua)?)?;
 
     // regex module (regular expressions)
+    #[cfg(feature = "regex")]
     globals.set("re", regex::register(lua)?)?;
 
     // Extend built-in string table with utility functions
@@ -2,8 +2,10 @@
 //!
 //! Provides operating system utilities beyond the standard Lua os module.
 
+use crate::dotenv;
 use coppermoon_core::Result;
 use mlua::{Lua, Table};
+use std::path::Path;
 
 /// Register the os_ext module (extends built-in os)
 pub fn register(lua: &Lua) -> Result<Table> {
@@ -18,6 +20,12 @@ pub fn register(lua: &Lua) -> Result<Table> {
     // os.unsetenv(key)
     os_table.set("unsetenv", lua.create_function(os_unsetenv)?)?;
 
+    // os.env_table() -> table
+    os_table.set("env_table", lua.create_function(os_env_table)?)?;
+
+    // os.load_env(path?, { override = false }) -> table
+    os_table.set("load_env", lua.create_function(os_load_env)?)?;
+
     // os.cwd() -> string
     os_table.set("cwd", lua.create_function(os_cwd)?)?;
 
@@ -64,6 +72,31 @@ fn os_unsetenv(_: &Lua, key: String) -> mlua::Result<()> {
     Ok(())
 }
 
+fn os_env_table(lua: &Lua, _: ()) -> mlua::Result<Table> {
+    let table = lua.create_table()?;
+    for (key, value) in std::env::vars_os() {
+        table.set(key.to_string_lossy().to_string(), value.to_string_lossy().to_string())?;
+    }
+    Ok(table)
+}
+
+fn os_load_env(lua: &Lua, (path, options): (Option<String>, Option<Table>)) -> mlua::Result<Table> {
+    let path = path.unwrap_or_else(|| ".env".to_string());
+    let override_existing = match options {
+        Some(options) => options.get::<Option<bool>>("override")?.unwrap_or(false),
+        None => false,
+    };
+
+    let applied = dotenv::load(Path::new(&path), override_existing)
+        .map_err(mlua::Error::runtime)?;
+
+    let table = lua.create_table()?;
+    for (key, value) in applied {
+        table.set(key, value)?;
+    }
+    Ok(table)
+}
+
 fn os_cwd(_: &Lua, _: ()) -> mlua::Result<String> {
     std::env::current_dir()
         .map(|p| p.to_string_lossy().to_string())
@@ -18,8 +18,12 @@ pub fn register(lua: &Lua) -> Result<()> {
     })?;
     globals.set("print", print_fn)?;
 
-    // Version information
-    globals.set("_COPPERMOON_VERSION", env!("CARGO_PKG_VERSION"))?;
+    // Version information, e.g. "0.1.0 (LuaJIT)"
+    globals.set(
+        "_COPPERMOON_VERSION",
+        format!("{} ({})", env!("CARGO_PKG_VERSION"), coppermoon_core::vm::NAME),
+    )?;
+    globals.set("_COPPERMOON_VM", coppermoon_core::vm::NAME)?;
 
     Ok(())
 }
@@ -60,8 +64,8 @@ fn format_table(table: &mlua::Table) -> String {
     // First pass: check if it's an array
     for pair in table.clone().pairs::<mlua::Value, mlua::Value>() {
         if let Ok((key, _)) = pair {
-            match key {
-                mlua::Value::Integer(i) if i == index => {
+            match coppermoon_core::vm::to_integer(&key) {
+                Some(i) if i == index => {
                     index += 1;
                 }
                 _ => {
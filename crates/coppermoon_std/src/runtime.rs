@@ -0,0 +1,184 @@
+//! Runtime introspection module for CopperMoon
+//!
+//! Memory statistics, garbage collector control and heap snapshots for
+//! tracking down memory growth in long-running scripts.
+
+use crate::heap;
+use coppermoon_core::event_loop;
+use coppermoon_core::module::NativeLibStore;
+use coppermoon_core::permissions::check_fs_write;
+use coppermoon_core::Result;
+use mlua::{Lua, Table};
+
+/// Register the runtime module
+pub fn register(lua: &Lua) -> Result<Table> {
+    let runtime = lua.create_table()?;
+
+    // runtime.memory() -> { lua, registry, timers, pending_work, native_libs }
+    runtime.set("memory", lua.create_function(runtime_memory)?)?;
+
+    // runtime.heap_snapshot(path) -> summary
+    runtime.set("heap_snapshot", lua.create_function(runtime_heap_snapshot)?)?;
+
+    // runtime.heap_diff(before_path, after_path, limit?) -> { memory, types, added }
+    runtime.set("heap_diff", lua.create_function(runtime_heap_diff)?)?;
+
+    runtime.set("gc", register_gc(lua)?)?;
+
+    Ok(runtime)
+}
+
+fn register_gc(lua: &Lua) -> mlua::Result<Table> {
+    let gc = lua.create_table()?;
+
+    // runtime.gc.collect() -> bytes freed
+    gc.set("collect", lua.create_function(|lua, ()| {
+        let before = lua.used_memory();
+        lua.gc_collect()?;
+        Ok(before.saturating_sub(lua.used_memory()))
+    })?)?;
+
+    // runtime.gc.step(kbytes?) -> true if a cycle finished
+    gc.set("step", lua.create_function(|lua, kbytes: Option<i32>| {
+        match kbytes {
+            Some(kbytes) => lua.gc_step_kbytes(kbytes),
+            None => lua.gc_step(),
+        }
+    })?)?;
+
+    // runtime.gc.stop() / runtime.gc.restart()
+    gc.set("stop", lua.create_function(|lua, ()| {
+        lua.gc_stop();
+        Ok(())
+    })?)?;
+    gc.set("restart", lua.create_function(|lua, ()| {
+        lua.gc_restart();
+        Ok(())
+    })?)?;
+
+    // runtime.gc.count() -> bytes in use
+    gc.set("count", lua.create_function(|lua, ()| Ok(lua.used_memory()))?)?;
+
+    // runtime.gc.pause(percent): wait for the heap to grow by percent before a new cycle
+    gc.set("pause", lua.create_function(|lua, percent: i32| {
+        if percent <= 0 {
+            return Err(mlua::Error::runtime("gc.pause expects a positive percentage"));
+        }
+        lua.gc_inc(percent, 0, 0);
+        Ok(())
+    })?)?;
+
+    // runtime.gc.mode(mode, options?) -> previous mode
+    gc.set("mode", lua.create_function(gc_mode)?)?;
+
+    Ok(gc)
+}
+
+/// `"incremental"` (options `pause`, `stepmul`, `stepsize`) or, on Lua 5.4,
+/// `"generational"` (options `minor`, `major`). Unset options keep their value.
+fn gc_mode(lua: &Lua, (mode, options): (String, Option<Table>)) -> mlua::Result<&'static str> {
+    let option = |name: &str| -> mlua::Result<i32> {
+        match &options {
+            Some(options) => Ok(options.get::<Option<i32>>(name)?.unwrap_or(0)),
+            None => Ok(0),
+        }
+    };
+    let previous = match mode.as_str() {
+        "incremental" => lua.gc_inc(option("pause")?, option("stepmul")?, option("stepsize")?),
+        #[cfg(feature = "lua54")]
+        "generational" => lua.gc_gen(option("minor")?, option("major")?),
+        #[cfg(not(feature = "lua54"))]
+        "generational" => {
+            return Err(mlua::Error::runtime(format!(
+                "generational GC is not available on {}",
+                coppermoon_core::vm::NAME
+            )))
+        }
+        other => {
+            return Err(mlua::Error::runtime(format!(
+                "unknown GC mode '{}' (expected 'incremental' or 'generational')",
+                other
+            )))
+        }
+    };
+    Ok(if matches!(previous, mlua::GCMode::Incremental) { "incremental" } else { "generational" })
+}
+
+fn runtime_memory(lua: &Lua, _: ()) -> mlua::Result<Table> {
+    let memory = lua.create_table()?;
+    memory.set("lua", lua.used_memory())?;
+    memory.set("registry", heap::registry_size(lua)?)?;
+    memory.set("timers", event_loop::pending_timer_count())?;
+    memory.set("pending_work", event_loop::pending_work_count())?;
+
+    let paths = lua
+        .app_data_ref::<NativeLibStore>()
+        .map(|store| store.paths())
+        .unwrap_or_default();
+    let native_libs = lua.create_sequence_from(paths.iter().map(|p| p.to_string_lossy().into_owned()))?;
+    memory.set("native_libs", native_libs)?;
+    Ok(memory)
+}
+
+fn runtime_heap_snapshot(lua: &Lua, path: String) -> mlua::Result<Table> {
+    check_fs_write(lua, "runtime.heap_snapshot")?;
+    // Only reachable objects belong in the snapshot
+    lua.gc_collect()?;
+    let snapshot = heap::snapshot(lua)?;
+
+    let file = std::fs::File::create(&path)
+        .map_err(|e| mlua::Error::runtime(format!("Failed to create '{}': {}", path, e)))?;
+    serde_json::to_writer(std::io::BufWriter::new(file), &snapshot)
+        .map_err(|e| mlua::Error::runtime(format!("Failed to write '{}': {}", path, e)))?;
+
+    let summary = lua.create_table()?;
+    summary.set("memory", snapshot.memory)?;
+    summary.set("nodes", snapshot.nodes.len())?;
+    summary.set("edges", snapshot.edges.len())?;
+    let types = lua.create_table()?;
+    for (kind, totals) in &snapshot.summary {
+        let entry = lua.create_table()?;
+        entry.set("count", totals.count)?;
+        entry.set("size", totals.size)?;
+        entry.set("retained", totals.retained)?;
+        types.set(kind.as_str(), entry)?;
+    }
+    summary.set("types", types)?;
+    Ok(summary)
+}
+
+fn read_snapshot(path: &str) -> mlua::Result<heap::Snapshot> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| mlua::Error::runtime(format!("Failed to open '{}': {}", path, e)))?;
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| mlua::Error::runtime(format!("'{}' is not a heap snapshot: {}", path, e)))
+}
+
+fn runtime_heap_diff(lua: &Lua, (before, after, limit): (String, String, Option<usize>)) -> mlua::Result<Table> {
+    let diff = heap::diff(&read_snapshot(&before)?, &read_snapshot(&after)?);
+
+    let result = lua.create_table()?;
+    result.set("memory", diff.memory)?;
+    let types = lua.create_table()?;
+    for (kind, delta) in &diff.types {
+        let entry = lua.create_table()?;
+        entry.set("count", delta.count)?;
+        entry.set("size", delta.size)?;
+        entry.set("retained", delta.retained)?;
+        types.set(kind.as_str(), entry)?;
+    }
+    result.set("types", types)?;
+
+    let added = lua.create_table()?;
+    for node in diff.added.iter().take(limit.unwrap_or(20)) {
+        let entry = lua.create_table()?;
+        entry.set("path", node.path.as_str())?;
+        entry.set("type", node.kind.as_str())?;
+        entry.set("size", node.size)?;
+        entry.set("retained", node.retained)?;
+        added.push(entry)?;
+    }
+    result.set("added", added)?;
+    result.set("added_count", diff.added.len())?;
+    Ok(result)
+}
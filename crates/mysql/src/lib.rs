@@ -3,6 +3,7 @@
 //! Provides MySQL and MariaDB database bindings for CopperMoon Lua runtime.
 //! This module provides a compatible interface with the SQLite module.
 
+use coppermoon_core::vm;
 use mlua::{FromLua, Lua, MultiValue, Result, Table, UserData, UserDataMethods, Value};
 use mysql::prelude::*;
 use mysql::{Conn, Opts, OptsBuilder, Pool, PooledConn, Row as MySqlRow};
@@ -109,7 +110,7 @@ impl UserData for Database {
                 Ok(_) => {
                     let affected = conn.affected_rows();
                     *this.affected_rows.borrow_mut() = affected;
-                    Ok(Value::Integer(affected as i64))
+                    Ok(vm::integer(affected as i64))
                 }
                 Err(e) => Err(mlua::Error::external(e)),
             }
@@ -141,7 +142,7 @@ impl UserData for Database {
                     let last_id = conn.last_insert_id();
                     *this.affected_rows.borrow_mut() = affected;
                     *this.last_insert_id.borrow_mut() = last_id;
-                    Ok(Value::Integer(affected as i64))
+                    Ok(vm::integer(affected as i64))
                 }
                 Err(e) => Err(mlua::Error::external(e)),
             }
@@ -429,8 +430,8 @@ impl UserData for Database {
 fn mysql_value_to_lua(value: &mysql::Value, lua: &Lua) -> Result<Value> {
     match value {
         mysql::Value::NULL => Ok(Value::Nil),
-        mysql::Value::Int(i) => Ok(Value::Integer(*i)),
-        mysql::Value::UInt(u) => Ok(Value::Integer(*u as i64)),
+        mysql::Value::Int(i) => Ok(vm::integer(*i)),
+        mysql::Value::UInt(u) => Ok(vm::integer(*u as i64)),
         mysql::Value::Float(f) => Ok(Value::Number(*f as f64)),
         mysql::Value::Double(d) => Ok(Value::Number(*d)),
         mysql::Value::Bytes(b) => {
@@ -480,10 +481,12 @@ impl MysqlValue {
 
 impl FromLua for MysqlValue {
     fn from_lua(value: Value, _lua: &Lua) -> Result<Self> {
+        if let Some(i) = vm::to_integer(&value) {
+            return Ok(MysqlValue::Integer(i));
+        }
         match value {
             Value::Nil => Ok(MysqlValue::Null),
             Value::Boolean(b) => Ok(MysqlValue::Integer(if b { 1 } else { 0 })),
-            Value::Integer(i) => Ok(MysqlValue::Integer(i)),
             Value::Number(n) => Ok(MysqlValue::Float(n)),
             Value::String(s) => Ok(MysqlValue::Text(s.to_str()?.to_string())),
             _ => Err(mlua::Error::external("Unsupported value type for MySQL")),
@@ -3,6 +3,7 @@
 //! Provides PostgreSQL database bindings for CopperMoon Lua runtime.
 //! This module provides a compatible interface with the MySQL and SQLite modules.
 
+use coppermoon_core::vm;
 use mlua::{FromLua, Lua, MultiValue, Result, Table, UserData, UserDataMethods, Value};
 use postgres::types::Type;
 use postgres::NoTls;
@@ -130,10 +131,12 @@ enum PgValue {
 
 impl FromLua for PgValue {
     fn from_lua(value: Value, _lua: &Lua) -> Result<Self> {
+        if let Some(i) = vm::to_integer(&value) {
+            return Ok(PgValue::Integer(i));
+        }
         match value {
             Value::Nil => Ok(PgValue::Null),
             Value::Boolean(b) => Ok(PgValue::Bool(b)),
-            Value::Integer(i) => Ok(PgValue::Integer(i)),
             Value::Number(n) => Ok(PgValue::Float(n)),
             Value::String(s) => Ok(PgValue::Text(s.to_str()?.to_string())),
             _ => Err(mlua::Error::external("Unsupported value type for PostgreSQL")),
@@ -184,15 +187,15 @@ fn pg_column_to_lua(row: &postgres::Row, idx: usize, pg_type: &Type, lua: &Lua)
             _ => Ok(Value::Nil),
         },
         Type::INT2 => match row.try_get::<_, Option<i16>>(idx) {
-            Ok(Some(v)) => Ok(Value::Integer(v as i64)),
+            Ok(Some(v)) => Ok(vm::integer(v as i64)),
             _ => Ok(Value::Nil),
         },
         Type::INT4 => match row.try_get::<_, Option<i32>>(idx) {
-            Ok(Some(v)) => Ok(Value::Integer(v as i64)),
+            Ok(Some(v)) => Ok(vm::integer(v as i64)),
             _ => Ok(Value::Nil),
         },
         Type::INT8 => match row.try_get::<_, Option<i64>>(idx) {
-            Ok(Some(v)) => Ok(Value::Integer(v)),
+            Ok(Some(v)) => Ok(vm::integer(v)),
             _ => Ok(Value::Nil),
         },
         Type::FLOAT4 => match row.try_get::<_, Option<f32>>(idx) {
@@ -233,7 +236,7 @@ impl UserData for Database {
             match client.execute(sql.as_str(), &[]) {
                 Ok(affected) => {
                     *this.affected_rows.borrow_mut() = affected;
-                    Ok(Value::Integer(affected as i64))
+                    Ok(vm::integer(affected as i64))
                 }
                 Err(e) => Err(mlua::Error::external(e)),
             }
@@ -274,7 +277,7 @@ impl UserData for Database {
                         }
                     }
 
-                    Ok(Value::Integer(affected as i64))
+                    Ok(vm::integer(affected as i64))
                 }
                 Err(e) => Err(mlua::Error::external(e)),
             }
@@ -3,6 +3,7 @@
 //! Provides SQLite database bindings for CopperMoon Lua runtime.
 //! This is an independent module, not part of the standard library.
 
+use coppermoon_core::vm;
 use mlua::{Lua, Result, Table, UserData, UserDataMethods, Value, MultiValue, FromLua};
 use rusqlite::{Connection, types::ValueRef};
 use std::cell::RefCell;
@@ -44,7 +45,7 @@ impl UserData for Database {
         methods.add_method("exec", |_lua, this, sql: String| {
             let conn = this.conn.borrow();
             match conn.execute(&sql, []) {
-                Ok(rows_affected) => Ok(Value::Integer(rows_affected as i64)),
+                Ok(rows_affected) => Ok(vm::integer(rows_affected as i64)),
                 Err(e) => Err(mlua::Error::external(e)),
             }
         });
@@ -71,7 +72,7 @@ impl UserData for Database {
                 .collect();
 
             match conn.execute(&sql, param_refs.as_slice()) {
-                Ok(rows_affected) => Ok(Value::Integer(rows_affected as i64)),
+                Ok(rows_affected) => Ok(vm::integer(rows_affected as i64)),
                 Err(e) => Err(mlua::Error::external(e)),
             }
         });
@@ -334,7 +335,7 @@ impl SqliteValue {
     fn to_lua(self, lua: &Lua) -> Result<Value> {
         match self {
             SqliteValue::Null => Ok(Value::Nil),
-            SqliteValue::Integer(i) => Ok(Value::Integer(i)),
+            SqliteValue::Integer(i) => Ok(vm::integer(i)),
             SqliteValue::Real(f) => Ok(Value::Number(f)),
             SqliteValue::Text(s) => Ok(Value::String(lua.create_string(&s)?)),
             SqliteValue::Blob(b) => Ok(Value::String(lua.create_string(&b)?)),
@@ -344,10 +345,12 @@ impl SqliteValue {
 
 impl FromLua for SqliteValue {
     fn from_lua(value: Value, _lua: &Lua) -> Result<Self> {
+        if let Some(i) = vm::to_integer(&value) {
+            return Ok(SqliteValue::Integer(i));
+        }
         match value {
             Value::Nil => Ok(SqliteValue::Null),
             Value::Boolean(b) => Ok(SqliteValue::Integer(if b { 1 } else { 0 })),
-            Value::Integer(i) => Ok(SqliteValue::Integer(i)),
             Value::Number(n) => Ok(SqliteValue::Real(n)),
             Value::String(s) => Ok(SqliteValue::Text(s.to_str()?.to_string())),
             _ => Err(mlua::Error::external("Unsupported value type for SQLite")),
@@ -372,7 +375,11 @@ pub fn register(lua: &Lua) -> Result<Table> {
     let module = lua.create_table()?;
 
     // sqlite.open(path) - Open a database
-    module.set("open", lua.create_function(|_, path: String| {
+    module.set("open", lua.create_function(|lua, path: String| {
+        // Opening a file creates it if missing, and the database is writable
+        if path != ":memory:" {
+            coppermoon_core::permissions::check_fs_write(lua, "sqlite.open on a file")?;
+        }
         match Database::open(&path) {
             Ok(db) => Ok(db),
             Err(e) => Err(mlua::Error::external(e)),
@@ -0,0 +1,24 @@
+-- Native module doing async I/O through coppermoon_sdk
+-- Build first: coppermoon native build examples/native-async
+-- then run: coppermoon examples/native-async/example.lua
+
+local async_native = require("async_native")
+
+local host = async_native.host()
+print("Loaded by CopperMoon " .. host.version .. " (" .. host.vm .. ")")
+
+async_native.readFile("examples/native-async/example.lua", function(err, data)
+    if err then
+        print("readFile failed: " .. err)
+        return
+    end
+    print("readFile: " .. #data:toString() .. " bytes")
+end)
+
+async_native.resolve("localhost", function(err, addresses)
+    print("resolve: " .. (err or table.concat(addresses, ", ")))
+end)
+
+async_native.after(100, function()
+    print("timer fired from native code")
+end)
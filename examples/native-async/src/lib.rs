@@ -0,0 +1,81 @@
+//! Example native module doing I/O off the main thread with coppermoon_sdk.
+//!
+//! ```lua
+//! local async_native = require("async_native")
+//! async_native.readFile("README.md", function(err, data)
+//!     print(err or ("read " .. #data:toString() .. " bytes"))
+//! end)
+//! ```
+
+use coppermoon_sdk::mlua::prelude::*;
+use std::net::ToSocketAddrs;
+
+#[mlua::lua_module]
+fn async_native(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    // Lua functions can't leave the main thread, so callbacks travel to
+    // `then` as registry keys
+
+    // async_native.readFile(path, callback(err, buffer))
+    module.set("readFile", lua.create_function(|lua, (path, callback): (String, LuaFunction)| {
+        let callback = lua.create_registry_value(callback)?;
+        coppermoon_sdk::spawn(
+            move || std::fs::read(&path),
+            move |lua, result| {
+                let callback: LuaFunction = lua.registry_value(&callback)?;
+                match result {
+                    Ok(bytes) => callback.call((LuaValue::Nil, coppermoon_sdk::create_buffer(lua, &bytes)?)),
+                    Err(e) => callback.call(e.to_string()),
+                }
+            },
+        )
+    })?)?;
+
+    // async_native.writeFile(path, string|buffer, callback(err))
+    module.set("writeFile", lua.create_function(|lua, (path, data, callback): (String, LuaValue, LuaFunction)| {
+        let bytes = coppermoon_sdk::buffer_bytes(lua, &data)?;
+        let callback = lua.create_registry_value(callback)?;
+        coppermoon_sdk::spawn(
+            move || std::fs::write(&path, bytes),
+            move |lua, result| {
+                let callback: LuaFunction = lua.registry_value(&callback)?;
+                callback.call(result.err().map(|e| e.to_string()))
+            },
+        )
+    })?)?;
+
+    // async_native.resolve(host, callback(err, addresses))
+    module.set("resolve", lua.create_function(|lua, (host, callback): (String, LuaFunction)| {
+        let callback = lua.create_registry_value(callback)?;
+        coppermoon_sdk::spawn(
+            move || {
+                (host.as_str(), 0)
+                    .to_socket_addrs()
+                    .map(|addrs| addrs.map(|a| a.ip().to_string()).collect::<Vec<_>>())
+            },
+            move |lua, result| {
+                let callback: LuaFunction = lua.registry_value(&callback)?;
+                match result {
+                    Ok(addrs) => callback.call((LuaValue::Nil, lua.create_sequence_from(addrs)?)),
+                    Err(e) => callback.call(e.to_string()),
+                }
+            },
+        )
+    })?)?;
+
+    // async_native.after(ms, callback): a timer driven from native code
+    module.set("after", lua.create_function(|lua, (ms, callback): (u64, LuaFunction)| {
+        coppermoon_sdk::set_timeout(lua, ms, callback)
+    })?)?;
+
+    // async_native.host() -> { version, vm }
+    module.set("host", lua.create_function(|lua, ()| {
+        let info = lua.create_table()?;
+        info.set("version", coppermoon_sdk::host_version())?;
+        info.set("vm", coppermoon_sdk::vm())?;
+        Ok(info)
+    })?)?;
+
+    Ok(module)
+}